@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use tar::Archive;
+use zip::ZipArchive;
+
+/// The archive formats sdists come in, told apart by their leading bytes
+/// rather than their file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    /// .tar.gz or .tgz
+    TarGz,
+    TarBz2,
+    TarXz,
+}
+
+impl ArchiveFormat {
+    /// Sniffs the format of the archive at `path`, None when it's none of these.
+    pub fn detect(path: &Path) -> io::Result<Option<ArchiveFormat>> {
+        let mut magic = [0u8; 6];
+        let mut read = 0;
+        let mut file = File::open(path)?;
+        while read < magic.len() {
+            match file.read(&mut magic[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        let magic = &magic[..read];
+
+        let format = if magic.starts_with(b"PK\x03\x04") {
+            ArchiveFormat::Zip
+        } else if magic.starts_with(&[0x1f, 0x8b]) {
+            ArchiveFormat::TarGz
+        } else if magic.starts_with(b"BZh") {
+            ArchiveFormat::TarBz2
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            ArchiveFormat::TarXz
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(format))
+    }
+}
+
+fn unsupported(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a zip, gzip, bzip2 or xz archive", path.display()),
+    )
+}
+
+/// Opens a tarball whatever it's compressed with.
+pub(crate) fn open_tar(path: &Path) -> io::Result<Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match ArchiveFormat::detect(path)? {
+        Some(ArchiveFormat::TarGz) => Box::new(GzDecoder::new(file)),
+        Some(ArchiveFormat::TarBz2) => Box::new(BzDecoder::new(file)),
+        Some(ArchiveFormat::TarXz) => {
+            // lzma-rs only decompresses into a writer, so the tar is held in memory
+            let mut tar = Vec::new();
+            lzma_rs::xz_decompress(&mut BufReader::new(file), &mut tar)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            Box::new(Cursor::new(tar))
+        }
+        Some(ArchiveFormat::Zip) | None => return Err(unsupported(path)),
+    };
+    Ok(Archive::new(reader))
+}
+
+/// Extracts the zip or tarball at `archive_path` into `output_dir` and
+/// returns the source directory it held: its single top-level directory,
+/// as sdists have, or `output_dir` itself when the entries sit side by side.
+pub fn extract_archive(archive_path: &Path, output_dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(output_dir)?;
+    let format = ArchiveFormat::detect(archive_path)?.ok_or_else(|| unsupported(archive_path))?;
+
+    // First path component of every entry; output_dir may hold other things already
+    let mut top_level = BTreeSet::new();
+    let mut record = |path: &Path| {
+        if let Some(Component::Normal(first)) = path.components().find(|c| *c != Component::CurDir)
+        {
+            top_level.insert(first.to_os_string());
+        }
+    };
+
+    if format == ArchiveFormat::Zip {
+        let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+        for i in 0..archive.len() {
+            let file = archive.by_index(i)?;
+            // enclosed_name refuses absolute paths and ones escaping output_dir
+            let path = file.enclosed_name().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsafe path {} in {}", file.name(), archive_path.display()),
+                )
+            })?;
+            record(&path);
+        }
+        archive.extract(output_dir)?;
+    } else {
+        let mut archive = open_tar(archive_path)?;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            // unpack_in skips entries that would land outside output_dir
+            if entry.unpack_in(output_dir)? {
+                record(&path);
+            }
+        }
+    }
+
+    match (top_level.len(), top_level.first()) {
+        (1, Some(name)) if output_dir.join(name).is_dir() => Ok(output_dir.join(name)),
+        _ => Ok(output_dir.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::io::Write;
+
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    fn tar_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            // Written by hand so that unsafe paths get through too
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn write_archive(dir: &Path, format: ArchiveFormat, files: &[(&str, &str)]) -> PathBuf {
+        let path = dir.join(format!("archive-{:?}", format));
+        let mut out = File::create(&path).unwrap();
+        match format {
+            ArchiveFormat::Zip => {
+                let mut zip = ZipWriter::new(out);
+                for (name, contents) in files {
+                    zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                    zip.write_all(contents.as_bytes()).unwrap();
+                }
+                zip.finish().unwrap();
+            }
+            ArchiveFormat::TarGz => {
+                let mut gz = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+                gz.write_all(&tar_bytes(files)).unwrap();
+                gz.finish().unwrap();
+            }
+            ArchiveFormat::TarBz2 => {
+                let mut bz = bzip2::write::BzEncoder::new(out, bzip2::Compression::default());
+                bz.write_all(&tar_bytes(files)).unwrap();
+                bz.finish().unwrap();
+            }
+            ArchiveFormat::TarXz => {
+                lzma_rs::xz_compress(&mut Cursor::new(tar_bytes(files)), &mut out).unwrap();
+            }
+        }
+        path
+    }
+
+    const FORMATS: [ArchiveFormat; 4] = [
+        ArchiveFormat::Zip,
+        ArchiveFormat::TarGz,
+        ArchiveFormat::TarBz2,
+        ArchiveFormat::TarXz,
+    ];
+
+    #[test]
+    fn detects_formats_by_content() {
+        let dir = TempDir::new("detect");
+        for format in FORMATS {
+            let path = write_archive(&dir.0, format, &[("pkg-1.0/setup.py", "")]);
+            assert_eq!(ArchiveFormat::detect(&path).unwrap(), Some(format));
+        }
+
+        let text = dir.0.join("pkg-1.0.tar.gz");
+        fs::write(&text, "not an archive").unwrap();
+        assert_eq!(ArchiveFormat::detect(&text).unwrap(), None);
+        let empty = dir.0.join("empty.zip");
+        fs::write(&empty, "").unwrap();
+        assert_eq!(ArchiveFormat::detect(&empty).unwrap(), None);
+        assert!(extract_archive(&text, &dir.0.join("out")).is_err());
+    }
+
+    #[test]
+    fn extracts_every_format_to_its_top_level_dir() {
+        let dir = TempDir::new("extract");
+        let files = [
+            ("pkg-1.0/pyproject.toml", "[project]\n"),
+            ("pkg-1.0/pkg/__init__.py", "VERSION = 1\n"),
+        ];
+        for format in FORMATS {
+            let archive = write_archive(&dir.0, format, &files);
+            let out = dir.0.join(format!("out-{:?}", format));
+            let source = extract_archive(&archive, &out).unwrap();
+            assert_eq!(source, out.join("pkg-1.0"), "{:?}", format);
+            assert_eq!(
+                fs::read_to_string(source.join("pkg/__init__.py")).unwrap(),
+                "VERSION = 1\n"
+            );
+        }
+    }
+
+    #[test]
+    fn extracts_side_by_side_entries_into_output_dir() {
+        let dir = TempDir::new("flat");
+        for format in FORMATS {
+            let archive =
+                write_archive(&dir.0, format, &[("setup.py", ""), ("pkg/__init__.py", "")]);
+            let out = dir.0.join(format!("out-{:?}", format));
+            assert_eq!(extract_archive(&archive, &out).unwrap(), out);
+            assert!(out.join("pkg/__init__.py").is_file());
+        }
+    }
+
+    #[test]
+    fn refuses_paths_outside_output_dir() {
+        let dir = TempDir::new("unsafe");
+        let files = [("pkg-1.0/setup.py", ""), ("../escaped.txt", "gotcha")];
+
+        let zip = write_archive(&dir.0, ArchiveFormat::Zip, &files);
+        let error = extract_archive(&zip, &dir.0.join("zip")).unwrap_err();
+        assert!(error.to_string().contains("Unsafe path ../escaped.txt"));
+
+        // Tarballs skip the entry and extract the rest
+        let tar = write_archive(&dir.0, ArchiveFormat::TarGz, &files);
+        let out = dir.0.join("tar");
+        assert_eq!(extract_archive(&tar, &out).unwrap(), out.join("pkg-1.0"));
+        assert!(!dir.0.join("escaped.txt").exists());
+    }
+}
@@ -6,9 +6,9 @@ pub struct BuildTuple {
     pub package: String,
     pub package_version: String,
     pub python_version: String,                // e.g. "3.11"
-    pub platform: String,                      // e.g. "windows-x86_64", "linux-aarch64"
+    pub platform: String,                      // e.g. "win_amd64", "manylinux_2_35_x86_64"
     pub abi: String,                           // e.g. "cp311", "abi3", "none"
-    pub compiler: Option<String>,              // e.g. "msvc", "gcc", "clang"
+    pub compiler: Option<String>,              // e.g. "msvc14", "gcc13_2_0", "clang17_0_6"
     pub build_flags: BTreeMap<String, String>, // e.g. "WITH_SSL" => "ON"
 }
 
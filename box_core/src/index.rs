@@ -0,0 +1,707 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use reqwest::Url;
+use reqwest::blocking::Client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::Deserialize;
+
+use crate::metadata::CoreMetadata;
+use crate::tags::{Tags, WheelFilename};
+use crate::version::{SpecifierSet, Version};
+
+pub const DEFAULT_INDEX_URL: &str = "https://pypi.org/simple/";
+
+// PEP 691 content negotiation, preferring JSON but accepting the PEP 503 HTML page
+const SIMPLE_ACCEPT: &str = "application/vnd.pypi.simple.v1+json, application/vnd.pypi.simple.v1+html;q=0.2, text/html;q=0.01";
+
+const SDIST_EXTENSIONS: [&str; 5] = [".tar.gz", ".tgz", ".tar.bz2", ".tar.xz", ".zip"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistributionKind {
+    Sdist,
+    Wheel,
+}
+
+#[derive(Debug, Clone)]
+pub struct DistributionFile {
+    pub filename: String,
+    pub url: String,
+    pub kind: DistributionKind,
+    pub version: Version,
+    pub hashes: BTreeMap<String, String>, // e.g. "sha256" => "ab12..."
+    pub requires_python: Option<String>,
+    pub yanked: bool,
+    pub core_metadata: bool, // PEP 658: METADATA is served separately at `{url}.metadata`
+}
+
+// A single file link as it appears on a project page, before filename parsing
+struct IndexLink {
+    filename: String,
+    url: Url,
+    hashes: BTreeMap<String, String>,
+    requires_python: Option<String>,
+    yanked: bool,
+    core_metadata: bool,
+}
+
+#[derive(Deserialize)]
+struct JsonProjectPage {
+    files: Vec<JsonFile>,
+}
+
+#[derive(Deserialize)]
+struct JsonFile {
+    filename: String,
+    url: String,
+    #[serde(default)]
+    hashes: BTreeMap<String, String>,
+    #[serde(rename = "requires-python", default)]
+    requires_python: Option<String>,
+    #[serde(default)]
+    yanked: serde_json::Value,
+    #[serde(rename = "core-metadata", default)]
+    core_metadata: serde_json::Value,
+    // Pre-PEP 714 name for `core-metadata`
+    #[serde(rename = "dist-info-metadata", default)]
+    dist_info_metadata: serde_json::Value,
+}
+
+pub struct IndexClient {
+    base: Url,
+    client: Client,
+}
+
+impl IndexClient {
+    /// `index_url` may be an http(s) URL, a file:// URL or a plain directory path.
+    pub fn new(index_url: &str) -> Result<IndexClient, Box<dyn std::error::Error>> {
+        Ok(IndexClient {
+            base: index_base_url(index_url)?,
+            client: Client::new(),
+        })
+    }
+
+    /// Where the index is, as a URL ending in a slash.
+    pub fn url(&self) -> &Url {
+        &self.base
+    }
+
+    pub fn project_url(&self, name: &str) -> Result<Url, Box<dyn std::error::Error>> {
+        Ok(self.base.join(&format!("{}/", normalize_name(name)))?)
+    }
+
+    /// Lists every sdist and wheel the index has for `name`, in index order.
+    pub fn list_files(
+        &self,
+        name: &str,
+    ) -> Result<Vec<DistributionFile>, Box<dyn std::error::Error>> {
+        let page_url = self.project_url(name)?;
+        println!("Fetching index page {}", page_url);
+
+        let (body, is_json) = self.fetch_page(&page_url)?;
+        let links = if is_json {
+            parse_json_page(&body, &page_url)?
+        } else {
+            parse_html_page(&body, &page_url)?
+        };
+
+        let project = normalize_name(name);
+        let files = links
+            .into_iter()
+            .filter_map(|link| {
+                let (kind, version) = parse_filename(&link.filename, &project)?;
+                // Legacy, non PEP 440 versions can't be compared, so they're skipped
+                let version = version.parse().ok()?;
+                Some(DistributionFile {
+                    filename: link.filename,
+                    url: link.url.to_string(),
+                    kind,
+                    version,
+                    hashes: link.hashes,
+                    requires_python: link.requires_python,
+                    yanked: link.yanked,
+                    core_metadata: link.core_metadata,
+                })
+            })
+            .collect();
+
+        Ok(files)
+    }
+
+    /// Fetches the PEP 658 metadata file served alongside `file`.
+    pub fn fetch_metadata(
+        &self,
+        file: &DistributionFile,
+    ) -> Result<CoreMetadata, Box<dyn std::error::Error>> {
+        let url = Url::parse(&file.metadata_url())?;
+
+        let text = if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| format!("Invalid file url: {}", url))?;
+            fs::read_to_string(path)?
+        } else {
+            self.client.get(url).send()?.error_for_status()?.text()?
+        };
+
+        Ok(CoreMetadata::parse(&text))
+    }
+
+    fn fetch_page(&self, url: &Url) -> Result<(String, bool), Box<dyn std::error::Error>> {
+        if url.scheme() == "file" {
+            let dir = url
+                .to_file_path()
+                .map_err(|_| format!("Invalid file url: {}", url))?;
+
+            let json_page = dir.join("index.json");
+            if json_page.exists() {
+                return Ok((fs::read_to_string(json_page)?, true));
+            }
+            return Ok((fs::read_to_string(dir.join("index.html"))?, false));
+        }
+
+        let response = self
+            .client
+            .get(url.clone())
+            .header(ACCEPT, SIMPLE_ACCEPT)
+            .send()?
+            .error_for_status()?;
+
+        let is_json = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.contains("json"))
+            .unwrap_or(false);
+
+        Ok((response.text()?, is_json))
+    }
+}
+
+impl DistributionFile {
+    pub fn metadata_url(&self) -> String {
+        format!("{}.metadata", self.url)
+    }
+}
+
+/// PEP 503 name normalization: lowercase with runs of `-`, `_` and `.` collapsed to `-`.
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut last_was_separator = false;
+
+    for c in name.trim().chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !last_was_separator {
+                normalized.push('-');
+            }
+            last_was_separator = true;
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+            last_was_separator = false;
+        }
+    }
+
+    normalized
+}
+
+/// pip's `--no-binary` / `--only-binary`: packages that must be built from
+/// source, and packages that may only be installed from wheels. ":all:"
+/// stands for every package; naming a package overrides ":all:" in the other list.
+#[derive(Debug, Clone, Default)]
+pub struct BinaryPolicy {
+    no_binary: Vec<String>,
+    only_binary: Vec<String>,
+}
+
+impl BinaryPolicy {
+    pub fn new(no_binary: &[String], only_binary: &[String]) -> BinaryPolicy {
+        let normalize = |names: &[String]| -> Vec<String> {
+            names
+                .iter()
+                .map(|name| match name.as_str() {
+                    ":all:" => name.clone(),
+                    _ => normalize_name(name),
+                })
+                .collect()
+        };
+        BinaryPolicy {
+            no_binary: normalize(no_binary),
+            only_binary: normalize(only_binary),
+        }
+    }
+
+    pub fn allows(&self, package: &str, kind: DistributionKind) -> bool {
+        let package = normalize_name(package);
+        let named = |list: &[String]| list.contains(&package);
+        let listed = |list: &[String]| named(list) || list.iter().any(|name| name == ":all:");
+
+        match kind {
+            DistributionKind::Wheel => !listed(&self.no_binary) || named(&self.only_binary),
+            DistributionKind::Sdist => !listed(&self.only_binary) || named(&self.no_binary),
+        }
+    }
+}
+
+/// The URL an index given as `index_url` is read from, ending in a slash:
+/// a plain directory path becomes a file:// URL.
+pub(crate) fn index_base_url(index_url: &str) -> Result<Url, Box<dyn std::error::Error>> {
+    let mut base = match Url::parse(index_url) {
+        Ok(url) => url,
+        Err(_) => Url::from_directory_path(fs::canonicalize(index_url)?)
+            .map_err(|_| format!("Invalid index url: {}", index_url))?,
+    };
+
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
+}
+
+/// Picks the artifact to download for `package`, from the newest version allowed
+/// by `specifiers` that has one: the compatible wheel whose tags rank best, or
+/// failing that an sdist, which has to be built first. With a `python` version,
+/// files whose Requires-Python excludes it are passed over.
+pub fn select_artifact<'a>(
+    files: &'a [DistributionFile],
+    specifiers: &SpecifierSet,
+    package: &str,
+    tags: &Tags,
+    python: Option<&Version>,
+    policy: &BinaryPolicy,
+) -> Option<&'a DistributionFile> {
+    let usable: Vec<(&DistributionFile, usize)> = files
+        .iter()
+        .filter(|f| is_usable(f, package, tags, python, policy))
+        .map(|f| (f, artifact_rank(f, tags)))
+        .collect();
+
+    let best = |allowed: &dyn Fn(&Version) -> bool| {
+        usable
+            .iter()
+            .filter(|(f, _)| allowed(&f.version))
+            .min_by(|(a, a_rank), (b, b_rank)| b.version.cmp(&a.version).then(a_rank.cmp(b_rank)))
+            .map(|(f, _)| *f)
+    };
+
+    // Pre-releases are only picked when nothing else satisfies the constraint
+    best(&|version| specifiers.matches(version))
+        .or_else(|| best(&|version| specifiers.contains(version)))
+}
+
+/// Whether `file` could be installed here, either directly or by building it.
+pub(crate) fn is_usable(
+    file: &DistributionFile,
+    package: &str,
+    tags: &Tags,
+    python: Option<&Version>,
+    policy: &BinaryPolicy,
+) -> bool {
+    if file.yanked || !policy.allows(package, file.kind) {
+        return false;
+    }
+    // An unparseable Requires-Python doesn't rule the file out
+    if let (Some(requires_python), Some(python)) = (&file.requires_python, python)
+        && requires_python
+            .parse::<SpecifierSet>()
+            .is_ok_and(|specifiers| !specifiers.contains(python))
+    {
+        return false;
+    }
+    match file.kind {
+        DistributionKind::Wheel => file
+            .filename
+            .parse::<WheelFilename>()
+            .is_ok_and(|wheel| tags.is_compatible(&wheel)),
+        // parse_filename only takes sdists with an extension extract_archive handles
+        DistributionKind::Sdist => true,
+    }
+}
+
+// Wheels by tag priority, then sdists, which have to be built first
+fn artifact_rank(file: &DistributionFile, tags: &Tags) -> usize {
+    file.filename
+        .parse::<WheelFilename>()
+        .ok()
+        .and_then(|wheel| tags.priority(&wheel))
+        .unwrap_or(usize::MAX)
+}
+
+fn parse_filename(filename: &str, project: &str) -> Option<(DistributionKind, String)> {
+    if filename.ends_with(".whl") {
+        let wheel: WheelFilename = filename.parse().ok()?;
+        if normalize_name(&wheel.name) != project {
+            return None;
+        }
+        return Some((DistributionKind::Wheel, wheel.version));
+    }
+
+    let stem = SDIST_EXTENSIONS
+        .iter()
+        .find_map(|ext| filename.strip_suffix(ext))?;
+
+    // sdist names may contain dashes themselves, so find the prefix that matches the project
+    stem.match_indices('-')
+        .find(|(i, _)| normalize_name(&stem[..*i]) == project)
+        .map(|(i, _)| (DistributionKind::Sdist, stem[i + 1..].to_string()))
+}
+
+fn parse_json_page(
+    body: &str,
+    page_url: &Url,
+) -> Result<Vec<IndexLink>, Box<dyn std::error::Error>> {
+    let page: JsonProjectPage = serde_json::from_str(body)?;
+
+    let mut links = Vec::new();
+    for file in page.files {
+        let mut url = page_url.join(&file.url)?;
+        url.set_fragment(None);
+
+        let yanked = match file.yanked {
+            serde_json::Value::Bool(b) => b,
+            serde_json::Value::String(_) => true,
+            _ => false,
+        };
+
+        // Either `true` or a dict of hashes of the metadata file
+        let core_metadata = [file.core_metadata, file.dist_info_metadata]
+            .iter()
+            .any(|value| {
+                matches!(
+                    value,
+                    serde_json::Value::Bool(true) | serde_json::Value::Object(_)
+                )
+            });
+
+        links.push(IndexLink {
+            filename: file.filename,
+            url,
+            hashes: file.hashes,
+            requires_python: file.requires_python,
+            yanked,
+            core_metadata,
+        });
+    }
+
+    Ok(links)
+}
+
+fn parse_html_page(
+    body: &str,
+    page_url: &Url,
+) -> Result<Vec<IndexLink>, Box<dyn std::error::Error>> {
+    let mut links = Vec::new();
+    // Lowercased once; ASCII lowercasing keeps byte offsets the same as in `body`
+    let lower = body.to_ascii_lowercase();
+    let mut pos = 0;
+
+    while let Some(start) = find_anchor_start(&lower, pos) {
+        let Some(tag_end) = lower[start..].find('>').map(|i| start + i) else {
+            break;
+        };
+        let tag = &body[start..tag_end];
+        let text_start = tag_end + 1;
+        let text_end = lower[text_start..]
+            .find("</a>")
+            .map_or(body.len(), |i| text_start + i);
+        let text = unescape_html(body[text_start..text_end].trim());
+        pos = text_end;
+
+        let Some(href) = html_attribute(tag, "href") else {
+            continue;
+        };
+
+        let mut url = page_url.join(&href)?;
+        let mut hashes = BTreeMap::new();
+        if let Some((algo, digest)) = url.fragment().and_then(|f| f.split_once('=')) {
+            hashes.insert(algo.to_string(), digest.to_string());
+        }
+        url.set_fragment(None);
+
+        let filename = if text.is_empty() {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            text
+        };
+
+        links.push(IndexLink {
+            filename,
+            url,
+            hashes,
+            requires_python: html_attribute(tag, "data-requires-python"),
+            yanked: html_attribute(tag, "data-yanked").is_some(),
+            core_metadata: ["data-core-metadata", "data-dist-info-metadata"]
+                .iter()
+                .filter_map(|name| html_attribute(tag, name))
+                .any(|value| value != "false"),
+        });
+    }
+
+    Ok(links)
+}
+
+// Offset of the next `<a ` tag in the lowercased page at or after `from`
+fn find_anchor_start(lower: &str, from: usize) -> Option<usize> {
+    lower[from..]
+        .match_indices("<a")
+        .map(|(i, _)| from + i)
+        .find(|i| {
+            lower[i + 2..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_whitespace())
+        })
+}
+
+fn html_attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search_from = 0;
+
+    while let Some(found) = lower[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+
+        // Must be a whole attribute name, not a suffix of another one
+        let preceded_by_space = lower[..start]
+            .chars()
+            .next_back()
+            .map(|c| c.is_whitespace())
+            .unwrap_or(false);
+        if !preceded_by_space {
+            continue;
+        }
+
+        let raw_after_name = &tag[search_from..];
+        let Some(after_eq) = raw_after_name.trim_start().strip_prefix('=') else {
+            // Valueless attribute such as a bare `data-yanked`
+            if raw_after_name.is_empty()
+                || raw_after_name.starts_with(|c: char| c.is_whitespace() || c == '/')
+            {
+                return Some(String::new());
+            }
+            continue;
+        };
+        let after_eq = after_eq.trim_start();
+
+        let value = match after_eq.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let inner = &after_eq[1..];
+                &inner[..inner.find(quote).unwrap_or(inner.len())]
+            }
+            _ => {
+                let end = after_eq
+                    .find(|c: char| c.is_whitespace() || c == '/')
+                    .unwrap_or(after_eq.len());
+                &after_eq[..end]
+            }
+        };
+
+        return Some(unescape_html(value));
+    }
+
+    None
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::Tag;
+    use std::path::PathBuf;
+
+    // A PEP 503/691 directory index checked into the repo, served over file://
+    fn fixture_index() -> IndexClient {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/index");
+        IndexClient::new(root.to_str().unwrap()).unwrap()
+    }
+
+    fn find<'a>(files: &'a [DistributionFile], filename: &str) -> &'a DistributionFile {
+        files
+            .iter()
+            .find(|f| f.filename == filename)
+            .unwrap_or_else(|| panic!("{} not listed", filename))
+    }
+
+    #[test]
+    fn html_page_lists_files_of_the_project() {
+        let files = fixture_index().list_files("Demo").unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.filename.as_str()).collect();
+        // demo-latest has no PEP 440 version and other-1.0 is another project
+        assert_eq!(
+            names,
+            [
+                "demo-1.0.tar.gz",
+                "demo-1.0-py3-none-any.whl",
+                "demo-1.1.zip",
+                "demo-2.0-py3-none-any.whl",
+                "demo-2.1.tar.bz2",
+                "demo-3.0.tar.xz",
+            ]
+        );
+
+        let sdist = find(&files, "demo-1.0.tar.gz");
+        assert_eq!(sdist.kind, DistributionKind::Sdist);
+        assert_eq!(sdist.version, "1.0".parse().unwrap());
+        assert_eq!(sdist.hashes["sha256"], "1".repeat(64));
+        assert!(!sdist.url.contains('#'));
+        assert!(sdist.url.starts_with("file://"));
+        assert!(!sdist.yanked);
+        assert_eq!(sdist.requires_python, None);
+
+        let wheel = find(&files, "demo-1.0-py3-none-any.whl");
+        assert_eq!(wheel.kind, DistributionKind::Wheel);
+        assert_eq!(wheel.requires_python.as_deref(), Some(">=3.8"));
+        assert!(wheel.core_metadata);
+
+        // Uppercase tags and single quoted, escaped attributes
+        let zip = find(&files, "demo-1.1.zip");
+        assert_eq!(zip.requires_python.as_deref(), Some(">=3.9,<4"));
+        assert!(zip.hashes.is_empty());
+        assert!(!zip.core_metadata);
+
+        // data-yanked with a reason and without a value
+        assert!(find(&files, "demo-2.0-py3-none-any.whl").yanked);
+        assert!(find(&files, "demo-2.1.tar.bz2").yanked);
+
+        // The file name comes from the url when the link has no text
+        assert!(
+            find(&files, "demo-3.0.tar.xz")
+                .url
+                .ends_with("/index/files/demo-3.0.tar.xz")
+        );
+    }
+
+    #[test]
+    fn json_page_lists_files_of_the_project() {
+        let files = fixture_index().list_files("json_pkg").unwrap();
+        assert_eq!(files.len(), 4);
+
+        let sdist = find(&files, "json_pkg-0.1.tar.gz");
+        assert_eq!(sdist.kind, DistributionKind::Sdist);
+        assert_eq!(sdist.hashes["sha256"], "4".repeat(64));
+        assert!(sdist.url.ends_with("/index/json-pkg/json_pkg-0.1.tar.gz"));
+
+        let wheel = find(&files, "json_pkg-0.2-py3-none-any.whl");
+        assert_eq!(
+            wheel.url,
+            "https://files.example.com/json_pkg-0.2-py3-none-any.whl"
+        );
+        assert_eq!(wheel.requires_python.as_deref(), Some(">=3.10"));
+        assert!(wheel.core_metadata);
+        assert!(!wheel.yanked);
+
+        let yanked = find(&files, "json_pkg-0.3-py3-none-any.whl");
+        assert!(yanked.yanked);
+        assert!(yanked.core_metadata);
+
+        assert!(!find(&files, "json_pkg-0.4.tar.gz").yanked);
+    }
+
+    #[test]
+    fn fetches_metadata_served_next_to_a_file() {
+        let index = fixture_index();
+        let files = index.list_files("demo").unwrap();
+        let metadata = index
+            .fetch_metadata(find(&files, "demo-1.0-py3-none-any.whl"))
+            .unwrap();
+        assert_eq!(metadata.name, "demo");
+        assert_eq!(metadata.requires_python.as_deref(), Some(">=3.8"));
+        assert_eq!(
+            metadata.requires_dist,
+            ["six>=1.0", "colorama; sys_platform == \"win32\""]
+        );
+    }
+
+    #[test]
+    fn unknown_project_is_an_error() {
+        assert!(fixture_index().list_files("missing").is_err());
+    }
+
+    #[test]
+    fn select_artifact_skips_yanked_and_prefers_wheels() {
+        let files = fixture_index().list_files("demo").unwrap();
+        let tags = Tags::new(vec![Tag::new("py3", "none", "any")]);
+        let any: SpecifierSet = "".parse().unwrap();
+
+        let best = select_artifact(&files, &any, "demo", &tags, None, &BinaryPolicy::default());
+        assert_eq!(best.unwrap().filename, "demo-3.0.tar.xz");
+
+        let pinned: SpecifierSet = "==1.0".parse().unwrap();
+        let best = select_artifact(
+            &files,
+            &pinned,
+            "demo",
+            &tags,
+            None,
+            &BinaryPolicy::default(),
+        );
+        assert_eq!(best.unwrap().filename, "demo-1.0-py3-none-any.whl");
+
+        let no_binary = BinaryPolicy::new(&[":all:".to_string()], &[]);
+        let best = select_artifact(&files, &pinned, "demo", &tags, None, &no_binary);
+        assert_eq!(best.unwrap().filename, "demo-1.0.tar.gz");
+
+        // Only yanked files satisfy ==2.0
+        let yanked: SpecifierSet = "==2.0".parse().unwrap();
+        assert!(
+            select_artifact(
+                &files,
+                &yanked,
+                "demo",
+                &tags,
+                None,
+                &BinaryPolicy::default()
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn select_artifact_checks_requires_python() {
+        let files = fixture_index().list_files("demo").unwrap();
+        let tags = Tags::new(vec![Tag::new("py3", "none", "any")]);
+        let policy = BinaryPolicy::default();
+        let pick = |specifiers: &str, python: &str| {
+            let specifiers: SpecifierSet = specifiers.parse().unwrap();
+            let python: Version = python.parse().unwrap();
+            select_artifact(&files, &specifiers, "demo", &tags, Some(&python), &policy)
+                .map(|f| f.filename.clone())
+        };
+
+        // The 1.0 wheel needs >=3.8, its sdist says nothing
+        assert_eq!(pick("==1.0", "3.12").unwrap(), "demo-1.0-py3-none-any.whl");
+        assert_eq!(pick("==1.0", "3.7").unwrap(), "demo-1.0.tar.gz");
+        // 1.1 only comes as a zip for >=3.9,<4
+        assert_eq!(pick("==1.1", "3.9.1").unwrap(), "demo-1.1.zip");
+        assert_eq!(pick("==1.1", "3.8"), None);
+        assert_eq!(pick("<1.5", "3.8").unwrap(), "demo-1.0-py3-none-any.whl");
+    }
+
+    #[test]
+    fn parses_large_html_pages() {
+        let page_url = Url::parse("https://example.com/simple/big/").unwrap();
+        let body: String = (0..20_000)
+            .map(|i| format!("<a href=\"big-{i}.0.tar.gz\">big-{i}.0.tar.gz</a><br/>\n"))
+            .collect();
+        let links = parse_html_page(&body, &page_url).unwrap();
+        assert_eq!(links.len(), 20_000);
+        assert_eq!(links[19_999].filename, "big-19999.0.tar.gz");
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize_name("Foo.Bar__baz-"), "foo-bar-baz-");
+        assert_eq!(normalize_name(" Django "), "django");
+    }
+}
@@ -0,0 +1,1235 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use zip::ZipArchive;
+
+use crate::index::normalize_name;
+use crate::metadata::CoreMetadata;
+use crate::python_builder::venv_python;
+
+const INSTALLER: &str = "boxpkg";
+// Holds the box.lock hash of the sdist or wheel a package was installed from,
+// so sync can tell a rebuilt package from the one in the environment
+const SOURCE_HASH_FILE: &str = "BOXPKG_SOURCE_HASH";
+
+// Where the venv's interpreter puts each kind of file
+const SCHEME_SCRIPT: &str = r#"
+import json, sys, sysconfig
+paths = sysconfig.get_paths()
+print(json.dumps({
+    "purelib": paths["purelib"],
+    "platlib": paths["platlib"],
+    "scripts": paths["scripts"],
+    "data": paths["data"],
+    "python": "%d.%d" % sys.version_info[:2],
+}))
+"#;
+
+/// The directories a wheel's files are installed into (the wheel spec's
+/// `.data/` keys), as laid out by one environment's interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheme {
+    pub purelib: PathBuf,
+    pub platlib: PathBuf,
+    pub scripts: PathBuf,
+    pub headers: PathBuf, // per distribution, under {headers}/{name}
+    pub data: PathBuf,
+}
+
+impl Scheme {
+    /// Asks the virtual environment's python where things go.
+    pub fn for_venv(venv_path: &Path) -> Result<Scheme, Box<dyn std::error::Error>> {
+        let output = Command::new(venv_python(venv_path))
+            .arg("-c")
+            .arg(SCHEME_SCRIPT)
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to query the install scheme of {}: {}",
+                venv_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        let paths: BTreeMap<String, String> = serde_json::from_slice(&output.stdout)?;
+        let path = |key: &str| -> Result<PathBuf, Box<dyn std::error::Error>> {
+            paths
+                .get(key)
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("The install scheme has no {} path", key).into())
+        };
+        let data = path("data")?;
+        // Where pip puts headers in a venv
+        let python = paths.get("python").cloned().unwrap_or_default();
+        let headers = data
+            .join("include")
+            .join("site")
+            .join(format!("python{}", python));
+
+        Ok(Scheme {
+            purelib: path("purelib")?,
+            platlib: path("platlib")?,
+            scripts: path("scripts")?,
+            headers,
+            data,
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&Path> {
+        match key {
+            "purelib" => Some(&self.purelib),
+            "platlib" => Some(&self.platlib),
+            "scripts" => Some(&self.scripts),
+            "headers" => Some(&self.headers),
+            "data" => Some(&self.data),
+            _ => None,
+        }
+    }
+}
+
+/// Installs a wheel into a virtual environment without pip, following the
+/// binary distribution format: the wheel's RECORD is checked first, files go to
+/// the scheme directory their `.data/` key names, `#!python` scripts are pointed
+/// at the venv's python, console_scripts and gui_scripts entry points get
+/// launchers, and INSTALLER and RECORD are written. `source_hash` is kept with
+/// the installed package (see `InstalledDistribution`). Returns the installed
+/// .dist-info directory.
+pub fn install_wheel(
+    venv_path: &Path,
+    wheel_path: &Path,
+    source_hash: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let venv_path = venv_path.canonicalize()?;
+    let scheme = Scheme::for_venv(&venv_path)?;
+    install_wheel_with_scheme(&scheme, &venv_python(&venv_path), wheel_path, source_hash)
+}
+
+/// Like `install_wheel`, into explicit scheme directories; `python` goes into
+/// the scripts' shebangs.
+pub fn install_wheel_with_scheme(
+    scheme: &Scheme,
+    python: &Path,
+    wheel_path: &Path,
+    source_hash: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    println!("Installing {}", wheel_path.display());
+    let mut archive = ZipArchive::new(File::open(wheel_path)?)?;
+
+    let dist_info = find_dist_info(&archive, wheel_path)?;
+    let data_dir = format!(
+        "{}.data",
+        dist_info.strip_suffix(".dist-info").unwrap_or(&dist_info)
+    );
+    let wheel_metadata = read_entry(&mut archive, &format!("{}/WHEEL", dist_info))?;
+    let root_is_purelib = parse_wheel_metadata(&wheel_metadata, wheel_path)?;
+    validate_record(&mut archive, &dist_info, wheel_path)?;
+
+    let root = if root_is_purelib {
+        &scheme.purelib
+    } else {
+        &scheme.platlib
+    };
+    let name = dist_info.split('-').next().unwrap_or_default().to_string();
+
+    // Another version left in place would shadow this one's metadata and
+    // leave its files behind, so it goes first, as pip does
+    for previous in installed_in_scheme(scheme)? {
+        if normalize_name(&previous.name) == normalize_name(&name) {
+            println!("Removing {} {}", previous.name, previous.version);
+            uninstall(&previous.dist_info)?;
+        }
+    }
+
+    let record_path = format!("{}/RECORD", dist_info);
+    let mut installed: Vec<RecordEntry> = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let entry_name = entry.name().to_string();
+        // Written fresh below, listing the files where they ended up
+        if entry_name == record_path
+            || entry_name == format!("{}.jws", record_path)
+            || entry_name == format!("{}.p7s", record_path)
+        {
+            continue;
+        }
+
+        let relative = safe_relative_path(&entry_name)
+            .ok_or_else(|| format!("Unsafe path {} in {}", entry_name, wheel_path.display()))?;
+        let (destination, is_script) = match entry_name
+            .strip_prefix(&data_dir)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(rest) => {
+                let (key, rest) = rest.split_once('/').unwrap_or((rest, ""));
+                let base = scheme.get(key).ok_or_else(|| {
+                    format!(
+                        "Unknown scheme directory {} in {}",
+                        key,
+                        wheel_path.display()
+                    )
+                })?;
+                let base = if key == "headers" {
+                    base.join(&name)
+                } else {
+                    base.to_path_buf()
+                };
+                let rest = safe_relative_path(rest).ok_or_else(|| {
+                    format!("Unsafe path {} in {}", entry_name, wheel_path.display())
+                })?;
+                (base.join(rest), key == "scripts")
+            }
+            None => (root.join(relative), false),
+        };
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let executable = is_script || entry.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+
+        let (hash, size) = if is_script {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            let contents = rewrite_shebang(contents, python);
+            fs::write(&destination, &contents)?;
+            (record_hash(&contents), contents.len() as u64)
+        } else {
+            let mut writer = HashingWriter::new(File::create(&destination)?);
+            io::copy(&mut entry, &mut writer)?;
+            writer.finish()
+        };
+        if executable {
+            make_executable(&destination)?;
+        }
+
+        installed.push(RecordEntry {
+            path: record_relative(root, &destination),
+            hash: Some(hash),
+            size: Some(size),
+        });
+    }
+
+    let dist_info_dir = root.join(&dist_info);
+    let entry_points_file = dist_info_dir.join("entry_points.txt");
+    if entry_points_file.exists() {
+        let entry_points = parse_entry_points(&fs::read_to_string(&entry_points_file)?)
+            .map_err(|e| format!("{} in {}", e, wheel_path.display()))?;
+        for entry_point in &entry_points {
+            let launcher = write_launcher(entry_point, &scheme.scripts, python)?;
+            installed.push(launcher_record(root, &launcher)?);
+        }
+    }
+
+    installed.push(write_dist_info_file(
+        root,
+        &dist_info_dir,
+        "INSTALLER",
+        &format!("{}\n", INSTALLER),
+    )?);
+    if let Some(source_hash) = source_hash {
+        installed.push(write_dist_info_file(
+            root,
+            &dist_info_dir,
+            SOURCE_HASH_FILE,
+            &format!("{}\n", source_hash),
+        )?);
+    }
+    installed.push(RecordEntry {
+        path: record_path.clone(),
+        hash: None,
+        size: None,
+    });
+
+    let record: String = installed.iter().map(RecordEntry::to_line).collect();
+    fs::write(dist_info_dir.join("RECORD"), record)?;
+
+    println!("Installed {}", dist_info);
+    Ok(dist_info_dir)
+}
+
+fn write_dist_info_file(
+    root: &Path,
+    dist_info_dir: &Path,
+    name: &str,
+    contents: &str,
+) -> io::Result<RecordEntry> {
+    let path = dist_info_dir.join(name);
+    fs::write(&path, contents)?;
+    Ok(RecordEntry {
+        path: record_relative(root, &path),
+        hash: Some(record_hash(contents.as_bytes())),
+        size: Some(contents.len() as u64),
+    })
+}
+
+/// A package found installed in an environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledDistribution {
+    pub name: String,
+    pub version: String,
+    pub dist_info: PathBuf,
+    pub installer: Option<String>,   // e.g. "boxpkg", "pip"
+    pub source_hash: Option<String>, // only when boxpkg installed it from box.lock
+}
+
+/// Every package installed in the virtual environment, from the .dist-info
+/// directories in its site-packages.
+pub fn installed_distributions(
+    venv_path: &Path,
+) -> Result<Vec<InstalledDistribution>, Box<dyn std::error::Error>> {
+    installed_in_scheme(&Scheme::for_venv(&venv_path.canonicalize()?)?)
+}
+
+fn installed_in_scheme(
+    scheme: &Scheme,
+) -> Result<Vec<InstalledDistribution>, Box<dyn std::error::Error>> {
+    let mut lib_dirs = vec![scheme.purelib.clone(), scheme.platlib.clone()];
+    lib_dirs.dedup();
+
+    let mut installed = Vec::new();
+    for lib_dir in lib_dirs.iter().filter(|dir| dir.exists()) {
+        for entry in fs::read_dir(lib_dir)? {
+            let dist_info = entry?.path();
+            let Some(dir_name) = dist_info.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(stem) = dir_name.strip_suffix(".dist-info") else {
+                continue;
+            };
+            if !dist_info.is_dir() {
+                continue;
+            }
+
+            let read = |name: &str| {
+                fs::read_to_string(dist_info.join(name))
+                    .ok()
+                    .map(|text| text.trim().to_string())
+                    .filter(|text| !text.is_empty())
+            };
+            // The directory name is {name}-{version}, but METADATA has the real spelling
+            let (stem_name, stem_version) = stem.split_once('-').unwrap_or((stem, ""));
+            let metadata = read("METADATA")
+                .map(|text| CoreMetadata::parse(&text))
+                .unwrap_or_default();
+            let or = |field: String, fallback: &str| {
+                if field.is_empty() {
+                    fallback.to_string()
+                } else {
+                    field
+                }
+            };
+
+            installed.push(InstalledDistribution {
+                name: or(metadata.name, stem_name),
+                version: or(metadata.version, stem_version),
+                installer: read("INSTALLER"),
+                source_hash: read(SOURCE_HASH_FILE),
+                dist_info,
+            });
+        }
+    }
+    installed.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(installed)
+}
+
+/// Removes an installed package: every file its RECORD lists, the bytecode
+/// python compiled from them, the directories that leaves empty, and the
+/// .dist-info directory itself.
+pub fn uninstall(dist_info: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let root = dist_info
+        .parent()
+        .ok_or_else(|| format!("Invalid .dist-info directory {}", dist_info.display()))?;
+    let record = fs::read_to_string(dist_info.join("RECORD")).map_err(|e| {
+        format!(
+            "Can't uninstall {} without its RECORD: {}",
+            dist_info.display(),
+            e
+        )
+    })?;
+    println!("Uninstalling {}", dist_info.display());
+
+    let mut dirs = BTreeSet::new();
+    for entry in record.lines().filter_map(RecordEntry::parse) {
+        let relative = Path::new(&entry.path);
+        let path = root.join(relative);
+        remove_file_if_exists(&path)?;
+
+        let Some(parent) = path.parent() else {
+            continue;
+        };
+        if path.extension().is_some_and(|ext| ext == "py")
+            && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+        {
+            let pycache = parent.join("__pycache__");
+            if let Ok(entries) = fs::read_dir(&pycache) {
+                for pyc in entries.flatten() {
+                    let name = pyc.file_name().to_string_lossy().into_owned();
+                    // e.g. module.cpython-311.pyc, module.cpython-311.opt-1.pyc
+                    if name.starts_with(&format!("{}.", stem)) && name.ends_with(".pyc") {
+                        remove_file_if_exists(&pyc.path())?;
+                    }
+                }
+            }
+            dirs.insert(pycache);
+        }
+        // Scripts and headers live outside site-packages, in directories the
+        // environment itself owns; only clean up inside it
+        if relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            dirs.insert(parent.to_path_buf());
+        }
+    }
+    if dist_info.exists() {
+        fs::remove_dir_all(dist_info)?;
+    }
+
+    // Deepest first, so a package directory goes after its subdirectories
+    let mut dirs: Vec<PathBuf> = dirs.into_iter().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in dirs {
+        let mut dir = dir.as_path();
+        while dir != root && dir.starts_with(root) && fs::remove_dir(dir).is_ok() {
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove_file_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// A `console_scripts` or `gui_scripts` entry of entry_points.txt, e.g.
+/// `black = black:patched_main`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryPoint {
+    name: String,
+    module: String,
+    attribute: String, // may be dotted, e.g. "cli.main"
+}
+
+fn parse_entry_points(text: &str) -> Result<Vec<EntryPoint>, String> {
+    let mut entry_points = Vec::new();
+    let mut section = "";
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim();
+            continue;
+        }
+        // Other groups are plugins for whoever reads them, not commands
+        if section != "console_scripts" && section != "gui_scripts" {
+            continue;
+        }
+
+        let invalid = || format!("Invalid entry point in [{}]: {}", section, line);
+        let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+        // Extras ("module:func [extra]") only matter to whoever installed them
+        let value = value.split('[').next().unwrap_or_default().trim();
+        let (module, attribute) = value.split_once(':').ok_or_else(invalid)?;
+        let (name, module, attribute) = (name.trim(), module.trim(), attribute.trim());
+
+        if name.is_empty()
+            || name.contains(['/', '\\'])
+            || name.starts_with('.')
+            || module.is_empty()
+            || attribute.is_empty()
+        {
+            return Err(invalid());
+        }
+        entry_points.push(EntryPoint {
+            name: name.to_string(),
+            module: module.to_string(),
+            attribute: attribute.to_string(),
+        });
+    }
+    Ok(entry_points)
+}
+
+// The same launcher pip writes: import the callable and exit with what it returns
+fn write_launcher(
+    entry_point: &EntryPoint,
+    scripts_dir: &Path,
+    python: &Path,
+) -> io::Result<PathBuf> {
+    let (top, _) = entry_point
+        .attribute
+        .split_once('.')
+        .unwrap_or((&entry_point.attribute, ""));
+    let launcher = format!(
+        "#!{python}
+# -*- coding: utf-8 -*-
+import re
+import sys
+from {module} import {top}
+if __name__ == \"__main__\":
+    sys.argv[0] = re.sub(r\"(-script\\.pyw|\\.exe)?$\", \"\", sys.argv[0])
+    sys.exit({attribute}())
+",
+        python = python.display(),
+        module = entry_point.module,
+        top = top,
+        attribute = entry_point.attribute,
+    );
+
+    if is_interpreter_name(&entry_point.name) {
+        return Err(io::Error::other(format!(
+            "Entry point {} would replace the environment's python",
+            entry_point.name
+        )));
+    }
+    fs::create_dir_all(scripts_dir)?;
+    let path = scripts_dir.join(&entry_point.name);
+    // Writing through a symlink would overwrite whatever it points at
+    if path.symlink_metadata().is_ok_and(|meta| meta.is_symlink()) {
+        fs::remove_file(&path)?;
+    }
+    fs::write(&path, launcher)?;
+    make_executable(&path)?;
+    println!("Created launcher {}", path.display());
+    Ok(path)
+}
+
+// python, python3, python3.12, the names venv links to the interpreter
+fn is_interpreter_name(name: &str) -> bool {
+    let Some(version) = name.strip_prefix("python") else {
+        return false;
+    };
+    version.is_empty()
+        || version
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn launcher_record(root: &Path, path: &Path) -> io::Result<RecordEntry> {
+    let contents = fs::read(path)?;
+    Ok(RecordEntry {
+        path: record_relative(root, path),
+        hash: Some(record_hash(&contents)),
+        size: Some(contents.len() as u64),
+    })
+}
+
+// The one top-level {name}-{version}.dist-info directory
+fn find_dist_info(
+    archive: &ZipArchive<File>,
+    wheel_path: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut dist_infos: Vec<&str> = archive
+        .file_names()
+        .filter_map(|name| name.split('/').next())
+        .filter(|top| top.ends_with(".dist-info"))
+        .collect();
+    dist_infos.sort();
+    dist_infos.dedup();
+
+    match dist_infos.as_slice() {
+        [dist_info] => Ok(dist_info.to_string()),
+        [] => Err(format!("No .dist-info directory in {}", wheel_path.display()).into()),
+        _ => Err(format!(
+            "More than one .dist-info directory in {}: {}",
+            wheel_path.display(),
+            dist_infos.join(", ")
+        )
+        .into()),
+    }
+}
+
+fn read_entry(
+    archive: &mut ZipArchive<File>,
+    name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| format!("Wheel has no {}", name))?;
+    let mut text = String::new();
+    entry.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// Root-Is-Purelib, after checking this is a wheel format we understand
+fn parse_wheel_metadata(text: &str, wheel_path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let field = |key: &str| {
+        text.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.trim().to_string())
+    };
+
+    let version = field("Wheel-Version")
+        .ok_or_else(|| format!("No Wheel-Version in {}", wheel_path.display()))?;
+    if version.split('.').next() != Some("1") {
+        return Err(format!(
+            "Unsupported Wheel-Version {} in {}",
+            version,
+            wheel_path.display()
+        )
+        .into());
+    }
+    Ok(field("Root-Is-Purelib").is_some_and(|v| v.eq_ignore_ascii_case("true")))
+}
+
+// Every file in the wheel must be listed in its RECORD with a matching hash, and
+// everything listed (bar RECORD itself) must be in the wheel
+fn validate_record(
+    archive: &mut ZipArchive<File>,
+    dist_info: &str,
+    wheel_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let record_path = format!("{}/RECORD", dist_info);
+    let record = read_entry(archive, &record_path)?;
+    let mut entries: BTreeMap<String, RecordEntry> = BTreeMap::new();
+    for line in record.lines().filter(|line| !line.trim().is_empty()) {
+        let entry = RecordEntry::parse(line)
+            .ok_or_else(|| format!("Invalid RECORD line in {}: {}", wheel_path.display(), line))?;
+        entries.insert(entry.path.clone(), entry);
+    }
+
+    let invalid = |message: String| -> Box<dyn std::error::Error> {
+        format!("Invalid RECORD in {}: {}", wheel_path.display(), message).into()
+    };
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        if name == record_path
+            || name == format!("{}.jws", record_path)
+            || name == format!("{}.p7s", record_path)
+        {
+            continue;
+        }
+
+        let entry = entries
+            .remove(&name)
+            .ok_or_else(|| invalid(format!("{} isn't listed", name)))?;
+        let expected = entry
+            .hash
+            .ok_or_else(|| invalid(format!("{} has no hash", name)))?;
+        let (algorithm, _) = expected
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("{} has a malformed hash {}", name, expected)))?;
+
+        let (actual, size) = match algorithm {
+            "sha256" => digest(Sha256::new(), &mut file)?,
+            "sha384" => digest(Sha384::new(), &mut file)?,
+            "sha512" => digest(Sha512::new(), &mut file)?,
+            // The spec rules out md5 and sha1
+            _ => {
+                return Err(invalid(format!(
+                    "{} uses unsupported hash {}",
+                    name, algorithm
+                )));
+            }
+        };
+        let actual = format!("{}={}", algorithm, actual);
+        if actual != expected {
+            return Err(invalid(format!(
+                "hash mismatch for {}: expected {}, got {}",
+                name, expected, actual
+            )));
+        }
+        if let Some(expected) = entry.size
+            && expected != size
+        {
+            return Err(invalid(format!(
+                "size mismatch for {}: expected {}, got {}",
+                name, expected, size
+            )));
+        }
+    }
+
+    entries.remove(&record_path);
+    if let Some(missing) = entries.keys().next() {
+        return Err(invalid(format!(
+            "{} is listed but not in the wheel",
+            missing
+        )));
+    }
+    Ok(())
+}
+
+/// One `path,hash,size` line of a RECORD file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecordEntry {
+    path: String,
+    hash: Option<String>, // e.g. "sha256=<urlsafe base64, no padding>"
+    size: Option<u64>,
+}
+
+impl RecordEntry {
+    fn parse(line: &str) -> Option<RecordEntry> {
+        let fields = split_csv(line);
+        let [path, hash, size] = fields.as_slice() else {
+            return None;
+        };
+        Some(RecordEntry {
+            path: path.clone(),
+            hash: (!hash.is_empty()).then(|| hash.clone()),
+            size: if size.is_empty() {
+                None
+            } else {
+                Some(size.parse().ok()?)
+            },
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let path = if self.path.contains([',', '"']) {
+            format!("\"{}\"", self.path.replace('"', "\"\""))
+        } else {
+            self.path.clone()
+        };
+        format!(
+            "{},{},{}\n",
+            path,
+            self.hash.as_deref().unwrap_or_default(),
+            self.size.map(|size| size.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+// RECORD is csv; only paths are ever quoted
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// A wheel path as a relative path that can't climb out of where it's installed
+fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if name.contains('\\') {
+        return None;
+    }
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| path.to_path_buf())
+}
+
+// How RECORD spells an installed file: relative to the directory holding the
+// .dist-info, with `/` separators
+fn record_relative(root: &Path, path: &Path) -> String {
+    let root: Vec<Component> = root.components().collect();
+    let path: Vec<Component> = path.components().collect();
+    let common = root.iter().zip(&path).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); root.len() - common];
+    parts.extend(
+        path[common..]
+            .iter()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+// The urlsafe base64 digest RECORD uses, and how many bytes were read
+fn digest<D: Digest + Write>(mut hasher: D, reader: &mut impl Read) -> io::Result<(String, u64)> {
+    let size = io::copy(reader, &mut hasher)?;
+    Ok((URL_SAFE_NO_PAD.encode(hasher.finalize()), size))
+}
+
+fn record_hash(contents: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(contents))
+    )
+}
+
+// `#!python` (or `#!pythonw`) is the wheel's placeholder for the installing interpreter
+fn rewrite_shebang(contents: Vec<u8>, python: &Path) -> Vec<u8> {
+    if !contents.starts_with(b"#!python") {
+        return contents;
+    }
+    let line_end = contents
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(contents.len());
+    let mut rewritten = format!("#!{}", python.display()).into_bytes();
+    rewritten.extend_from_slice(&contents[line_end..]);
+    rewritten
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Hashes what passes through, so big files are written and hashed in one go
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> (String, u64) {
+        (
+            format!("sha256={}", URL_SAFE_NO_PAD.encode(self.hasher.finalize())),
+            self.size,
+        )
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    const DIST_INFO: &str = "demo-1.0.dist-info";
+    const WHEEL: &[u8] = b"Wheel-Version: 1.0\nRoot-Is-Purelib: true\nTag: py3-none-any\n";
+
+    fn scheme(root: &Path) -> Scheme {
+        Scheme {
+            purelib: root.join("lib"),
+            platlib: root.join("platlib"),
+            scripts: root.join("bin"),
+            headers: root.join("include"),
+            data: root.join("data"),
+        }
+    }
+
+    // A wheel holding `files` plus WHEEL, with `record` as its RECORD, or a
+    // correct RECORD when None
+    fn write_wheel(dir: &Path, files: &[(&str, &[u8])], record: Option<&str>) -> PathBuf {
+        write_wheel_version(dir, "1.0", files, record)
+    }
+
+    fn write_wheel_version(
+        dir: &Path,
+        version: &str,
+        files: &[(&str, &[u8])],
+        record: Option<&str>,
+    ) -> PathBuf {
+        let path = dir.join(format!("demo-{}-py3-none-any.whl", version));
+        let dist_info = format!("demo-{}.dist-info", version);
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+        let wheel_file = format!("{}/WHEEL", dist_info);
+        let mut files: Vec<(&str, &[u8])> = files.to_vec();
+        files.push((&wheel_file, WHEEL));
+        let mut generated = String::new();
+        for (name, contents) in &files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents).unwrap();
+            generated.push_str(&format!(
+                "{},{},{}\n",
+                name,
+                record_hash(contents),
+                contents.len()
+            ));
+        }
+        generated.push_str(&format!("{}/RECORD,,\n", dist_info));
+
+        zip.start_file(format!("{}/RECORD", dist_info), options)
+            .unwrap();
+        zip.write_all(record.unwrap_or(&generated).as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+        path
+    }
+
+    fn install(dir: &Path, wheel: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        install_wheel_with_scheme(
+            &scheme(dir),
+            Path::new("/opt/venv/bin/python"),
+            wheel,
+            Some("sha256:abc"),
+        )
+    }
+
+    #[test]
+    fn installs_and_uninstalls_a_wheel() {
+        let dir = TempDir::new("roundtrip");
+        let wheel = write_wheel(
+            &dir.0,
+            &[
+                ("demo/__init__.py", b"VERSION = '1.0'\n"),
+                (
+                    "demo-1.0.data/scripts/demo-tool",
+                    b"#!python\nprint('tool')\n",
+                ),
+                ("demo-1.0.data/headers/demo.h", b"#define DEMO 1\n"),
+                (
+                    "demo-1.0.dist-info/METADATA",
+                    b"Metadata-Version: 2.1\nName: demo\nVersion: 1.0\n",
+                ),
+            ],
+            None,
+        );
+
+        let dist_info = install(&dir.0, &wheel).unwrap();
+        let lib = dir.0.join("lib");
+        assert_eq!(dist_info, lib.join(DIST_INFO));
+        assert!(lib.join("demo/__init__.py").exists());
+        assert!(dir.0.join("include/demo/demo.h").exists());
+        assert_eq!(
+            fs::read_to_string(dir.0.join("bin/demo-tool")).unwrap(),
+            "#!/opt/venv/bin/python\nprint('tool')\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dist_info.join("INSTALLER")).unwrap(),
+            "boxpkg\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dist_info.join(SOURCE_HASH_FILE)).unwrap(),
+            "sha256:abc\n"
+        );
+
+        // RECORD lists where files ended up, relative to site-packages
+        let record = fs::read_to_string(dist_info.join("RECORD")).unwrap();
+        let paths: Vec<String> = record
+            .lines()
+            .filter_map(RecordEntry::parse)
+            .map(|entry| entry.path)
+            .collect();
+        for expected in [
+            "demo/__init__.py",
+            "../bin/demo-tool",
+            "../include/demo/demo.h",
+            "demo-1.0.dist-info/INSTALLER",
+            "demo-1.0.dist-info/RECORD",
+        ] {
+            assert!(
+                paths.iter().any(|p| p == expected),
+                "{} not in {:?}",
+                expected,
+                paths
+            );
+        }
+
+        uninstall(&dist_info).unwrap();
+        assert!(!lib.join("demo").exists());
+        assert!(!dist_info.exists());
+        assert!(!dir.0.join("bin/demo-tool").exists());
+    }
+
+    #[test]
+    fn writes_launchers_for_entry_points() {
+        let dir = TempDir::new("launchers");
+        let entry_points =
+            b"[console_scripts]\ndemo-cli = demo.cli:main\n[demo.plugins]\nx = y:z\n";
+        let wheel = write_wheel(
+            &dir.0,
+            &[
+                ("demo/cli.py", b"def main():\n    return 0\n"),
+                ("demo-1.0.dist-info/entry_points.txt", entry_points),
+            ],
+            None,
+        );
+        // A link left where the launcher goes is replaced, not written through
+        let target = dir.0.join("target");
+        fs::write(&target, "untouched").unwrap();
+        fs::create_dir_all(dir.0.join("bin")).unwrap();
+        std::os::unix::fs::symlink(&target, dir.0.join("bin/demo-cli")).unwrap();
+
+        let dist_info = install(&dir.0, &wheel).unwrap();
+        let path = dir.0.join("bin/demo-cli");
+        assert!(!path.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "untouched");
+        let launcher = fs::read_to_string(&path).unwrap();
+        assert!(launcher.starts_with("#!/opt/venv/bin/python\n"));
+        assert!(launcher.contains("from demo.cli import main"));
+        assert!(!dir.0.join("bin/x").exists());
+
+        let record = fs::read_to_string(dist_info.join("RECORD")).unwrap();
+        assert!(
+            record
+                .lines()
+                .any(|line| line.starts_with("../bin/demo-cli,"))
+        );
+        uninstall(&dist_info).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_launchers_named_like_the_interpreter() {
+        for name in ["python", "python3", "python3.12"] {
+            let dir = TempDir::new("interpreter");
+            let entry_points = format!("[console_scripts]\n{} = demo:main\n", name);
+            let wheel = write_wheel(
+                &dir.0,
+                &[(
+                    "demo-1.0.dist-info/entry_points.txt",
+                    entry_points.as_bytes(),
+                )],
+                None,
+            );
+            let error = install(&dir.0, &wheel).unwrap_err().to_string();
+            assert!(
+                error.contains("would replace the environment's python"),
+                "{}",
+                error
+            );
+            assert!(!dir.0.join("bin").join(name).exists());
+        }
+        assert!(!is_interpreter_name("python-lsp"));
+        assert!(!is_interpreter_name("python.12"));
+        assert!(!is_interpreter_name("pythonx"));
+    }
+
+    #[test]
+    fn installing_another_version_replaces_the_installed_one() {
+        let dir = TempDir::new("upgrade");
+        let old = write_wheel_version(
+            &dir.0,
+            "1.0",
+            &[
+                ("demo/__init__.py", b"VERSION = '1.0'\n"),
+                ("demo/legacy.py", b"OLD = True\n"),
+            ],
+            None,
+        );
+        install(&dir.0, &old).unwrap();
+        // A differently spelled name in another version is the same package
+        fs::create_dir_all(dir.0.join("lib/Demo-0.9.dist-info")).unwrap();
+        fs::write(dir.0.join("lib/Demo-0.9.dist-info/RECORD"), "").unwrap();
+
+        let new = write_wheel_version(
+            &dir.0,
+            "2.0",
+            &[("demo/__init__.py", b"VERSION = '2.0'\n")],
+            None,
+        );
+        let dist_info = install(&dir.0, &new).unwrap();
+
+        let lib = dir.0.join("lib");
+        let dist_infos: Vec<String> = fs::read_dir(&lib)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".dist-info"))
+            .collect();
+        assert_eq!(dist_infos, ["demo-2.0.dist-info"]);
+        assert_eq!(dist_info, lib.join("demo-2.0.dist-info"));
+        assert!(!lib.join("demo/legacy.py").exists());
+        assert_eq!(
+            fs::read_to_string(lib.join("demo/__init__.py")).unwrap(),
+            "VERSION = '2.0'\n"
+        );
+    }
+
+    #[test]
+    fn refuses_a_file_whose_hash_doesnt_match_record() {
+        let dir = TempDir::new("hash-mismatch");
+        let record = format!(
+            "demo/__init__.py,{},5\n{}/WHEEL,{},{}\n{}/RECORD,,\n",
+            record_hash(b"other"),
+            DIST_INFO,
+            record_hash(WHEEL),
+            WHEEL.len(),
+            DIST_INFO
+        );
+        let wheel = write_wheel(&dir.0, &[("demo/__init__.py", b"x = 1")], Some(&record));
+
+        let error = install(&dir.0, &wheel).unwrap_err().to_string();
+        assert!(
+            error.contains("hash mismatch for demo/__init__.py"),
+            "{}",
+            error
+        );
+        // Checked before anything is written
+        assert!(!dir.0.join("lib").exists());
+    }
+
+    #[test]
+    fn refuses_files_missing_from_record_and_the_wheel() {
+        let dir = TempDir::new("unlisted");
+        let record = format!(
+            "{}/WHEEL,{},{}\n{}/RECORD,,\n",
+            DIST_INFO,
+            record_hash(WHEEL),
+            WHEEL.len(),
+            DIST_INFO
+        );
+        let wheel = write_wheel(&dir.0, &[("demo/__init__.py", b"x = 1")], Some(&record));
+        let error = install(&dir.0, &wheel).unwrap_err().to_string();
+        assert!(error.contains("demo/__init__.py isn't listed"), "{}", error);
+
+        let record = format!(
+            "demo/gone.py,{},1\n{}/WHEEL,{},{}\n{}/RECORD,,\n",
+            record_hash(b"x"),
+            DIST_INFO,
+            record_hash(WHEEL),
+            WHEEL.len(),
+            DIST_INFO
+        );
+        let wheel = write_wheel(&dir.0, &[], Some(&record));
+        let error = install(&dir.0, &wheel).unwrap_err().to_string();
+        assert!(
+            error.contains("demo/gone.py is listed but not in the wheel"),
+            "{}",
+            error
+        );
+
+        let record = format!(
+            "demo/__init__.py,md5=abc,5\n{}/WHEEL,{},{}\n{}/RECORD,,\n",
+            DIST_INFO,
+            record_hash(WHEEL),
+            WHEEL.len(),
+            DIST_INFO
+        );
+        let wheel = write_wheel(&dir.0, &[("demo/__init__.py", b"x = 1")], Some(&record));
+        let error = install(&dir.0, &wheel).unwrap_err().to_string();
+        assert!(error.contains("unsupported hash md5"), "{}", error);
+    }
+
+    #[test]
+    fn refuses_paths_escaping_the_install_directories() {
+        for escaping in [
+            "../evil.py",
+            "demo-1.0.data/purelib/../../evil.py",
+            "demo-1.0.data/scripts/../../../evil",
+        ] {
+            let dir = TempDir::new("escape");
+            let wheel = write_wheel(&dir.0, &[(escaping, b"evil")], None);
+            let error = install(&dir.0, &wheel).unwrap_err().to_string();
+            assert!(error.contains("Unsafe path"), "{}: {}", escaping, error);
+            assert!(!dir.0.join("evil.py").exists());
+            assert!(!dir.0.parent().unwrap().join("evil.py").exists());
+        }
+
+        let dir = TempDir::new("unknown-key");
+        let wheel = write_wheel(&dir.0, &[("demo-1.0.data/elsewhere/x", b"x")], None);
+        let error = install(&dir.0, &wheel).unwrap_err().to_string();
+        assert!(
+            error.contains("Unknown scheme directory elsewhere"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn safe_relative_paths() {
+        assert!(safe_relative_path("demo/__init__.py").is_some());
+        assert!(safe_relative_path("./demo/x.py").is_some());
+        assert!(safe_relative_path("../x").is_none());
+        assert!(safe_relative_path("/etc/passwd").is_none());
+        assert!(safe_relative_path("demo\\..\\x").is_none());
+        assert!(safe_relative_path("demo/../../x").is_none());
+    }
+
+    #[test]
+    fn parses_record_lines() {
+        let entry = RecordEntry::parse("\"odd,name\"\"s.py\",sha256=abc,12").unwrap();
+        assert_eq!(entry.path, "odd,name\"s.py");
+        assert_eq!(entry.hash.as_deref(), Some("sha256=abc"));
+        assert_eq!(entry.size, Some(12));
+        assert_eq!(RecordEntry::parse(entry.to_line().trim_end()), Some(entry));
+
+        let record = RecordEntry::parse("demo-1.0.dist-info/RECORD,,").unwrap();
+        assert_eq!((record.hash, record.size), (None, None));
+        assert!(RecordEntry::parse("a,b").is_none());
+        assert!(RecordEntry::parse("a,sha256=x,big").is_none());
+    }
+
+    #[test]
+    fn parses_entry_points() {
+        let entry_points = parse_entry_points(
+            "# comment\n[console_scripts]\nblack = black:patched_main\n\
+             tool=pkg.cli:app.run [extra]\n[gui_scripts]\nviewer = pkg.gui:main\n\
+             [other]\nignored = nothing\n",
+        )
+        .unwrap();
+        let names: Vec<(&str, &str, &str)> = entry_points
+            .iter()
+            .map(|e| (e.name.as_str(), e.module.as_str(), e.attribute.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("black", "black", "patched_main"),
+                ("tool", "pkg.cli", "app.run"),
+                ("viewer", "pkg.gui", "main"),
+            ]
+        );
+
+        for bad in ["x = nocolon", "../evil = a:b", " = a:b", "x = :b"] {
+            let text = format!("[console_scripts]\n{}\n", bad);
+            assert!(parse_entry_points(&text).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn record_paths_are_relative_to_site_packages() {
+        let root = Path::new("/venv/lib/python3.11/site-packages");
+        assert_eq!(
+            record_relative(
+                root,
+                Path::new("/venv/lib/python3.11/site-packages/demo/x.py")
+            ),
+            "demo/x.py"
+        );
+        assert_eq!(
+            record_relative(root, Path::new("/venv/bin/demo")),
+            "../../../bin/demo"
+        );
+    }
+}
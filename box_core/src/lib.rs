@@ -1,51 +1,112 @@
 use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::process::Command;
+
+mod archive;
+pub use archive::{ArchiveFormat, extract_archive};
 
 mod build_tuple;
 
 use crate::build_tuple::BuildTuple;
 
+mod index;
+pub use index::{
+    BinaryPolicy, DEFAULT_INDEX_URL, DistributionFile, DistributionKind, IndexClient,
+    normalize_name, select_artifact,
+};
+
+mod markers;
+pub use markers::{Marker, MarkerEnvironment, MarkerOperator, MarkerValue};
+
+mod metadata;
+pub use metadata::{CoreMetadata, read_sdist_metadata, read_wheel_metadata};
+
+mod provider;
+pub use provider::IndexDependencyProvider;
+
+mod requirement;
+pub use requirement::Requirement;
+
+mod resolver;
+pub use resolver::{DependencyProvider, Resolution, ResolveError, resolve};
+
+mod tags;
+pub use tags::{Tag, Tags, WheelFilename};
+
+mod version;
+pub use version::{Operator, Specifier, SpecifierSet, Version};
+
+mod scheduler;
+pub use scheduler::{Job, JobOutput, Progress, ScheduleError, run_jobs};
+
 mod system_resolver;
-use system_resolver::{detect_abi_tag, detect_platform, detect_python_version};
+use system_resolver::{
+    detect_compiler, detect_interpreter_tags, detect_marker_environment, detect_python_version,
+};
 
+use reqwest::Url;
 use reqwest::blocking::get;
 use std::fs::File;
 use std::io::copy;
 
-use flate2::read::GzDecoder;
-use tar::Archive;
+use sha2::{Digest, Sha256};
 
 mod python_builder;
-use python_builder::{build_wheel, setup_python_env};
+pub use python_builder::{
+    BuildError, BuildSystem, build_editable, build_wheel, has_build_system, read_build_system,
+    setup_python_env,
+};
+
+mod installer;
+pub use installer::{
+    InstalledDistribution, Scheme, install_wheel, install_wheel_with_scheme,
+    installed_distributions, uninstall,
+};
+
+#[cfg(test)]
+pub(crate) mod test_support;
 
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::process::Command;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct SystemEnvironmentInfo {
-    pub platform: String,
-    pub python_version: String,
-    pub abi_tag: String,
+    pub platform: String, // most specific platform tag, e.g. "manylinux_2_35_x86_64"
+    pub python_version: String, // e.g. "3.11.7"
+    pub interpreter_tag: String, // e.g. "cp311"
+    pub abi_tag: String,  // most specific ABI tag, e.g. "cp311", "cp311d"
+    pub compiler: Option<String>,
+    pub markers: MarkerEnvironment,
+    pub tags: Tags,
 }
 
 pub fn get_system_info() -> SystemEnvironmentInfo {
     let py = detect_python_version();
-    let abi = detect_abi_tag().unwrap_or("None".to_string());
+    let python_version = py.unwrap_or("".to_string());
+
+    let markers = detect_marker_environment(&python_version);
+    let interpreter = detect_interpreter_tags(&markers);
 
     let system_env: SystemEnvironmentInfo = SystemEnvironmentInfo {
-        platform: detect_platform().to_string(),
-        python_version: py.unwrap_or("".to_string()),
-        abi_tag: abi,
+        platform: interpreter
+            .platforms
+            .first()
+            .cloned()
+            .unwrap_or("any".to_string()),
+        interpreter_tag: interpreter.interpreter(),
+        abi_tag: interpreter
+            .abis
+            .first()
+            .cloned()
+            .unwrap_or("none".to_string()),
+        compiler: detect_compiler(),
+        tags: interpreter.tags(),
+        markers,
+        python_version,
     };
 
-    println!(
-        "platform {} python_version {} abi_tag {}",
-        system_env.platform, system_env.python_version, system_env.abi_tag
-    );
-
-    return system_env;
+    system_env
 }
 
 pub fn get_build_tuple(name: &str, version: &str, system_env: SystemEnvironmentInfo) -> BuildTuple {
@@ -59,30 +120,45 @@ pub fn get_build_tuple(name: &str, version: &str, system_env: SystemEnvironmentI
         python_version: system_env.python_version,
         platform: system_env.platform,
         abi: system_env.abi_tag,
-        compiler: Some("gcc".to_string()),
+        compiler: system_env.compiler,
         build_flags: flags,
     };
 
     // println!("Build tuple: {:#?}", tuple);
     // println!("Cache key: {}", tuple.hash_key());
 
-    return tuple;
+    tuple
 }
 
 pub fn download_source(url: &str, path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
     if let Some((_, base)) = url.rsplit_once('/') {
         println!("filename: {}", base);
-        // Perform the GET request
-        let response = get(url)?;
-
         let file_path = path.join(base);
 
+        // Local indexes hand out file:// links, which reqwest can't fetch
+        if let Ok(parsed) = Url::parse(url)
+            && parsed.scheme() == "file"
+        {
+            let source = parsed
+                .to_file_path()
+                .map_err(|_| format!("Invalid file url: {}", url))?;
+            fs::copy(&source, &file_path)?;
+            println!("Copied to {}", file_path.display());
+            return Ok(file_path);
+        }
+
+        // Perform the GET request; a 404 or error page must not be saved as the artifact
+        let mut response = get(url)?.error_for_status()?;
+
         // Create a file to save the tarball
         let mut out = File::create(&file_path)?;
 
-        // Stream the response into the file
-        let mut content = response;
-        copy(&mut content, &mut out)?;
+        // Stream the response into the file, leaving no partial download behind
+        if let Err(e) = copy(&mut response, &mut out) {
+            drop(out);
+            let _ = fs::remove_file(&file_path);
+            return Err(e.into());
+        }
 
         println!("Downloaded to {}", file_path.display());
         return Ok(file_path.to_path_buf());
@@ -90,112 +166,383 @@ pub fn download_source(url: &str, path: &Path) -> Result<PathBuf, Box<dyn std::e
     Err("couldn't get base comp of url".into())
 }
 
-pub fn extract_tar_gz(file_path: &Path, output_dir: &Path) -> std::io::Result<()> {
-    // Open the .tar.gz file
-    let file = File::open(file_path)?;
-    let decoder = GzDecoder::new(file);
+/// Clones the git repository at `url` into `dest`, replacing whatever is
+/// there, and checks out `rev` (a commit, tag or branch; the default branch
+/// when None). Returns the full hash of the commit checked out.
+pub fn git_checkout(
+    url: &str,
+    rev: Option<&str>,
+    dest: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Both come from the manifest; git would take "--upload-pack=..." for an option
+    if let Some(rev) = rev.filter(|rev| rev.starts_with('-')) {
+        return Err(format!("Invalid git revision {}", rev).into());
+    }
+    if dest.exists() {
+        fs::remove_dir_all(dest)?;
+    }
+    println!("Cloning {}", url);
+    let status = Command::new("git")
+        .args(["clone", "--quiet", "--"])
+        .arg(url)
+        .arg(dest)
+        .status()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !status.success() {
+        return Err(format!("git clone {} exited with {}", url, status).into());
+    }
 
-    // Create a tar Archive from the decompressed stream
-    let mut archive = Archive::new(decoder);
+    if let Some(rev) = rev {
+        let git = |args: &[&str]| -> io::Result<bool> {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dest)
+                .args(args)
+                .output()?;
+            Ok(output.status.success())
+        };
+        let checkout = |target: &str| git(&["checkout", "--quiet", "--detach", target]);
+
+        // After a clone, a branch other than the default one is only origin/<branch>,
+        // and a commit no branch holds any more has to be fetched by itself
+        let found = checkout(rev)?
+            || checkout(&format!("origin/{}", rev))?
+            || (git(&["fetch", "--quiet", "origin", rev])? && checkout("FETCH_HEAD")?);
+        if !found {
+            return Err(format!("{} has no revision {}", url, rev).into());
+        }
+    }
 
-    // Extract the archive to the specified directory
-    archive.unpack(output_dir)?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dest)
+        .args(["rev-parse", "HEAD"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("Couldn't read the commit checked out from {}", url).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
+/// Hex sha256 digest of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// What the top of a source tree holds besides its sources: VCS data,
+// environments, build output. Deeper down, a `build/` may well be a subpackage
+const UNHASHED_DIRS: &[&str] = &[".git", ".hg", ".box", ".venv", "venv", "build", "dist"];
+
+/// Hex sha256 digest of a source tree: every file's relative path and
+/// contents, in sorted order, so it changes whenever a source does.
+pub fn hash_directory(path: &Path) -> io::Result<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let top_level = dir == path;
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                // Bytecode and egg-info metadata are generated at any depth
+                let generated = name == "__pycache__"
+                    || name.ends_with(".egg-info")
+                    || (top_level && UNHASHED_DIRS.contains(&name.as_str()));
+                if !generated {
+                    dirs.push(entry.path());
+                }
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in &files {
+        let relative = file.strip_prefix(path).unwrap_or(file);
+        hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::metadata(file)?.len().to_le_bytes());
+        copy(&mut File::open(file)?, &mut hasher)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks a file against a `sha256:<hex>` hash as recorded in box.lock.
+pub fn verify_hash(path: &Path, expected: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (algorithm, digest) = expected.split_once(':').unwrap_or(("sha256", expected));
+    if algorithm != "sha256" {
+        return Err(format!(
+            "Unsupported hash algorithm {} for {}",
+            algorithm,
+            path.display()
+        )
+        .into());
+    }
+
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(digest) {
+        return Err(format!(
+            "Hash mismatch for {}: expected sha256:{}, got sha256:{}",
+            path.display(),
+            digest,
+            actual
+        )
+        .into());
+    }
     Ok(())
 }
 
-pub fn create_venv_and_build(project_path: &Path) {
-    let _ = setup_python_env(project_path);
-    let _ = build_wheel(project_path);
+/// Downloads a distribution into `path` and checks it against the sha256 the
+/// index published for it. A file that fails the check is deleted.
+pub fn download_artifact(
+    file: &DistributionFile,
+    path: &Path,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let file_path = download_source(&file.url, path)?;
+
+    match file.hashes.get("sha256") {
+        Some(expected) => {
+            if let Err(e) = verify_hash(&file_path, expected) {
+                let _ = fs::remove_file(&file_path);
+                return Err(e);
+            }
+            println!("Verified sha256 of {}", file.filename);
+        }
+        None => println!("Warning: index gave no sha256 for {}", file.filename),
+    }
+
+    Ok(file_path)
 }
 
-pub fn move_wheel(
+/// Unpacks a zip or tarball sdist into `build_dir` and builds it through its PEP 517
+/// backend, with build requirements from `index_url`, returning the path of the
+/// wheel, which is left in `build_dir`/wheels.
+pub fn build_sdist(
+    sdist_path: &Path,
     build_dir: &Path,
+    index_url: &str,
+) -> Result<PathBuf, BuildError> {
+    let project_source_folder = extract_archive(sdist_path, build_dir).map_err(|e| {
+        if e.kind() == io::ErrorKind::InvalidData {
+            BuildError::InvalidSdist(sdist_path.to_path_buf())
+        } else {
+            BuildError::Io(e)
+        }
+    })?;
+    if project_source_folder == build_dir {
+        // Not the single {name}-{version}/ directory an sdist should hold
+        return Err(BuildError::InvalidSdist(sdist_path.to_path_buf()));
+    }
+    let unzipped_folder = project_source_folder
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    let wheel_path = build_wheel(
+        &project_source_folder,
+        &build_dir.join(format!("{}-build-env", unzipped_folder)),
+        &build_dir.join("wheels"),
+        index_url,
+    )?;
+    println!("built successfully.");
+
+    Ok(wheel_path)
+}
+
+/// Moves a built wheel into `cache_dir`, returning its new path.
+pub fn move_wheel(
+    wheel_file: &Path,
     cache_dir: &Path,
 ) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let dist_dir = build_dir.join("dist/").canonicalize();
-    match dist_dir {
-        Ok(dist_dir) => {
-            println!("dist_dir {:?} ", dist_dir.display());
-
-            // Check if dist/ exists
-            if !dist_dir.exists() {
-                println!("! dist_dir exists");
-                return Err(Box::new(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "dist/ directory not found",
-                )));
+    // Build the destination path
+    let dest_path = cache_dir.join(
+        wheel_file
+            .file_name()
+            .ok_or("wheel path has no file name")?,
+    );
+
+    // Move the file
+    fs::rename(wheel_file, &dest_path)?;
+
+    Ok(dest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Answers a single request on localhost with `status` and `body`
+    fn serve_once(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
             }
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        format!("http://127.0.0.1:{}/pkg-1.0.tar.gz", port)
+    }
 
-            println!("dist_dir.exists ");
-
-            // Find the first .whl file in dist/
-            let wheel_file = fs::read_dir(&dist_dir)?
-                .filter_map(Result::ok)
-                .find(|entry| {
-                    entry
-                        .path()
-                        .extension()
-                        .map(|ext| ext == "whl")
-                        .unwrap_or(false)
-                })
-                .ok_or_else(|| {
-                    io::Error::new(io::ErrorKind::NotFound, "No .whl file found in dist/")
-                })?;
-
-            println!("wheel_file {:?} ", wheel_file);
-
-            // Ensure cache dir exists
-            // fs::create_dir_all(cache_dir)?;
-
-            // Build the destination path
-            let dest_path = cache_dir.join(wheel_file.file_name());
-
-            // Move the file
-            fs::rename(wheel_file.path(), &dest_path)?;
-
-            println!(
-                "wheel_file {:?} dest_path {:?}",
-                wheel_file.path().to_str(),
-                &dest_path.to_str()
-            );
-            return Ok(dest_path);
-        }
-        Err(e) => {
-            return Err(Box::new(e));
-        }
+    #[test]
+    fn download_source_saves_the_body() {
+        let dir = TempDir::new("download-ok");
+        let url = serve_once("200 OK", "sdist bytes");
+        let path = download_source(&url, &dir.0).unwrap();
+        assert_eq!(path, dir.0.join("pkg-1.0.tar.gz"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "sdist bytes");
     }
-}
 
-pub fn install_wheel(
-    venv_path: &Path,
-    wheel_path: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Path to the virtual env's Python executable
-    let mut python_executable = venv_path.join("bin").join("python");
-    if cfg!(target_os = "windows") {
-        python_executable = venv_path.join("Scripts").join("python.exe"); // Windows
-    }
-    println!("python_executable {}", python_executable.display());
-    println!("wheel_path.to_str() {}", wheel_path.to_str().unwrap());
-    // Run: python -m pip install /path/to/wheel.whl
-    let status = Command::new(python_executable)
-        .args(["-m", "pip", "install", wheel_path.to_str().unwrap()])
-        .status()
-        .expect("pip install failed");
+    #[test]
+    fn download_source_refuses_error_responses() {
+        let dir = TempDir::new("download-404");
+        let url = serve_once("404 Not Found", "<html>Not Found</html>");
+        assert!(download_source(&url, &dir.0).is_err());
+        assert!(!dir.0.join("pkg-1.0.tar.gz").exists());
+    }
+
+    #[test]
+    fn hash_directory_skips_build_output_only_at_the_top() {
+        let dir = TempDir::new("hash-directory");
+        fs::create_dir_all(dir.0.join("pkg/build")).unwrap();
+        fs::write(dir.0.join("pkg/__init__.py"), "").unwrap();
+        fs::write(dir.0.join("pkg/build/steps.py"), "STEPS = 1\n").unwrap();
+        let original = hash_directory(&dir.0).unwrap();
+
+        // Generated files don't count
+        for generated in [
+            "build/lib",
+            "dist",
+            ".git/objects",
+            "pkg/__pycache__",
+            "src/pkg.egg-info",
+        ] {
+            fs::create_dir_all(dir.0.join(generated)).unwrap();
+            fs::write(dir.0.join(generated).join("file"), "generated").unwrap();
+        }
+        assert_eq!(hash_directory(&dir.0).unwrap(), original);
 
-    println!("status {}", status.code().expect("FAIL"));
+        // A subpackage named build/ does
+        fs::write(dir.0.join("pkg/build/steps.py"), "STEPS = 2\n").unwrap();
+        assert_ne!(hash_directory(&dir.0).unwrap(), original);
+    }
 
-    if !status.success() {
-        println!("Failed to install wheel {}", wheel_path.display());
-        return Err("Failed to install wheel".into());
-    } else {
-        println!("python_executable {} installed", wheel_path.display());
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=box", "-c", "user.email=box@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {:?}", args, output);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
     }
 
-    Ok(())
-}
+    #[test]
+    fn git_checkout_finds_commits_tags_and_branches() {
+        // A bare repository with a tag on main and a second branch
+        let dir = TempDir::new("git-checkout");
+        let remote = dir.0.join("remote.git");
+        let work = dir.0.join("work");
+        fs::create_dir_all(&work).unwrap();
+        git(
+            &dir.0,
+            &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+        );
+        git(&work, &["init", "--quiet", "-b", "main"]);
+        fs::write(work.join("version.txt"), "1").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "--quiet", "-m", "first"]);
+        git(&work, &["tag", "v1"]);
+        let first = git(&work, &["rev-parse", "HEAD"]);
+        fs::write(work.join("version.txt"), "2").unwrap();
+        git(&work, &["commit", "--quiet", "-am", "second"]);
+        let second = git(&work, &["rev-parse", "HEAD"]);
+        git(&work, &["checkout", "--quiet", "-b", "feature", &first]);
+        fs::write(work.join("version.txt"), "feature").unwrap();
+        git(&work, &["commit", "--quiet", "-am", "feature"]);
+        let feature = git(&work, &["rev-parse", "HEAD"]);
+        let remote_arg = remote.to_str().unwrap();
+        git(
+            &work,
+            &["push", "--quiet", remote_arg, "main", "feature", "v1"],
+        );
+
+        let url = format!("file://{}", remote.display());
+        let dest = dir.0.join("checkout");
+        let checked_out = |rev: Option<&str>| {
+            let commit = git_checkout(&url, rev, &dest).unwrap();
+            (
+                commit,
+                fs::read_to_string(dest.join("version.txt")).unwrap(),
+            )
+        };
+        assert_eq!(checked_out(None), (second.clone(), "2".to_string()));
+        assert_eq!(checked_out(Some("v1")), (first.clone(), "1".to_string()));
+        assert_eq!(checked_out(Some("main")), (second, "2".to_string()));
+        assert_eq!(
+            checked_out(Some("feature")),
+            (feature.clone(), "feature".to_string())
+        );
+        assert_eq!(checked_out(Some(&feature[..12])).0, feature);
+        assert_eq!(checked_out(Some(&first)).0, first);
+
+        let missing = git_checkout(&url, Some("no-such-branch"), &dest).unwrap_err();
+        assert!(
+            missing
+                .to_string()
+                .contains("has no revision no-such-branch")
+        );
+    }
+
+    #[test]
+    fn git_checkout_takes_no_options_from_the_manifest() {
+        let dir = TempDir::new("git-options");
+        let marker = dir.0.join("ran");
+        let dest = dir.0.join("checkout");
+
+        let url = format!("--upload-pack=touch {}", marker.display());
+        assert!(git_checkout(&url, None, &dest).is_err());
+        assert!(!marker.exists());
+
+        let error = git_checkout("file:///nowhere.git", Some("--output=x"), &dest).unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid git revision"),
+            "{}",
+            error
+        );
+    }
 
-pub fn create_python_env(project_path: &Path) {
-    let _ = setup_python_env(project_path);
+    #[test]
+    fn verify_hash_checks_sha256() {
+        let dir = TempDir::new("verify-hash");
+        let file = dir.0.join("artifact");
+        fs::write(&file, "hello").unwrap();
+        let digest = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        verify_hash(&file, &format!("sha256:{}", digest)).unwrap();
+        verify_hash(&file, &digest.to_uppercase()).unwrap();
+        assert!(verify_hash(&file, "sha256:00").is_err());
+        assert!(verify_hash(&file, &format!("md5:{}", digest)).is_err());
+    }
 }
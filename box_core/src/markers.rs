@@ -0,0 +1,486 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::index::normalize_name;
+use crate::version::{Operator, Specifier, Version};
+
+/// The PEP 508 environment marker variables of the target interpreter.
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq, Deserialize)]
+pub struct MarkerEnvironment {
+    pub implementation_name: String,            // e.g. "cpython"
+    pub implementation_version: String,         // e.g. "3.11.7"
+    pub os_name: String,                        // e.g. "posix", "nt"
+    pub platform_machine: String,               // e.g. "x86_64", "arm64"
+    pub platform_python_implementation: String, // e.g. "CPython"
+    pub platform_release: String,               // e.g. "6.5.0-21-generic"
+    pub platform_system: String,                // e.g. "Linux", "Windows"
+    pub platform_version: String,               // e.g. "#21~22.04.1-Ubuntu SMP ..."
+    pub python_full_version: String,            // e.g. "3.11.7"
+    pub python_version: String,                 // e.g. "3.11"
+    pub sys_platform: String,                   // e.g. "linux", "win32", "darwin"
+}
+
+impl MarkerEnvironment {
+    fn get(&self, variable: &str) -> Option<&str> {
+        let value = match variable {
+            "implementation_name" => &self.implementation_name,
+            "implementation_version" => &self.implementation_version,
+            "os_name" => &self.os_name,
+            "platform_machine" => &self.platform_machine,
+            "platform_python_implementation" => &self.platform_python_implementation,
+            "platform_release" => &self.platform_release,
+            "platform_system" => &self.platform_system,
+            "platform_version" => &self.platform_version,
+            "python_full_version" => &self.python_full_version,
+            "python_version" => &self.python_version,
+            "sys_platform" => &self.sys_platform,
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MarkerValue {
+    Variable(String),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarkerOperator {
+    Compare(Operator),
+    In,
+    NotIn,
+}
+
+/// A parsed environment marker, e.g. `python_version < "3.10" and os_name == "posix"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Marker {
+    And(Vec<Marker>),
+    Or(Vec<Marker>),
+    Expression {
+        left: MarkerValue,
+        operator: MarkerOperator,
+        right: MarkerValue,
+    },
+}
+
+impl Marker {
+    /// `extras` are the extras requested of the package whose metadata this
+    /// marker came from; they are what `extra == "..."` is checked against.
+    pub fn evaluate(&self, env: &MarkerEnvironment, extras: &[String]) -> bool {
+        match self {
+            Marker::And(markers) => markers.iter().all(|m| m.evaluate(env, extras)),
+            Marker::Or(markers) => markers.iter().any(|m| m.evaluate(env, extras)),
+            Marker::Expression {
+                left,
+                operator,
+                right,
+            } => evaluate_expression(left, *operator, right, env, extras),
+        }
+    }
+}
+
+fn evaluate_expression(
+    left: &MarkerValue,
+    operator: MarkerOperator,
+    right: &MarkerValue,
+    env: &MarkerEnvironment,
+    extras: &[String],
+) -> bool {
+    // `extra` has no single value; it matches any requested extra by normalized name
+    let extra_literal = match (left, right) {
+        (MarkerValue::Variable(v), MarkerValue::Literal(l))
+        | (MarkerValue::Literal(l), MarkerValue::Variable(v))
+            if v == "extra" =>
+        {
+            Some(normalize_name(l))
+        }
+        _ => None,
+    };
+    if let Some(extra) = extra_literal {
+        let requested = extras.iter().any(|e| normalize_name(e) == extra);
+        return match operator {
+            MarkerOperator::Compare(Operator::Equal) => requested,
+            MarkerOperator::Compare(Operator::NotEqual) => !requested,
+            _ => false,
+        };
+    }
+
+    let resolve = |value: &MarkerValue| -> String {
+        match value {
+            MarkerValue::Variable(name) => env.get(name).unwrap_or_default().to_string(),
+            MarkerValue::Literal(literal) => literal.clone(),
+        }
+    };
+    let left = resolve(left);
+    let right = resolve(right);
+
+    match operator {
+        MarkerOperator::In => right.contains(&left),
+        MarkerOperator::NotIn => !right.contains(&left),
+        MarkerOperator::Compare(op) => {
+            // PEP 508: compare as versions when both sides are versions, as strings otherwise
+            let specifier = format!("{}{}", op.as_str(), right).parse::<Specifier>();
+            if let (Ok(specifier), Ok(version)) = (specifier, left.parse::<Version>()) {
+                return specifier.contains(&version);
+            }
+            match op {
+                Operator::Equal | Operator::ArbitraryEqual => left == right,
+                Operator::NotEqual => left != right,
+                Operator::Less => left < right,
+                Operator::LessEqual => left <= right,
+                Operator::Greater => left > right,
+                Operator::GreaterEqual => left >= right,
+                Operator::Compatible => false,
+            }
+        }
+    }
+}
+
+impl fmt::Display for MarkerValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarkerValue::Variable(name) => write!(f, "{}", name),
+            MarkerValue::Literal(literal) if literal.contains('"') => write!(f, "'{}'", literal),
+            MarkerValue::Literal(literal) => write!(f, "\"{}\"", literal),
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Marker::And(markers) => {
+                let parts: Vec<String> = markers
+                    .iter()
+                    .map(|m| match m {
+                        Marker::Or(_) => format!("({})", m),
+                        _ => m.to_string(),
+                    })
+                    .collect();
+                write!(f, "{}", parts.join(" and "))
+            }
+            Marker::Or(markers) => {
+                let parts: Vec<String> = markers.iter().map(|m| m.to_string()).collect();
+                write!(f, "{}", parts.join(" or "))
+            }
+            Marker::Expression {
+                left,
+                operator,
+                right,
+            } => {
+                let operator = match operator {
+                    MarkerOperator::Compare(op) => op.as_str(),
+                    MarkerOperator::In => "in",
+                    MarkerOperator::NotIn => "not in",
+                };
+                write!(f, "{} {} {}", left, operator, right)
+            }
+        }
+    }
+}
+
+impl FromStr for Marker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let marker = parser.or()?;
+
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("Invalid marker (unexpected trailing input): {}", s));
+        }
+        Ok(marker)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    OpenParen,
+    CloseParen,
+    Literal(String),
+    Word(String),     // variable names and the and/or/in/not keywords
+    Operator(String), // comparison operators
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::OpenParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::CloseParen);
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&q| q == c)
+                .ok_or_else(|| format!("Invalid marker (unclosed string): {}", s))?;
+            tokens.push(Token::Literal(chars[i + 1..i + 1 + end].iter().collect()));
+            i += end + 2;
+        } else if "<>=!~".contains(c) {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| "<>=!~".contains(**c))
+                .count();
+            tokens.push(Token::Operator(chars[i..i + len].iter().collect()));
+            i += len;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            let len = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '.')
+                .count();
+            tokens.push(Token::Word(chars[i..i + len].iter().collect()));
+            i += len;
+        } else {
+            return Err(format!("Invalid marker (unexpected '{}'): {}", c, s));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w == word)
+    }
+
+    fn or(&mut self) -> Result<Marker, String> {
+        let mut markers = vec![self.and()?];
+        while self.peek_word("or") {
+            self.pos += 1;
+            markers.push(self.and()?);
+        }
+        Ok(if markers.len() == 1 {
+            markers.remove(0)
+        } else {
+            Marker::Or(markers)
+        })
+    }
+
+    fn and(&mut self) -> Result<Marker, String> {
+        let mut markers = vec![self.atom()?];
+        while self.peek_word("and") {
+            self.pos += 1;
+            markers.push(self.atom()?);
+        }
+        Ok(if markers.len() == 1 {
+            markers.remove(0)
+        } else {
+            Marker::And(markers)
+        })
+    }
+
+    fn atom(&mut self) -> Result<Marker, String> {
+        if self.tokens.get(self.pos) == Some(&Token::OpenParen) {
+            self.pos += 1;
+            let marker = self.or()?;
+            if self.tokens.get(self.pos) != Some(&Token::CloseParen) {
+                return Err("Invalid marker (unclosed parenthesis)".to_string());
+            }
+            self.pos += 1;
+            return Ok(marker);
+        }
+
+        let left = self.value()?;
+        let operator = self.operator()?;
+        let right = self.value()?;
+        Ok(Marker::Expression {
+            left,
+            operator,
+            right,
+        })
+    }
+
+    fn value(&mut self) -> Result<MarkerValue, String> {
+        let value = match self.tokens.get(self.pos) {
+            Some(Token::Literal(literal)) => MarkerValue::Literal(literal.clone()),
+            Some(Token::Word(word)) if is_marker_variable(word) => {
+                MarkerValue::Variable(normalize_variable(word))
+            }
+            other => {
+                return Err(format!(
+                    "Invalid marker (expected a value, found {:?})",
+                    other
+                ));
+            }
+        };
+        self.pos += 1;
+        Ok(value)
+    }
+
+    fn operator(&mut self) -> Result<MarkerOperator, String> {
+        let operator = match self.tokens.get(self.pos) {
+            Some(Token::Word(w)) if w == "in" => MarkerOperator::In,
+            Some(Token::Word(w)) if w == "not" => {
+                self.pos += 1;
+                if !self.peek_word("in") {
+                    return Err("Invalid marker (expected 'in' after 'not')".to_string());
+                }
+                MarkerOperator::NotIn
+            }
+            Some(Token::Operator(op)) => {
+                let op = match op.as_str() {
+                    "~=" => Operator::Compatible,
+                    "==" => Operator::Equal,
+                    "!=" => Operator::NotEqual,
+                    "<=" => Operator::LessEqual,
+                    ">=" => Operator::GreaterEqual,
+                    "<" => Operator::Less,
+                    ">" => Operator::Greater,
+                    "===" => Operator::ArbitraryEqual,
+                    _ => return Err(format!("Invalid marker operator: {}", op)),
+                };
+                MarkerOperator::Compare(op)
+            }
+            other => {
+                return Err(format!(
+                    "Invalid marker (expected an operator, found {:?})",
+                    other
+                ));
+            }
+        };
+        self.pos += 1;
+        Ok(operator)
+    }
+}
+
+fn is_marker_variable(word: &str) -> bool {
+    word == "extra"
+        || MarkerEnvironment::default()
+            .get(&normalize_variable(word))
+            .is_some()
+}
+
+// Legacy dotted names from PEP 345, e.g. "os.name" and "python_implementation"
+fn normalize_variable(word: &str) -> String {
+    match word {
+        "os.name" => "os_name".to_string(),
+        "sys.platform" => "sys_platform".to_string(),
+        "platform.version" => "platform_version".to_string(),
+        "platform.machine" => "platform_machine".to_string(),
+        "platform.python_implementation" | "python_implementation" => {
+            "platform_python_implementation".to_string()
+        }
+        _ => word.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linux_311() -> MarkerEnvironment {
+        MarkerEnvironment {
+            implementation_name: "cpython".to_string(),
+            implementation_version: "3.11.7".to_string(),
+            os_name: "posix".to_string(),
+            platform_machine: "x86_64".to_string(),
+            platform_python_implementation: "CPython".to_string(),
+            platform_release: "6.5.0-21-generic".to_string(),
+            platform_system: "Linux".to_string(),
+            platform_version: "#21~22.04.1-Ubuntu SMP".to_string(),
+            python_full_version: "3.11.7".to_string(),
+            python_version: "3.11".to_string(),
+            sys_platform: "linux".to_string(),
+        }
+    }
+
+    fn holds(marker: &str) -> bool {
+        holds_with(marker, &[])
+    }
+
+    fn holds_with(marker: &str, extras: &[&str]) -> bool {
+        let extras: Vec<String> = extras.iter().map(|e| e.to_string()).collect();
+        marker
+            .parse::<Marker>()
+            .unwrap_or_else(|e| panic!("{}: {}", marker, e))
+            .evaluate(&linux_311(), &extras)
+    }
+
+    #[test]
+    fn compares_versions_as_versions() {
+        assert!(holds("python_version >= '3.8'"));
+        assert!(holds("python_version > '3.9'"));
+        assert!(!holds("python_version < '3.9'"));
+        assert!(holds("python_full_version == '3.11.*'"));
+        assert!(holds("python_version ~= '3.10'"));
+        assert!(holds("'3.12' > python_version"));
+        assert!(holds("python_version != '3.1'"));
+    }
+
+    #[test]
+    fn compares_other_values_as_strings() {
+        assert!(holds("sys_platform == 'linux'"));
+        assert!(holds("os_name != \"nt\""));
+        assert!(!holds("platform_system == 'Windows'"));
+        assert!(holds("'linux' in sys_platform"));
+        assert!(holds("platform_machine in 'x86_64 aarch64'"));
+        assert!(holds("platform_machine not in 'arm64 aarch64'"));
+        assert!(holds("platform_release >= '5'"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(holds(
+            "os_name == 'nt' and python_version < '3' or sys_platform == 'linux'"
+        ));
+        assert!(!holds(
+            "os_name == 'nt' and (python_version < '3' or sys_platform == 'linux')"
+        ));
+        assert!(holds("(os_name == 'posix')"));
+    }
+
+    #[test]
+    fn extras_match_requested_ones_by_normalized_name() {
+        assert!(!holds("extra == 'socks'"));
+        assert!(holds_with("extra == 'socks'", &["socks"]));
+        assert!(holds_with("extra == 'Test_Utils'", &["test-utils"]));
+        assert!(holds_with("extra != 'socks'", &["security"]));
+        assert!(holds_with(
+            "python_version >= '3.8' and extra == 'socks'",
+            &["socks"]
+        ));
+    }
+
+    #[test]
+    fn displays_markers() {
+        let marker: Marker = "python_version<'3.8' and (os_name=='nt' or extra == \"win\")"
+            .parse()
+            .unwrap();
+        let shown = marker.to_string();
+        assert_eq!(shown.parse::<Marker>().unwrap(), marker);
+    }
+
+    #[test]
+    fn rejects_invalid_markers() {
+        for bad in [
+            "",
+            "python_version",
+            "python_version >= ",
+            "unknown_var == '1'",
+            "os_name == 'nt' and",
+            "(os_name == 'nt'",
+            "os_name = 'nt'",
+            "os_name == 'nt",
+        ] {
+            assert!(bad.parse::<Marker>().is_err(), "{:?} parsed", bad);
+        }
+    }
+}
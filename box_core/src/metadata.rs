@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::archive::{ArchiveFormat, open_tar};
+
+/// The fields of a METADATA / PKG-INFO file (core metadata spec) that boxpkg cares about.
+#[derive(Debug, Clone, Default)]
+pub struct CoreMetadata {
+    pub metadata_version: String,
+    pub name: String,
+    pub version: String,
+    pub requires_dist: Vec<String>,
+    pub requires_python: Option<String>,
+    pub provides_extra: Vec<String>,
+    pub dynamic: Vec<String>,
+}
+
+impl CoreMetadata {
+    pub fn parse(text: &str) -> CoreMetadata {
+        let mut metadata = CoreMetadata::default();
+
+        for line in text.lines() {
+            // Headers end at the first blank line, the rest is the description body
+            if line.trim().is_empty() {
+                break;
+            }
+            // Continuation lines only ever extend free-text fields we don't keep
+            if line.starts_with([' ', '\t']) {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "metadata-version" => metadata.metadata_version = value,
+                "name" => metadata.name = value,
+                "version" => metadata.version = value,
+                "requires-dist" => metadata.requires_dist.push(value),
+                "requires-python" => metadata.requires_python = Some(value),
+                "provides-extra" => metadata.provides_extra.push(value),
+                "dynamic" => metadata.dynamic.push(value.to_ascii_lowercase()),
+                _ => {}
+            }
+        }
+
+        metadata
+    }
+
+    /// Whether `Requires-Dist` can be trusted without building. Only metadata 2.2+
+    /// (PEP 643) promises that non-dynamic fields in an sdist's PKG-INFO are final.
+    pub fn has_static_requirements(&self) -> bool {
+        let mut parts = self.metadata_version.split('.');
+        let major: u32 = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+        let minor: u32 = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+
+        (major, minor) >= (2, 2) && !self.dynamic.iter().any(|field| field == "requires-dist")
+    }
+}
+
+/// Reads `*.dist-info/METADATA` out of a wheel.
+pub fn read_wheel_metadata(wheel_path: &Path) -> Result<CoreMetadata, Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(File::open(wheel_path)?)?;
+
+    let metadata_name = archive
+        .file_names()
+        .find(|name| name.ends_with(".dist-info/METADATA") && name.matches('/').count() == 1)
+        .map(str::to_string)
+        .ok_or_else(|| format!("No METADATA found in {}", wheel_path.display()))?;
+
+    let mut text = String::new();
+    archive.by_name(&metadata_name)?.read_to_string(&mut text)?;
+
+    Ok(CoreMetadata::parse(&text))
+}
+
+/// Reads the top-level `PKG-INFO` out of a zip or tarball sdist.
+pub fn read_sdist_metadata(sdist_path: &Path) -> Result<CoreMetadata, Box<dyn std::error::Error>> {
+    if ArchiveFormat::detect(sdist_path)? == Some(ArchiveFormat::Zip) {
+        let mut archive = zip::ZipArchive::new(File::open(sdist_path)?)?;
+        let pkg_info = archive
+            .file_names()
+            .find(|name| name.ends_with("/PKG-INFO") && name.matches('/').count() == 1)
+            .map(str::to_string)
+            .ok_or_else(|| format!("No PKG-INFO found in {}", sdist_path.display()))?;
+
+        let mut text = String::new();
+        archive.by_name(&pkg_info)?.read_to_string(&mut text)?;
+        return Ok(CoreMetadata::parse(&text));
+    }
+
+    let mut archive = open_tar(sdist_path)?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let components: Vec<_> = path.components().collect();
+
+        // "{name}-{version}/PKG-INFO", not the copy inside *.egg-info/
+        if components.len() == 2 && path.file_name().is_some_and(|f| f == "PKG-INFO") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return Ok(CoreMetadata::parse(&text));
+        }
+    }
+
+    Err(format!("No PKG-INFO found in {}", sdist_path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::test_support::TempDir;
+
+    const PKG_INFO: &str = "Metadata-Version: 2.1
+Name: demo
+Version: 1.0
+Summary: A demo
+License: Permission is hereby granted,
+        Requires-Dist: not-a-field
+\tProvides-Extra: not-one-either
+Requires-Python: >=3.8
+Requires-Dist: six>=1.0
+requires-dist: colorama; sys_platform == \"win32\"
+Provides-Extra: cli
+Requires-Dist: click; extra == \"cli\"
+Dynamic: Requires-Dist
+
+Requires-Dist: in-the-description
+";
+
+    #[test]
+    fn parses_repeated_fields_and_skips_continuations() {
+        let metadata = CoreMetadata::parse(PKG_INFO);
+        assert_eq!(metadata.metadata_version, "2.1");
+        assert_eq!(metadata.name, "demo");
+        assert_eq!(metadata.version, "1.0");
+        assert_eq!(metadata.requires_python.as_deref(), Some(">=3.8"));
+        // Lines indented under License belong to it, and the body isn't headers
+        assert_eq!(
+            metadata.requires_dist,
+            [
+                "six>=1.0",
+                "colorama; sys_platform == \"win32\"",
+                "click; extra == \"cli\""
+            ]
+        );
+        assert_eq!(metadata.provides_extra, ["cli"]);
+        assert_eq!(metadata.dynamic, ["requires-dist"]);
+    }
+
+    #[test]
+    fn only_metadata_2_2_without_dynamic_requirements_is_static() {
+        let with = |headers: &str| CoreMetadata::parse(&format!("{}\nName: demo\n", headers));
+        assert!(with("Metadata-Version: 2.2").has_static_requirements());
+        assert!(with("Metadata-Version: 2.4\nDynamic: License").has_static_requirements());
+        assert!(!with("Metadata-Version: 2.1").has_static_requirements());
+        assert!(!with("Metadata-Version: 1.2").has_static_requirements());
+        assert!(!with("Name: no-version").has_static_requirements());
+        assert!(!with("Metadata-Version: 2.3\nDynamic: requires-dist").has_static_requirements());
+    }
+
+    #[test]
+    fn reads_pkg_info_from_the_top_of_an_sdist() {
+        let dir = TempDir::new("sdist-metadata");
+        let files = [
+            (
+                "demo-1.0/demo.egg-info/PKG-INFO",
+                "Metadata-Version: 2.2\nName: wrong\n",
+            ),
+            ("demo-1.0/PKG-INFO", PKG_INFO),
+        ];
+
+        let tarball = dir.0.join("demo-1.0.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&tarball).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(gz);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let zipped = dir.0.join("demo-1.0.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zipped).unwrap());
+        for (path, contents) in files {
+            zip.start_file(path, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        for sdist in [tarball, zipped] {
+            let metadata = read_sdist_metadata(&sdist).unwrap();
+            assert_eq!(metadata.name, "demo", "{}", sdist.display());
+            assert_eq!(metadata.requires_dist.len(), 3);
+            // Metadata 2.1 doesn't promise these are the built wheel's requirements
+            assert!(!metadata.has_static_requirements());
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::index::{
+    BinaryPolicy, DistributionFile, DistributionKind, IndexClient, is_usable, normalize_name,
+};
+use crate::metadata::{CoreMetadata, read_sdist_metadata, read_wheel_metadata};
+use crate::requirement::Requirement;
+use crate::resolver::DependencyProvider;
+use crate::tags::{Tags, WheelFilename};
+use crate::version::Version;
+use crate::{build_sdist, download_artifact};
+
+/// Answers the resolver's questions from a simple index, reading each
+/// candidate's Requires-Dist from the cheapest source available.
+pub struct IndexDependencyProvider {
+    index: IndexClient,
+    work_dir: PathBuf,
+    python_version: Option<Version>,
+    tags: Tags,
+    policy: BinaryPolicy,
+    files: HashMap<String, Vec<DistributionFile>>,
+    built_wheels: HashMap<(String, Version), PathBuf>,
+    preferences: HashMap<String, Version>,
+    pinned: HashMap<String, (Version, Vec<Requirement>)>,
+}
+
+impl IndexDependencyProvider {
+    /// Downloads and metadata builds happen under `work_dir`. Only versions with a
+    /// wheel compatible with `tags`, or an sdist, that `policy` allows are offered.
+    pub fn new(
+        index: IndexClient,
+        work_dir: &Path,
+        python_version: &str,
+        tags: Tags,
+        policy: BinaryPolicy,
+    ) -> Self {
+        IndexDependencyProvider {
+            index,
+            work_dir: work_dir.to_path_buf(),
+            python_version: python_version.parse().ok(),
+            tags,
+            policy,
+            files: HashMap::new(),
+            built_wheels: HashMap::new(),
+            preferences: HashMap::new(),
+            pinned: HashMap::new(),
+        }
+    }
+
+    /// Have the resolver keep these versions (e.g. from box.lock) where the
+    /// requirements still allow them, instead of picking the newest.
+    pub fn prefer(&mut self, package: &str, version: Version) {
+        self.preferences.insert(normalize_name(package), version);
+    }
+
+    /// Offer only this version of `package`, with these requirements, instead
+    /// of asking the index, e.g. for a dependency on a path, git repo or url.
+    pub fn pin(&mut self, package: &str, version: Version, requirements: Vec<Requirement>) {
+        self.pinned
+            .insert(normalize_name(package), (version, requirements));
+    }
+
+    pub fn files(&mut self, package: &str) -> Result<&[DistributionFile], String> {
+        let package = normalize_name(package);
+        if !self.files.contains_key(&package) {
+            let files = self.index.list_files(&package).map_err(|e| e.to_string())?;
+            self.files.insert(package.clone(), files);
+        }
+        Ok(&self.files[&package])
+    }
+
+    /// The wheel built from the sdist, if it already had to be built to read
+    /// its metadata, so it needn't be rebuilt.
+    pub fn built_wheel(&self, package: &str, version: &Version) -> Option<&Path> {
+        self.built_wheels
+            .get(&(normalize_name(package), version.clone()))
+            .map(PathBuf::as_path)
+    }
+
+    fn metadata(
+        &mut self,
+        package: &str,
+        version: &Version,
+    ) -> Result<CoreMetadata, Box<dyn std::error::Error>> {
+        let candidates: Vec<DistributionFile> = self
+            .files(package)?
+            .iter()
+            .filter(|f| f.version == *version && !f.yanked)
+            .cloned()
+            .collect();
+
+        // PEP 658 metadata is a single small download
+        if let Some(file) = candidates.iter().find(|f| f.core_metadata)
+            && let Ok(metadata) = self.index.fetch_metadata(file)
+        {
+            return Ok(metadata);
+        }
+
+        // Any wheel's METADATA will do, but one we'd install anyway saves a download later
+        let mut wheels: Vec<&DistributionFile> = candidates
+            .iter()
+            .filter(|f| f.kind == DistributionKind::Wheel)
+            .collect();
+        wheels.sort_by_key(|f| {
+            f.filename
+                .parse::<WheelFilename>()
+                .ok()
+                .and_then(|wheel| self.tags.priority(&wheel))
+                .unwrap_or(usize::MAX)
+        });
+        if let Some(wheel) = wheels.first() {
+            let wheel_path = download_artifact(wheel, &self.work_dir)?;
+            return read_wheel_metadata(&wheel_path);
+        }
+
+        let sdist = candidates
+            .iter()
+            .find(|f| f.kind == DistributionKind::Sdist)
+            .ok_or_else(|| format!("No usable distribution of {} {}", package, version))?;
+        let sdist_path = download_artifact(sdist, &self.work_dir)?;
+
+        if let Ok(metadata) = read_sdist_metadata(&sdist_path)
+            && metadata.has_static_requirements()
+        {
+            return Ok(metadata);
+        }
+
+        // Older sdists only know their requirements once built
+        println!("Building {} {} to read its metadata", package, version);
+        let wheel_path = build_sdist(
+            &sdist_path,
+            &self.work_dir.join(package),
+            self.index.url().as_str(),
+        )?;
+        let metadata = read_wheel_metadata(&wheel_path)?;
+        self.built_wheels
+            .insert((normalize_name(package), version.clone()), wheel_path);
+
+        Ok(metadata)
+    }
+}
+
+impl DependencyProvider for IndexDependencyProvider {
+    fn available_versions(&mut self, package: &str) -> Result<Vec<Version>, String> {
+        if let Some((version, _)) = self.pinned.get(&normalize_name(package)) {
+            return Ok(vec![version.clone()]);
+        }
+        self.files(package)?;
+        let mut versions: Vec<Version> = self.files[&normalize_name(package)]
+            .iter()
+            .filter(|f| {
+                is_usable(
+                    f,
+                    package,
+                    &self.tags,
+                    self.python_version.as_ref(),
+                    &self.policy,
+                )
+            })
+            .map(|f| f.version.clone())
+            .collect();
+
+        versions.sort();
+        versions.dedup();
+        Ok(versions)
+    }
+
+    fn preferred_version(&self, package: &str) -> Option<Version> {
+        self.preferences.get(&normalize_name(package)).cloned()
+    }
+
+    fn dependencies(
+        &mut self,
+        package: &str,
+        version: &Version,
+    ) -> Result<Vec<Requirement>, String> {
+        if let Some((_, requirements)) = self.pinned.get(&normalize_name(package)) {
+            return Ok(requirements.clone());
+        }
+        let metadata = self.metadata(package, version).map_err(|e| e.to_string())?;
+
+        let requirements: Vec<Requirement> = metadata
+            .requires_dist
+            .iter()
+            .map(|requires_dist| requires_dist.parse())
+            .collect::<Result<_, _>>()?;
+        // Only the project itself may say where a package comes from
+        if let Some(requirement) = requirements.iter().find(|r| r.url.is_some()) {
+            return Err(format!(
+                "{} {} requires {}, and direct URL requirements aren't supported in package metadata",
+                package, version, requirement
+            ));
+        }
+        Ok(requirements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use crate::tags::Tag;
+    use crate::test_support::TempDir;
+
+    // py3 wheels are preferred to py2 ones
+    fn provider(index: &Path, work_dir: &Path) -> IndexDependencyProvider {
+        IndexDependencyProvider::new(
+            IndexClient::new(index.to_str().unwrap()).unwrap(),
+            work_dir,
+            "3.11.4",
+            Tags::new(vec![
+                Tag::new("py3", "none", "any"),
+                Tag::new("py2", "none", "any"),
+            ]),
+            BinaryPolicy::default(),
+        )
+    }
+
+    // The PEP 503/691 directory index checked into the repo
+    fn fixture_index() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/index")
+    }
+
+    fn requirements(
+        provider: &mut IndexDependencyProvider,
+        package: &str,
+        version: &str,
+    ) -> Vec<String> {
+        provider
+            .dependencies(package, &version.parse().unwrap())
+            .unwrap()
+            .iter()
+            .map(Requirement::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn reads_pep_658_metadata_without_downloading() {
+        let dir = TempDir::new("provider-pep658");
+        let mut provider = provider(&fixture_index(), &dir.0);
+        // demo-1.0.tar.gz and its wheel aren't there to download, only the metadata
+        assert_eq!(
+            requirements(&mut provider, "demo", "1.0"),
+            ["six>=1.0", "colorama; sys_platform == \"win32\""]
+        );
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn yanked_files_are_never_offered() {
+        let dir = TempDir::new("provider-yanked");
+        let mut provider = provider(&fixture_index(), &dir.0);
+        let versions: Vec<String> = provider
+            .available_versions("Demo")
+            .unwrap()
+            .iter()
+            .map(Version::to_string)
+            .collect();
+        // 2.0 and 2.1 are yanked
+        assert_eq!(versions, ["1.0", "1.1", "3.0"]);
+
+        let error = provider
+            .dependencies("demo", &"2.0".parse().unwrap())
+            .unwrap_err();
+        assert_eq!(error, "No usable distribution of demo 2.0");
+    }
+
+    #[test]
+    fn pinned_packages_skip_the_index_and_preferences_are_kept() {
+        let dir = TempDir::new("provider-pin");
+        // An index with no projects at all
+        fs::create_dir_all(dir.0.join("index")).unwrap();
+        let mut provider = provider(&dir.0.join("index"), &dir.0);
+        let version: Version = "0.3.0".parse().unwrap();
+        provider.pin(
+            "Local_Pkg",
+            version.clone(),
+            vec!["attrs>=23".parse().unwrap()],
+        );
+
+        assert_eq!(provider.available_versions("local-pkg").unwrap(), [version]);
+        assert_eq!(
+            requirements(&mut provider, "local.pkg", "0.3.0"),
+            ["attrs>=23"]
+        );
+        assert!(provider.available_versions("attrs").is_err());
+
+        assert_eq!(provider.preferred_version("attrs"), None);
+        provider.prefer("Attrs", "23.1".parse().unwrap());
+        assert_eq!(
+            provider.preferred_version("attrs"),
+            Some("23.1".parse().unwrap())
+        );
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar_gz(path: &Path, files: &[(&str, &str)]) {
+        let gz = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(gz);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn metadata(version: &str, requires_dist: &str) -> String {
+        format!(
+            "Metadata-Version: {}\nName: pkg\nVersion: 1.0\nRequires-Dist: {}\n",
+            version, requires_dist
+        )
+    }
+
+    // Builds pkg 3.0 with a Requires-Dist its PKG-INFO doesn't have
+    const BACKEND: &str = r#"
+import os, zipfile
+
+def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):
+    name = "pkg-3.0-py3-none-any.whl"
+    with zipfile.ZipFile(os.path.join(wheel_directory, name), "w") as wheel:
+        wheel.writestr("pkg-3.0.dist-info/METADATA", "Metadata-Version: 2.1\nName: pkg\nVersion: 3.0\nRequires-Dist: from-build\n")
+    return name
+"#;
+
+    #[test]
+    fn falls_back_from_wheels_to_pkg_info_to_a_build() {
+        let dir = TempDir::new("provider-fallback");
+        let index = dir.0.join("index");
+        let files = index.join("pkg");
+        fs::create_dir_all(&files).unwrap();
+
+        // 1.0: the best wheel's METADATA rather than the other wheel's or the sdist's
+        write_zip(
+            &files.join("pkg-1.0-py2-none-any.whl"),
+            &[("pkg-1.0.dist-info/METADATA", &metadata("2.1", "from-py2"))],
+        );
+        write_zip(
+            &files.join("pkg-1.0-py3-none-any.whl"),
+            &[("pkg-1.0.dist-info/METADATA", &metadata("2.1", "from-py3"))],
+        );
+        write_tar_gz(
+            &files.join("pkg-1.0.tar.gz"),
+            &[("pkg-1.0/PKG-INFO", &metadata("2.2", "from-sdist"))],
+        );
+        // 2.0: PKG-INFO that metadata 2.2 makes final, so nothing is built
+        write_tar_gz(
+            &files.join("pkg-2.0.tar.gz"),
+            &[("pkg-2.0/PKG-INFO", &metadata("2.2", "from-pkg-info"))],
+        );
+        // 3.0: PKG-INFO older than 2.2 can't be trusted, so it's built
+        write_tar_gz(
+            &files.join("pkg-3.0.tar.gz"),
+            &[
+                ("pkg-3.0/PKG-INFO", &metadata("2.1", "from-pkg-info")),
+                (
+                    "pkg-3.0/pyproject.toml",
+                    "[build-system]\nrequires = []\nbuild-backend = \"in_tree\"\nbackend-path = [\"backend\"]\n",
+                ),
+                ("pkg-3.0/backend/in_tree.py", BACKEND),
+            ],
+        );
+        // Sorted, so the py2 wheel comes first on the page
+        let mut names: Vec<String> = fs::read_dir(&files)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let links: String = names
+            .iter()
+            .map(|name| format!("<a href=\"{0}\">{0}</a>\n", name))
+            .collect();
+        fs::write(files.join("index.html"), links).unwrap();
+
+        let work_dir = dir.0.join("work");
+        fs::create_dir_all(&work_dir).unwrap();
+        let mut provider = provider(&index, &work_dir);
+        assert_eq!(requirements(&mut provider, "pkg", "1.0"), ["from-py3"]);
+        assert_eq!(requirements(&mut provider, "pkg", "2.0"), ["from-pkg-info"]);
+        assert!(
+            provider
+                .built_wheel("pkg", &"2.0".parse().unwrap())
+                .is_none()
+        );
+
+        assert_eq!(requirements(&mut provider, "pkg", "3.0"), ["from-build"]);
+        let wheel = provider
+            .built_wheel("PKG", &"3.0".parse().unwrap())
+            .unwrap();
+        assert!(
+            wheel.ends_with("pkg-3.0-py3-none-any.whl"),
+            "{}",
+            wheel.display()
+        );
+    }
+
+    #[test]
+    fn direct_references_in_metadata_are_refused() {
+        let dir = TempDir::new("provider-direct-url");
+        let files = dir.0.join("index").join("pkg");
+        fs::create_dir_all(&files).unwrap();
+        let requires_dist = "dep @ https://example.com/dep-1.0.tar.gz";
+        write_zip(
+            &files.join("pkg-1.0-py3-none-any.whl"),
+            &[(
+                "pkg-1.0.dist-info/METADATA",
+                &metadata("2.1", requires_dist),
+            )],
+        );
+        fs::write(
+            files.join("index.html"),
+            "<a href=\"pkg-1.0-py3-none-any.whl\">pkg-1.0-py3-none-any.whl</a>\n",
+        )
+        .unwrap();
+
+        let mut provider = provider(&dir.0.join("index"), &dir.0);
+        let error = provider
+            .dependencies("pkg", &"1.0".parse().unwrap())
+            .unwrap_err();
+        assert!(error.contains(requires_dist), "{}", error);
+        assert!(
+            error.contains("direct URL requirements aren't supported"),
+            "{}",
+            error
+        );
+    }
+}
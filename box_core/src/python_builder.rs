@@ -1,134 +1,927 @@
-use std::env;
-use std::path::Path;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub fn setup_python_env(project_path: &Path) -> Result<(), String> {
-    // Define the path to the Python virtual environment
-    let venv_dir = project_path.join("venv");
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::index::index_base_url;
+
+// What pip assumes for projects that predate pyproject.toml (PEP 517's legacy fallback)
+const LEGACY_REQUIRES: &str = "setuptools>=40.8.0";
+const LEGACY_BACKEND: &str = "setuptools.build_meta:__legacy__";
+
+// Calls one PEP 517 hook in the build environment, like pyproject_hooks' _in_process.
+// Backends print freely on stdout, so the hook's return value goes to a json file.
+const HOOK_RUNNER: &str = r#"
+import importlib, json, os, sys
+
+hook, backend_spec, backend_path, output_dir, result_file = sys.argv[1:6]
 
-    // Check if the virtual environment already exists
-    if !&venv_dir.exists() {
-        // Create the virtual environment if it doesn't exist
-        println!("Creating virtual environment...");
-        let status = Command::new("python")
-            .arg("-m")
-            .arg("venv")
-            .arg(&venv_dir)
-            .status()
-            .map_err(|e| format!("Failed to create virtual environment: {}", e))?;
-
-        if !status.success() {
-            return Err("Failed to create virtual environment".to_string());
+backend_path = json.loads(backend_path)
+if backend_path:
+    sys.path[:0] = [os.path.abspath(path) for path in backend_path]
+
+module_name, _, object_path = backend_spec.partition(":")
+backend = importlib.import_module(module_name.strip())
+for attribute in filter(None, object_path.strip().split(".")):
+    backend = getattr(backend, attribute)
+
+if hook in ("get_requires_for_build_wheel", "get_requires_for_build_editable"):
+    # Optional hook, no extra requirements when the backend doesn't define it
+    get_requires = getattr(backend, hook, None)
+    result = get_requires({}) if get_requires else []
+elif hook == "build_wheel":
+    result = backend.build_wheel(output_dir, {}, None)
+elif hook == "build_editable":
+    # Optional too (PEP 660), None tells boxpkg to fall back to a .pth file
+    build_editable = getattr(backend, hook, None)
+    result = build_editable(output_dir, {}, None) if build_editable else None
+else:
+    sys.exit("unknown hook " + hook)
+
+with open(result_file, "w") as f:
+    json.dump({"return": result}, f)
+"#;
+
+/// Why building a wheel failed.
+#[derive(Debug)]
+pub enum BuildError {
+    ProjectNotFound(PathBuf),
+    InvalidSdist(PathBuf),
+    InvalidPyProject {
+        path: PathBuf,
+        message: String,
+    },
+    /// Creating the (isolated) virtual environment failed
+    Environment(String),
+    /// Installing these build requirements failed
+    Requirements(Vec<String>),
+    /// A PEP 517 hook failed or returned something unusable
+    Hook {
+        hook: String,
+        backend: String,
+        message: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ProjectNotFound(path) => {
+                write!(f, "Directory not found: {}", path.display())
+            }
+            BuildError::InvalidSdist(path) => write!(f, "Invalid sdist: {}", path.display()),
+            BuildError::InvalidPyProject { path, message } => {
+                write!(f, "Invalid {}: {}", path.display(), message)
+            }
+            BuildError::Environment(message) => {
+                write!(f, "Failed to create virtual environment: {}", message)
+            }
+            BuildError::Requirements(requirements) => write!(
+                f,
+                "Failed to install build requirements: {}",
+                requirements.join(", ")
+            ),
+            BuildError::Hook {
+                hook,
+                backend,
+                message,
+            } => write!(f, "{} ({}) failed: {}", hook, backend, message),
+            BuildError::Io(e) => write!(f, "{}", e),
         }
     }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    // Activate the virtual environment (platform-specific)
-    println!("Activating virtual environment...");
-    let activate_script = if cfg!(target_os = "windows") {
-        &venv_dir.join("Scripts").join("activate.bat")
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        BuildError::Io(e)
+    }
+}
+
+/// The `[build-system]` table of a pyproject.toml (PEP 518/517).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BuildSystem {
+    pub requires: Vec<String>,
+    #[serde(rename = "build-backend", default = "legacy_backend")]
+    pub build_backend: String,
+    #[serde(rename = "backend-path", default)]
+    pub backend_path: Vec<String>,
+}
+
+fn legacy_backend() -> String {
+    LEGACY_BACKEND.to_string()
+}
+
+#[derive(Deserialize)]
+struct PyProject {
+    #[serde(rename = "build-system")]
+    build_system: Option<BuildSystem>,
+}
+
+/// Reads `[build-system]` from the project's pyproject.toml, falling back to
+/// setuptools' legacy backend when there is no pyproject.toml or no table.
+pub fn read_build_system(project_path: &Path) -> Result<BuildSystem, BuildError> {
+    let pyproject_path = project_path.join("pyproject.toml");
+
+    let build_system = if pyproject_path.exists() {
+        let text = fs::read_to_string(&pyproject_path)?;
+        let pyproject: PyProject =
+            toml::from_str(&text).map_err(|e| BuildError::InvalidPyProject {
+                path: pyproject_path.clone(),
+                message: e.to_string(),
+            })?;
+        pyproject.build_system
     } else {
-        &venv_dir.join("bin").join("activate")
+        None
     };
 
-    // Use `source` for Unix or `activate.bat` for Windows
-    let activate_status = Command::new("sh")
-        .arg("-c")
-        .arg(format!("source {}", &activate_script.display()))
-        .status()
-        .map_err(|e| format!("Failed to activate environment: {}", e))?;
+    Ok(build_system.unwrap_or_else(|| BuildSystem {
+        requires: vec![LEGACY_REQUIRES.to_string()],
+        build_backend: legacy_backend(),
+        backend_path: Vec::new(),
+    }))
+}
 
-    if !activate_status.success() {
-        return Err("Failed to activate virtual environment".to_string());
+/// Whether the project says how it's built: a [build-system] table in its
+/// pyproject.toml, or a setup.py for setuptools' legacy backend.
+pub fn has_build_system(project_path: &Path) -> bool {
+    if project_path.join("setup.py").exists() {
+        return true;
     }
+    fs::read_to_string(project_path.join("pyproject.toml"))
+        .ok()
+        .and_then(|text| toml::from_str::<PyProject>(&text).ok())
+        .is_some_and(|pyproject| pyproject.build_system.is_some())
+}
 
-    let mut python_command = Command::new("python");
+/// The interpreter boxpkg creates environments with.
+fn base_python() -> &'static str {
+    let python3 = Command::new("python3").arg("--version").output();
+    match python3 {
+        Ok(output) if output.status.success() => "python3",
+        _ => "python",
+    }
+}
 
+/// The python executable inside a virtual environment.
+pub fn venv_python(venv_dir: &Path) -> PathBuf {
     if cfg!(target_os = "windows") {
-        python_command
-            .arg("-m")
-            .arg("pip")
-            .arg("install")
-            .arg("setuptools")
-            .arg("wheel")
-            .arg("build")
-            // Set the environment variable for the virtual environment
-            .env("VIRTUAL_ENV", venv_dir.display().to_string()) // This is the path to the venv
-            // Update PATH to include the virtual environment's Scripts directory
-            .env(
-                "PATH",
-                format!(
-                    "{}/Scripts;{}",
-                    &venv_dir.display().to_string(),
-                    std::env::var("PATH").expect("string")
-                ),
-            );
+        venv_dir.join("Scripts").join("python.exe")
     } else {
-        python_command
-            .arg("-m")
-            .arg("pip")
-            .arg("install")
-            .arg("setuptools")
-            .arg("wheel")
-            .arg("build")
-            // Set the environment variable for the virtual environment
-            .env("VIRTUAL_ENV", &venv_dir.display().to_string()) // This is the path to the venv
-            // Update PATH to include the virtual environment's binary directory
-            .env(
-                "PATH",
-                format!(
-                    "{}/bin:{}",
-                    &venv_dir.display().to_string(),
-                    std::env::var("PATH").expect("string")
-                ),
-            ); // Unix-based systems
-    }
-
-    let install_status = python_command
+        venv_dir.join("bin").join("python")
+    }
+}
+
+fn create_venv(venv_dir: &Path, with_pip: bool) -> Result<(), BuildError> {
+    println!("Creating virtual environment {}...", venv_dir.display());
+    let mut command = Command::new(base_python());
+    command.arg("-m").arg("venv");
+    if !with_pip {
+        command.arg("--without-pip");
+    }
+
+    let status = command
+        .arg(venv_dir)
         .status()
-        .map_err(|e| format!("Failed to install dependencies: {}", e))?;
+        .map_err(|e| BuildError::Environment(e.to_string()))?;
 
-    if !install_status.success() {
-        return Err("Failed to install dependencies in the virtual environment".to_string());
+    if !status.success() {
+        return Err(BuildError::Environment(format!(
+            "python -m venv {} exited with {}",
+            venv_dir.display(),
+            status
+        )));
     }
+    Ok(())
+}
+
+/// Creates the project's virtual environment in `project_path`/venv, if it isn't there yet.
+/// It gets no pip; boxpkg installs wheels itself.
+pub fn setup_python_env(project_path: &Path) -> Result<(), BuildError> {
+    let venv_dir = project_path.join("venv");
 
+    if !venv_dir.exists() {
+        create_venv(&venv_dir, false)?;
+    }
     Ok(())
 }
 
-pub fn build_wheel(project_path: &Path) -> Result<(), String> {
-    let dis = env::current_dir();
+// Installs into an environment that has no pip of its own, using the base interpreter's,
+// from the index the project's packages come from
+fn install_requirements(
+    venv_dir: &Path,
+    requirements: &[String],
+    index_url: &str,
+) -> Result<(), BuildError> {
+    if requirements.is_empty() {
+        return Ok(());
+    }
+    println!("Installing build requirements: {}", requirements.join(", "));
+
+    let status = Command::new(base_python())
+        .args(["-m", "pip", "--python"])
+        .arg(venv_python(venv_dir))
+        .args(["install", "--disable-pip-version-check"])
+        .args(pip_index_args(index_url))
+        .arg("--")
+        .args(requirements)
+        .status()?;
+
+    if !status.success() {
+        return Err(BuildError::Requirements(requirements.to_vec()));
+    }
+    Ok(())
+}
+
+// pip takes http(s) and file:// indexes, but not a plain directory path
+fn pip_index_args(index_url: &str) -> [String; 2] {
+    let url = index_base_url(index_url)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| index_url.to_string());
+    ["--index-url".to_string(), url]
+}
+
+fn call_hook(
+    project_path: &Path,
+    env_dir: &Path,
+    build_system: &BuildSystem,
+    hook: &str,
+    output_dir: &Path,
+) -> Result<serde_json::Value, BuildError> {
+    let hook_error = |message: String| BuildError::Hook {
+        hook: hook.to_string(),
+        backend: build_system.build_backend.clone(),
+        message,
+    };
+    let result_file = env_dir.join(format!("{}.json", hook));
+    let backend_path = serde_json::to_string(&build_system.backend_path).unwrap_or_default();
+
+    // The hook runs in the project directory; ours is never changed, so
+    // builds on other threads aren't affected
+    let status = Command::new(venv_python(env_dir))
+        .arg("-c")
+        .arg(HOOK_RUNNER)
+        .arg(hook)
+        .arg(&build_system.build_backend)
+        .arg(backend_path)
+        .arg(output_dir)
+        .arg(&result_file)
+        .current_dir(project_path)
+        .status()?;
+
+    if !status.success() {
+        return Err(hook_error(format!(
+            "exited with {} in {}",
+            status,
+            project_path.display()
+        )));
+    }
+
+    let text =
+        fs::read_to_string(&result_file).map_err(|e| hook_error(format!("no result: {}", e)))?;
+    let result: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| hook_error(format!("invalid result: {}", e)))?;
+    Ok(result["return"].clone())
+}
+
+/// Builds a wheel of the project in `project_path` through its PEP 517 backend,
+/// in a fresh environment at `env_dir` holding only the build requirements,
+/// which are installed from `index_url`. Returns the path of the wheel,
+/// written to `output_dir`.
+///
+/// Nothing process-wide is touched, so builds can run on several threads at
+/// once as long as each has its own `env_dir`.
+pub fn build_wheel(
+    project_path: &Path,
+    env_dir: &Path,
+    output_dir: &Path,
+    index_url: &str,
+) -> Result<PathBuf, BuildError> {
+    let wheel_path = build(project_path, env_dir, output_dir, index_url, "wheel")?;
+    Ok(wheel_path.expect("build_wheel is a required hook"))
+}
+
+/// Builds an editable wheel of the project in `project_path`, one that
+/// imports the project from where it is, through the backend's PEP 660
+/// build_editable hook. Backends without it get a wheel holding the
+/// project's metadata and a .pth file that puts its sources on sys.path.
+/// Otherwise like `build_wheel`.
+pub fn build_editable(
+    project_path: &Path,
+    env_dir: &Path,
+    output_dir: &Path,
+    index_url: &str,
+) -> Result<PathBuf, BuildError> {
+    if let Some(wheel_path) = build(project_path, env_dir, output_dir, index_url, "editable")? {
+        return Ok(wheel_path);
+    }
+
+    let project_path = project_path.canonicalize()?;
+    println!(
+        "No build_editable hook, installing {} through a .pth file",
+        project_path.display()
+    );
+    let base_dir = output_dir.join("base");
+    let wheel_path = build_wheel(&project_path, env_dir, &base_dir, index_url)?;
+    let editable_path = pth_wheel(&wheel_path, &project_path, output_dir)?;
+    fs::remove_dir_all(&base_dir)?;
+    Ok(editable_path)
+}
+
+// Runs get_requires_for_build_<target> and build_<target>. None when the
+// backend has no hook for an editable build.
+fn build(
+    project_path: &Path,
+    env_dir: &Path,
+    output_dir: &Path,
+    index_url: &str,
+    target: &str,
+) -> Result<Option<PathBuf>, BuildError> {
+    if !project_path.exists() {
+        return Err(BuildError::ProjectNotFound(project_path.to_path_buf()));
+    }
+    // Absolute, since the hooks run from inside the project
+    let project_path = project_path.canonicalize()?;
+
+    let build_system = read_build_system(&project_path)?;
+    println!(
+        "Building {} with {}",
+        project_path.display(),
+        build_system.build_backend
+    );
+
+    // Start from nothing so a build can't depend on what a previous one installed
+    if env_dir.exists() {
+        fs::remove_dir_all(env_dir)?;
+    }
+    create_venv(env_dir, false)?;
+    let env_dir = env_dir.canonicalize()?;
+    install_requirements(&env_dir, &build_system.requires, index_url)?;
+
+    fs::create_dir_all(output_dir)?;
+    let output_dir = output_dir.canonicalize()?;
 
-    match dis {
-        Ok(dis) => {
-            println!("current_dir! {}", dis.display());
-            // Ensure the project path exists
-            if !project_path.exists() {
-                return Err(format!("Directory not found: {}", project_path.display()));
+    let requires_hook = format!("get_requires_for_build_{}", target);
+    let extra_requires = call_hook(
+        &project_path,
+        &env_dir,
+        &build_system,
+        &requires_hook,
+        &output_dir,
+    )?;
+    let extra_requires: Vec<String> =
+        serde_json::from_value(extra_requires.clone()).map_err(|_| BuildError::Hook {
+            hook: requires_hook.clone(),
+            backend: build_system.build_backend.clone(),
+            message: format!(
+                "returned {} instead of a list of requirements",
+                extra_requires
+            ),
+        })?;
+    install_requirements(&env_dir, &extra_requires, index_url)?;
+
+    let build_hook = format!("build_{}", target);
+    let wheel_name = call_hook(
+        &project_path,
+        &env_dir,
+        &build_system,
+        &build_hook,
+        &output_dir,
+    )?;
+    let wheel_path = match wheel_name.as_str() {
+        Some(name) => output_dir.join(name),
+        None if wheel_name.is_null() && target == "editable" => return Ok(None),
+        None => {
+            return Err(BuildError::Hook {
+                hook: build_hook,
+                backend: build_system.build_backend.clone(),
+                message: format!("returned {} instead of a filename", wheel_name),
+            });
+        }
+    };
+    if !wheel_path.exists() {
+        return Err(BuildError::Hook {
+            hook: build_hook,
+            backend: build_system.build_backend.clone(),
+            message: format!("reported {} but it wasn't written", wheel_path.display()),
+        });
+    }
+
+    println!("Wheel build successful: {}", wheel_path.display());
+    Ok(Some(wheel_path))
+}
+
+// The .dist-info of `wheel_path` plus a .pth file pointing at the project's
+// sources (its src/ directory in a src layout), as a wheel in `output_dir`
+fn pth_wheel(
+    wheel_path: &Path,
+    project_path: &Path,
+    output_dir: &Path,
+) -> Result<PathBuf, BuildError> {
+    let invalid = |e: &dyn fmt::Display| BuildError::Hook {
+        hook: "build_wheel".to_string(),
+        backend: wheel_path.display().to_string(),
+        message: e.to_string(),
+    };
+    let mut archive = ZipArchive::new(File::open(wheel_path)?).map_err(|e| invalid(&e))?;
+
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| invalid(&e))?;
+        let name = entry.name().to_string();
+        let in_dist_info = name
+            .split_once('/')
+            .is_some_and(|(dir, _)| dir.ends_with(".dist-info"));
+        if !in_dist_info || entry.is_dir() || name.ends_with("/RECORD") {
+            continue;
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        files.push((name, contents));
+    }
+    let dist_info = files
+        .first()
+        .and_then(|(name, _)| name.split_once('/'))
+        .map(|(dir, _)| dir.to_string())
+        .ok_or_else(|| invalid(&"no .dist-info directory"))?;
+
+    let sources = project_path.join("src");
+    let sources = if sources.is_dir() {
+        sources
+    } else {
+        project_path.to_path_buf()
+    };
+    let distribution = dist_info.trim_end_matches(".dist-info");
+    files.push((
+        format!("__editable__.{}.pth", distribution),
+        format!("{}\n", sources.display()).into_bytes(),
+    ));
+
+    let mut record: String = files
+        .iter()
+        .map(|(name, contents)| {
+            format!(
+                "{},sha256={},{}\n",
+                name,
+                URL_SAFE_NO_PAD.encode(Sha256::digest(contents)),
+                contents.len()
+            )
+        })
+        .collect();
+    record.push_str(&format!("{}/RECORD,,\n", dist_info));
+    files.push((format!("{}/RECORD", dist_info), record.into_bytes()));
+
+    let editable_path = output_dir.join(wheel_path.file_name().unwrap_or_default());
+    let mut writer = ZipWriter::new(File::create(&editable_path)?);
+    for (name, contents) in &files {
+        writer
+            .start_file(name.as_str(), SimpleFileOptions::default())
+            .map_err(|e| invalid(&e))?;
+        writer.write_all(contents)?;
+    }
+    writer.finish().map_err(|e| invalid(&e))?;
+    Ok(editable_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::DEFAULT_INDEX_URL;
+    use crate::test_support::TempDir;
+
+    // An environment that is just the base interpreter, enough to run hooks in
+    fn hook_env(dir: &Path) -> PathBuf {
+        let output = Command::new(base_python())
+            .args(["-c", "import sys; print(sys.executable)"])
+            .output()
+            .unwrap();
+        let python = String::from_utf8(output.stdout).unwrap();
+        let env_dir = dir.join("env");
+        fs::create_dir_all(env_dir.join("bin")).unwrap();
+        std::os::unix::fs::symlink(python.trim(), venv_python(&env_dir)).unwrap();
+        env_dir
+    }
+
+    fn in_tree_backend(dir: &Path, backend: &str) -> BuildSystem {
+        fs::create_dir_all(dir.join("backend")).unwrap();
+        fs::write(dir.join("backend/in_tree.py"), backend).unwrap();
+        BuildSystem {
+            requires: Vec::new(),
+            build_backend: "in_tree".to_string(),
+            backend_path: vec!["backend".to_string()],
+        }
+    }
+
+    #[test]
+    fn reads_build_system() {
+        let dir = TempDir::new("read-build-system");
+        fs::write(
+            dir.0.join("pyproject.toml"),
+            r#"
+[project]
+name = "demo"
+
+[build-system]
+requires = ["flit_core>=3.2,<4"]
+build-backend = "backend:api"
+backend-path = ["tools"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_build_system(&dir.0).unwrap(),
+            BuildSystem {
+                requires: vec!["flit_core>=3.2,<4".to_string()],
+                build_backend: "backend:api".to_string(),
+                backend_path: vec!["tools".to_string()],
             }
+        );
+        assert!(has_build_system(&dir.0));
+    }
+
+    #[test]
+    fn falls_back_to_legacy_setuptools() {
+        let legacy = BuildSystem {
+            requires: vec![LEGACY_REQUIRES.to_string()],
+            build_backend: LEGACY_BACKEND.to_string(),
+            backend_path: Vec::new(),
+        };
+        let dir = TempDir::new("legacy-build-system");
 
-            // Change to the project directory
-            env::set_current_dir(project_path).map_err(|e| e.to_string())?;
+        // No pyproject.toml at all
+        fs::write(dir.0.join("setup.py"), "").unwrap();
+        assert_eq!(read_build_system(&dir.0).unwrap(), legacy);
+        assert!(has_build_system(&dir.0));
 
-            // Run the build command for the Python wheel
-            let status = Command::new("python")
-                .arg("-m")
-                .arg("build")
-                .status()
-                .map_err(|e| format!("Failed to run wheel build: {}", e))?;
+        // A pyproject.toml without [build-system]
+        fs::write(dir.0.join("pyproject.toml"), "[project]\nname = \"demo\"\n").unwrap();
+        assert_eq!(read_build_system(&dir.0).unwrap(), legacy);
 
-            if !status.success() {
-                return Err("Wheel build failed".to_string());
+        // [build-system] without build-backend keeps its requires
+        fs::write(
+            dir.0.join("pyproject.toml"),
+            "[build-system]\nrequires = [\"setuptools\", \"wheel\"]\n",
+        )
+        .unwrap();
+        let build_system = read_build_system(&dir.0).unwrap();
+        assert_eq!(build_system.requires, ["setuptools", "wheel"]);
+        assert_eq!(build_system.build_backend, LEGACY_BACKEND);
+
+        fs::remove_file(dir.0.join("setup.py")).unwrap();
+        fs::write(dir.0.join("pyproject.toml"), "[project]\nname = \"demo\"\n").unwrap();
+        assert!(!has_build_system(&dir.0));
+    }
+
+    #[test]
+    fn rejects_an_invalid_pyproject() {
+        let dir = TempDir::new("invalid-pyproject");
+        fs::write(
+            dir.0.join("pyproject.toml"),
+            "[build-system]\nrequires = 3\n",
+        )
+        .unwrap();
+        match read_build_system(&dir.0) {
+            Err(BuildError::InvalidPyProject { path, .. }) => {
+                assert_eq!(path, dir.0.join("pyproject.toml"))
             }
+            other => panic!("expected InvalidPyProject, got {:?}", other),
+        }
+    }
 
-            println!("Wheel build successful, check the 'dist/' directory.");
+    #[test]
+    fn build_requirements_come_from_the_index() {
+        assert_eq!(
+            pip_index_args("https://example.com/simple"),
+            ["--index-url", "https://example.com/simple/"]
+        );
+        let dir = TempDir::new("pip-index");
+        let [_, url] = pip_index_args(dir.0.to_str().unwrap());
+        assert!(url.starts_with("file:///") && url.ends_with('/'), "{}", url);
+    }
+
+    #[test]
+    fn hooks_return_their_result() {
+        let dir = TempDir::new("hook-result");
+        let env_dir = hook_env(&dir.0);
+        let build_system = in_tree_backend(
+            &dir.0,
+            "print('backends may print')\n\
+             def get_requires_for_build_wheel(config_settings=None):\n    return ['cython']\n\
+             def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):\n    \
+             return wheel_directory\n",
+        );
+        let call = |hook: &str| call_hook(&dir.0, &env_dir, &build_system, hook, Path::new("out"));
+
+        assert_eq!(
+            call("get_requires_for_build_wheel").unwrap(),
+            serde_json::json!(["cython"])
+        );
+        assert_eq!(call("build_wheel").unwrap(), serde_json::json!("out"));
+        // Optional hooks the backend doesn't have
+        assert_eq!(
+            call("get_requires_for_build_editable").unwrap(),
+            serde_json::json!([])
+        );
+        assert_eq!(call("build_editable").unwrap(), serde_json::Value::Null);
+    }
 
-            // Change to the project directory
-            println!("current_dir! {}", dis.display());
-            env::set_current_dir(dis).map_err(|e| e.to_string())?;
+    #[test]
+    fn hook_failures_are_errors() {
+        let dir = TempDir::new("hook-failure");
+        let env_dir = hook_env(&dir.0);
+        let build_system = in_tree_backend(
+            &dir.0,
+            "def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):\n    \
+             raise RuntimeError('no compiler')\n",
+        );
+
+        let error = call_hook(&dir.0, &env_dir, &build_system, "build_wheel", &dir.0).unwrap_err();
+        match error {
+            BuildError::Hook {
+                hook,
+                backend,
+                message,
+            } => {
+                assert_eq!(hook, "build_wheel");
+                assert_eq!(backend, "in_tree");
+                assert!(message.starts_with("exited with"), "{}", message);
+            }
+            other => panic!("expected a hook error, got {:?}", other),
         }
-        Err(e) => {
-            println!("Failed to check current_dir: {}", e);
+        assert!(call_hook(&dir.0, &env_dir, &build_system, "prepare_metadata", &dir.0).is_err());
+    }
+
+    // Builds demo 1.0, a wheel holding demo/__init__.py
+    const WHEEL_BACKEND: &str = r#"
+import base64, hashlib, os, zipfile
+
+def _record_hash(data):
+    digest = base64.urlsafe_b64encode(hashlib.sha256(data).digest()).rstrip(b"=")
+    return "sha256=" + digest.decode()
+
+def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):
+    name = "demo-1.0-py3-none-any.whl"
+    files = {
+        "demo/__init__.py": b"",
+        "demo-1.0.dist-info/METADATA": b"Metadata-Version: 2.1\nName: demo\nVersion: 1.0\n",
+        "demo-1.0.dist-info/WHEEL": b"Wheel-Version: 1.0\nRoot-Is-Purelib: true\nTag: py3-none-any\n",
+    }
+    record = "".join("%s,%s,%d\n" % (path, _record_hash(data), len(data)) for path, data in files.items())
+    files["demo-1.0.dist-info/RECORD"] = (record + "demo-1.0.dist-info/RECORD,,\n").encode()
+    with zipfile.ZipFile(os.path.join(wheel_directory, name), "w") as wheel:
+        for path, data in files.items():
+            wheel.writestr(path, data)
+    return name
+"#;
+
+    // A project built by `backend`, kept in the project as backend/in_tree.py
+    fn project(dir: &Path, backend: &str) -> PathBuf {
+        let project = dir.join("project");
+        in_tree_backend(&project, backend);
+        fs::write(
+            project.join("pyproject.toml"),
+            "[build-system]\nrequires = []\nbuild-backend = \"in_tree\"\nbackend-path = [\"backend\"]\n",
+        )
+        .unwrap();
+        project
+    }
+
+    fn build_error(dir: &Path, backend: &str) -> BuildError {
+        let project = project(dir, backend);
+        build_wheel(
+            &project,
+            &dir.join("env"),
+            &dir.join("wheels"),
+            DEFAULT_INDEX_URL,
+        )
+        .unwrap_err()
+    }
+
+    #[test]
+    fn builds_wheels_on_several_threads() {
+        let dir = TempDir::new("threads");
+        let wheels: Vec<PathBuf> = std::thread::scope(|scope| {
+            let builds: Vec<_> = (0..2)
+                .map(|i| {
+                    let dir = dir.0.join(i.to_string());
+                    scope.spawn(move || {
+                        let project = project(&dir, WHEEL_BACKEND);
+                        build_wheel(
+                            &project,
+                            &dir.join("env"),
+                            &dir.join("wheels"),
+                            DEFAULT_INDEX_URL,
+                        )
+                        .unwrap()
+                    })
+                })
+                .collect();
+            builds
+                .into_iter()
+                .map(|build| build.join().unwrap())
+                .collect()
+        });
+        for (i, wheel) in wheels.iter().enumerate() {
+            assert_eq!(
+                *wheel,
+                dir.0
+                    .join(i.to_string())
+                    .join("wheels/demo-1.0-py3-none-any.whl")
+                    .canonicalize()
+                    .unwrap()
+            );
         }
     }
 
-    Ok(())
+    #[test]
+    fn editable_builds_without_the_hook_install_a_pth_file() {
+        let dir = TempDir::new("editable");
+        let project = project(&dir.0, WHEEL_BACKEND);
+        fs::create_dir_all(project.join("src/demo")).unwrap();
+        fs::write(project.join("src/demo/__init__.py"), "WHERE = 'source'\n").unwrap();
+
+        let wheel = build_editable(
+            &project,
+            &dir.0.join("env"),
+            &dir.0.join("wheels"),
+            DEFAULT_INDEX_URL,
+        )
+        .unwrap();
+        let mut archive = ZipArchive::new(File::open(&wheel).unwrap()).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(String::from).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "__editable__.demo-1.0.pth",
+                "demo-1.0.dist-info/METADATA",
+                "demo-1.0.dist-info/RECORD",
+                "demo-1.0.dist-info/WHEEL",
+            ]
+        );
+        let mut pth = String::new();
+        archive
+            .by_name("__editable__.demo-1.0.pth")
+            .unwrap()
+            .read_to_string(&mut pth)
+            .unwrap();
+        let sources = project.canonicalize().unwrap().join("src");
+        assert_eq!(pth, format!("{}\n", sources.display()));
+
+        // Installed, it imports the package from the project
+        let venv = dir.0.join("venv");
+        create_venv(&venv, false).unwrap();
+        crate::install_wheel(&venv, &wheel, None).unwrap();
+        let output = Command::new(venv_python(&venv))
+            .args(["-c", "import demo; print(demo.WHERE, demo.__file__)"])
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("source {}\n", sources.join("demo/__init__.py").display())
+        );
+    }
+
+    #[test]
+    fn missing_project_is_project_not_found() {
+        let dir = TempDir::new("missing-project");
+        let missing = dir.0.join("missing");
+        let error = build_wheel(
+            &missing,
+            &dir.0.join("env"),
+            &dir.0.join("wheels"),
+            DEFAULT_INDEX_URL,
+        )
+        .unwrap_err();
+        assert!(matches!(error, BuildError::ProjectNotFound(path) if path == missing));
+    }
+
+    #[test]
+    fn a_venv_that_cant_be_created_is_an_environment_error() {
+        let dir = TempDir::new("no-venv");
+        // `python -m venv` can't make a directory inside a file
+        let box_dir = dir.0.join("not-a-dir");
+        fs::write(&box_dir, "").unwrap();
+        let error = setup_python_env(&box_dir).unwrap_err();
+        assert!(
+            matches!(&error, BuildError::Environment(message) if message.contains("exited with")),
+            "{:?}",
+            error
+        );
+
+        setup_python_env(&dir.0).unwrap();
+        assert!(venv_python(&dir.0.join("venv")).exists());
+    }
+
+    #[test]
+    fn missing_backend_is_a_hook_error() {
+        let dir = TempDir::new("missing-backend");
+        let project = dir.0.join("project");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            project.join("pyproject.toml"),
+            "[build-system]\nrequires = []\nbuild-backend = \"no_such_backend\"\n",
+        )
+        .unwrap();
+        let error = build_wheel(
+            &project,
+            &dir.0.join("env"),
+            &dir.0.join("wheels"),
+            DEFAULT_INDEX_URL,
+        )
+        .unwrap_err();
+        match error {
+            BuildError::Hook { hook, backend, .. } => {
+                assert_eq!(hook, "get_requires_for_build_wheel");
+                assert_eq!(backend, "no_such_backend");
+            }
+            other => panic!("expected a hook error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failing_hook_is_a_hook_error() {
+        let dir = TempDir::new("failing-hook");
+        let error = build_error(
+            &dir.0,
+            "def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):\n    \
+             raise RuntimeError('no compiler')\n",
+        );
+        match error {
+            BuildError::Hook { hook, message, .. } => {
+                assert_eq!(hook, "build_wheel");
+                assert!(message.starts_with("exited with"), "{}", message);
+            }
+            other => panic!("expected a hook error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_results_are_hook_errors() {
+        let message = |name: &str, backend: &str| match build_error(&TempDir::new(name).0, backend)
+        {
+            BuildError::Hook { hook, message, .. } => format!("{}: {}", hook, message),
+            other => panic!("expected a hook error, got {:?}", other),
+        };
+
+        // The runner's result file gets clobbered on the way out
+        let clobbered = message(
+            "clobbered-result",
+            "import atexit, sys\n\
+             atexit.register(lambda: open(sys.argv[5], 'w').write('not json'))\n",
+        );
+        assert!(
+            clobbered.starts_with("get_requires_for_build_wheel: invalid result"),
+            "{}",
+            clobbered
+        );
+
+        let not_a_list = message(
+            "requires-not-a-list",
+            "def get_requires_for_build_wheel(config_settings=None):\n    return 'cython'\n",
+        );
+        assert_eq!(
+            not_a_list,
+            "get_requires_for_build_wheel: returned \"cython\" instead of a list of requirements"
+        );
+
+        let not_a_filename = message(
+            "not-a-filename",
+            "def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):\n    \
+             return 42\n",
+        );
+        assert_eq!(
+            not_a_filename,
+            "build_wheel: returned 42 instead of a filename"
+        );
+
+        let not_written = message(
+            "not-written",
+            "def build_wheel(wheel_directory, config_settings=None, metadata_directory=None):\n    \
+             return 'demo-1.0-py3-none-any.whl'\n",
+        );
+        assert!(
+            not_written.starts_with("build_wheel: reported")
+                && not_written.ends_with("but it wasn't written"),
+            "{}",
+            not_written
+        );
+    }
 }
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::index::normalize_name;
+use crate::markers::{Marker, MarkerEnvironment};
+use crate::version::SpecifierSet;
+
+/// A PEP 508 dependency as written by the user or in Requires-Dist,
+/// e.g. `requests[socks]>=2.31,<3; python_version >= "3.8"`, or a direct
+/// reference like `pkg @ https://example.com/pkg-1.0.tar.gz`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Requirement {
+    pub name: String,
+    pub extras: Vec<String>,
+    pub specifiers: SpecifierSet,
+    /// What follows `@`; a direct reference has no specifiers
+    pub url: Option<String>,
+    pub marker: Option<Marker>,
+}
+
+impl Requirement {
+    /// Parses a manifest `[dependencies]` entry, where the value is everything
+    /// after the name (see `constraint`).
+    pub fn from_constraint(name: &str, constraint: &str) -> Result<Requirement, String> {
+        let constraint = constraint.trim();
+        let constraint = constraint.strip_prefix('*').unwrap_or(constraint);
+        format!("{}{}", name, constraint).parse()
+    }
+
+    pub fn normalized_name(&self) -> String {
+        normalize_name(&self.name)
+    }
+
+    /// The requirement minus its name, as stored in the manifest; "*" when unconstrained.
+    pub fn constraint(&self) -> String {
+        let mut constraint = self.extras_suffix();
+        match &self.url {
+            Some(url) if constraint.is_empty() => constraint = format!("@ {}", url),
+            Some(url) => constraint.push_str(&format!(" @ {}", url)),
+            None => constraint.push_str(&self.specifiers.to_string()),
+        }
+
+        if constraint.is_empty() {
+            constraint.push('*');
+        }
+        constraint.push_str(&self.marker_suffix());
+        constraint
+    }
+
+    /// Whether this requirement applies on `env`. `extras` are the extras
+    /// requested of the package that declared it.
+    pub fn applies_to(&self, env: &MarkerEnvironment, extras: &[String]) -> bool {
+        self.marker
+            .as_ref()
+            .map(|marker| marker.evaluate(env, extras))
+            .unwrap_or(true)
+    }
+
+    fn extras_suffix(&self) -> String {
+        if self.extras.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", self.extras.join(","))
+        }
+    }
+
+    // A URL needs a space before the `;`, which it could otherwise contain
+    fn marker_suffix(&self) -> String {
+        match (&self.marker, &self.url) {
+            (Some(marker), Some(_)) => format!(" ; {}", marker),
+            (Some(marker), None) => format!("; {}", marker),
+            (None, _) => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.name, self.extras_suffix())?;
+        match &self.url {
+            Some(url) => write!(f, " @ {}", url)?,
+            None => write!(f, "{}", self.specifiers)?,
+        }
+        write!(f, "{}", self.marker_suffix())
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // After a URL only a `;` with a space before it starts the marker
+        let marker_start = if s.contains('@') {
+            s.match_indices(';')
+                .map(|(i, _)| i)
+                .find(|&i| s[..i].ends_with(char::is_whitespace))
+        } else {
+            s.find(';')
+        };
+        let (requirement, marker) = match marker_start {
+            Some(i) => (&s[..i], Some(s[i + 1..].parse::<Marker>()?)),
+            None => (s, None),
+        };
+
+        let requirement = requirement.trim();
+        let name_end = requirement
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .unwrap_or(requirement.len());
+        let name = &requirement[..name_end];
+
+        if name.is_empty() {
+            return Err(format!("Invalid requirement (missing name): {}", s));
+        }
+
+        let mut rest = requirement[name_end..].trim();
+        let mut extras = Vec::new();
+        if let Some(inner) = rest.strip_prefix('[') {
+            let (list, after) = inner
+                .split_once(']')
+                .ok_or_else(|| format!("Invalid requirement (unclosed extras): {}", s))?;
+            extras = list
+                .split(',')
+                .map(str::trim)
+                .filter(|extra| !extra.is_empty())
+                .map(str::to_string)
+                .collect();
+            rest = after.trim();
+        }
+
+        if let Some(url) = rest.strip_prefix('@') {
+            let url = url.trim();
+            if url.is_empty() {
+                return Err(format!("Invalid requirement (missing URL): {}", s));
+            }
+            return Ok(Requirement {
+                name: name.to_string(),
+                extras,
+                specifiers: SpecifierSet::default(),
+                url: Some(url.to_string()),
+                marker,
+            });
+        }
+
+        // The older "name (>=1.0)" form is still found in metadata
+        if let Some(inner) = rest.strip_prefix('(') {
+            rest = inner
+                .strip_suffix(')')
+                .ok_or_else(|| format!("Invalid requirement (unclosed parenthesis): {}", s))?;
+        }
+
+        Ok(Requirement {
+            name: name.to_string(),
+            extras,
+            specifiers: rest.parse()?,
+            url: None,
+            marker,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(s: &str) -> Requirement {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    #[test]
+    fn parses_names_extras_and_specifiers() {
+        let requirement = r("Requests[socks, security]>=2.31,<3");
+        assert_eq!(requirement.name, "Requests");
+        assert_eq!(requirement.normalized_name(), "requests");
+        assert_eq!(requirement.extras, ["socks", "security"]);
+        assert_eq!(requirement.specifiers.to_string(), ">=2.31,<3");
+        assert_eq!(requirement.marker, None);
+
+        // Empty and blank extras are dropped, whitespace around them too
+        assert_eq!(r("pkg[ a ,, ,b ]").extras, ["a", "b"]);
+        assert!(r("pkg[]").extras.is_empty());
+        assert!(r("zope.interface").specifiers.to_string().is_empty());
+    }
+
+    #[test]
+    fn parses_the_legacy_parenthesized_form() {
+        let requirement = r("foo (>=1.0,<2)");
+        assert_eq!(requirement.name, "foo");
+        assert_eq!(requirement.specifiers.to_string(), ">=1.0,<2");
+        assert_eq!(r("foo[bar] (==1.0)").extras, ["bar"]);
+    }
+
+    #[test]
+    fn splits_off_the_marker() {
+        let requirement = r("pywin32>=300; sys_platform == \"win32\"");
+        assert_eq!(requirement.specifiers.to_string(), ">=300");
+        assert_eq!(
+            requirement.marker.unwrap().to_string(),
+            "sys_platform == \"win32\""
+        );
+
+        let requirement = r("importlib-metadata ; python_version < '3.8'");
+        assert_eq!(requirement.name, "importlib-metadata");
+        assert!(requirement.specifiers.to_string().is_empty());
+        assert!(requirement.marker.is_some());
+    }
+
+    #[test]
+    fn parses_direct_references() {
+        let requirement = r("pkg[extra] @ https://example.com/pkg-1.0.tar.gz");
+        assert_eq!(requirement.name, "pkg");
+        assert_eq!(requirement.extras, ["extra"]);
+        assert_eq!(
+            requirement.url.as_deref(),
+            Some("https://example.com/pkg-1.0.tar.gz")
+        );
+        assert!(requirement.specifiers.to_string().is_empty());
+
+        // A `;` right after the URL is part of it
+        let requirement = r("pkg @ https://example.com/a;b ; os_name == \"nt\"");
+        assert_eq!(requirement.url.as_deref(), Some("https://example.com/a;b"));
+        assert_eq!(requirement.marker.unwrap().to_string(), "os_name == \"nt\"");
+        assert_eq!(
+            r("pkg@git+https://example.com/pkg.git@v1").url.as_deref(),
+            Some("git+https://example.com/pkg.git@v1")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_requirements() {
+        let error = |s: &str| s.parse::<Requirement>().unwrap_err();
+        assert!(error(">=1.0").contains("missing name"));
+        assert!(error("").contains("missing name"));
+        assert!(error("pkg[extra").contains("unclosed extras"));
+        assert!(error("pkg (>=1.0").contains("unclosed parenthesis"));
+        assert!(error("pkg @ ; os_name == \"nt\"").contains("missing URL"));
+    }
+
+    #[test]
+    fn constraints_round_trip() {
+        // The manifest's "*" is no constraint at all
+        let requirement = Requirement::from_constraint("attrs", "*").unwrap();
+        assert_eq!(requirement, r("attrs"));
+        assert_eq!(requirement.constraint(), "*");
+        assert_eq!(requirement.to_string(), "attrs");
+
+        for written in [
+            "attrs>=23",
+            "Requests[socks]>=2,<3",
+            "rich[jupyter]",
+            "numpy>=2; python_version >= \"3.10\"",
+            "colorama; sys_platform == \"win32\"",
+            "pkg @ https://example.com/pkg-1.0.tar.gz",
+            "pkg[extra] @ file:///src/pkg ; python_version >= \"3.10\"",
+        ] {
+            let requirement = r(written);
+            let constraint = requirement.constraint();
+            let again = Requirement::from_constraint(&requirement.name, &constraint).unwrap();
+            assert_eq!(again, requirement, "{}", written);
+            assert_eq!(again.constraint(), constraint);
+            assert_eq!(again.to_string(), written);
+        }
+        assert_eq!(
+            r("colorama; os_name == \"nt\"").constraint(),
+            "*; os_name == \"nt\""
+        );
+        assert_eq!(
+            r("pkg[extra] @ https://example.com/pkg.zip").constraint(),
+            "[extra] @ https://example.com/pkg.zip"
+        );
+    }
+}
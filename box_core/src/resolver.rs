@@ -0,0 +1,1071 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::index::normalize_name;
+use crate::markers::MarkerEnvironment;
+use crate::requirement::Requirement;
+use crate::version::{SpecifierSet, Version};
+
+// The project itself is modelled as a package with a single version, which
+// depends on everything the user asked for
+const ROOT: &str = "";
+
+pub trait DependencyProvider {
+    /// Every version of `package` that could be installed, in any order.
+    fn available_versions(&mut self, package: &str) -> Result<Vec<Version>, String>;
+
+    /// The requirements of one specific release of `package`, markers unevaluated.
+    fn dependencies(
+        &mut self,
+        package: &str,
+        version: &Version,
+    ) -> Result<Vec<Requirement>, String>;
+
+    /// A version to pick over the newest one whenever it's still allowed,
+    /// e.g. the one already locked.
+    fn preferred_version(&self, _package: &str) -> Option<Version> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub packages: BTreeMap<String, Version>,
+    pub dependencies: BTreeMap<String, Vec<String>>, // package => names of its direct dependencies
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    NoSolution(String), // human readable derivation of the conflict
+    Provider(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NoSolution(explanation) => {
+                write!(f, "No solution found:\n{}", explanation)
+            }
+            ResolveError::Provider(message) => write!(f, "Resolution failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Resolves `requirements` and everything they transitively depend on to a
+/// single version per package, using the PubGrub algorithm
+/// (https://github.com/dart-lang/pub/blob/master/doc/solver.md).
+///
+/// Requirements whose markers don't hold on `env` are skipped. Extras are
+/// modelled as virtual "name[extra]" packages pinned to the same version as
+/// "name", which pull in the extra's additional requirements.
+pub fn resolve<P: DependencyProvider>(
+    project_name: &str,
+    requirements: &[Requirement],
+    env: &MarkerEnvironment,
+    provider: &mut P,
+) -> Result<Resolution, ResolveError> {
+    let mut solver = Solver {
+        provider,
+        env: env.clone(),
+        project_name: project_name.to_string(),
+        root_requirements: requirements.to_vec(),
+        root_version: "0".parse().map_err(ResolveError::Provider)?,
+        universes: HashMap::new(),
+        incompatibilities: Vec::new(),
+        incompatibilities_by_package: HashMap::new(),
+        assignments: Vec::new(),
+        decision_level: 0,
+        dependencies: BTreeMap::new(),
+        requirements: HashMap::new(),
+    };
+
+    solver.solve()
+}
+
+type VersionSet = BTreeSet<Version>;
+
+type IncompatibilityId = usize;
+
+// Every version set is a subset of the package's known versions, so "not in S"
+// never has to be materialized as a complement
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Positive(VersionSet), // the package is selected with a version in the set
+    Negative(VersionSet), // the package is absent, or selected outside the set
+}
+
+impl Term {
+    fn any() -> Term {
+        Term::Negative(VersionSet::new())
+    }
+
+    fn exact(version: Version) -> Term {
+        Term::Positive(VersionSet::from([version]))
+    }
+
+    fn negate(&self) -> Term {
+        match self {
+            Term::Positive(set) => Term::Negative(set.clone()),
+            Term::Negative(set) => Term::Positive(set.clone()),
+        }
+    }
+
+    fn intersection(&self, other: &Term) -> Term {
+        match (self, other) {
+            (Term::Positive(a), Term::Positive(b)) => Term::Positive(a & b),
+            (Term::Positive(a), Term::Negative(b)) | (Term::Negative(b), Term::Positive(a)) => {
+                Term::Positive(a - b)
+            }
+            (Term::Negative(a), Term::Negative(b)) => Term::Negative(a | b),
+        }
+    }
+
+    fn union(&self, other: &Term) -> Term {
+        self.negate().intersection(&other.negate()).negate()
+    }
+
+    fn subset_of(&self, other: &Term) -> bool {
+        self.intersection(other) == *self
+    }
+
+    fn is_disjoint(&self, other: &Term) -> bool {
+        self.intersection(other) == Term::Positive(VersionSet::new())
+    }
+
+    fn contains(&self, version: &Version) -> bool {
+        match self {
+            Term::Positive(set) => set.contains(version),
+            Term::Negative(set) => !set.contains(version),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Cause {
+    Root,
+    NoVersions,
+    Unavailable(String),
+    Dependency(String), // the requirement as written, e.g. "urllib3<3,>=1.21.1"
+    Derived(IncompatibilityId, IncompatibilityId),
+}
+
+// A set of terms that can't all be true at once
+#[derive(Debug, Clone)]
+struct Incompatibility {
+    terms: BTreeMap<String, Term>,
+    cause: Cause,
+}
+
+#[derive(Debug, Clone)]
+struct Assignment {
+    package: String,
+    term: Term,
+    decision_level: usize,
+    cause: Option<IncompatibilityId>, // None for decisions
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Relation {
+    Satisfied,
+    Contradicted,
+    AlmostSatisfied(String),
+    Inconclusive,
+}
+
+struct Solver<'a, P: DependencyProvider> {
+    provider: &'a mut P,
+    env: MarkerEnvironment,
+    project_name: String,
+    root_requirements: Vec<Requirement>,
+    root_version: Version,
+    universes: HashMap<String, VersionSet>,
+    incompatibilities: Vec<Incompatibility>,
+    incompatibilities_by_package: HashMap<String, Vec<IncompatibilityId>>,
+    assignments: Vec<Assignment>, // the partial solution, in assignment order
+    decision_level: usize,
+    dependencies: BTreeMap<String, Vec<String>>,
+    requirements: HashMap<(String, Version), Vec<Requirement>>, // raw provider answers
+}
+
+// Splits a solver package key such as "requests[socks]" into name and extra
+fn split_extra(package: &str) -> (&str, Option<&str>) {
+    match package.split_once('[') {
+        Some((name, extra)) => (name, Some(extra.trim_end_matches(']'))),
+        None => (package, None),
+    }
+}
+
+impl<P: DependencyProvider> Solver<'_, P> {
+    fn solve(&mut self) -> Result<Resolution, ResolveError> {
+        self.universes.insert(
+            ROOT.to_string(),
+            VersionSet::from([self.root_version.clone()]),
+        );
+        let root_incompatibility = Incompatibility {
+            terms: BTreeMap::from([(
+                ROOT.to_string(),
+                Term::exact(self.root_version.clone()).negate(),
+            )]),
+            cause: Cause::Root,
+        };
+        let id = self.store(root_incompatibility);
+        self.index(id);
+
+        let mut next = ROOT.to_string();
+        loop {
+            self.unit_propagation(next)?;
+            match self.choose_package_version()? {
+                Some(package) => next = package,
+                None => break,
+            }
+        }
+
+        let mut resolution = Resolution::default();
+        for assignment in &self.assignments {
+            // Extras are pinned to their base package, so only the base is reported
+            if assignment.cause.is_some()
+                || assignment.package == ROOT
+                || split_extra(&assignment.package).1.is_some()
+            {
+                continue;
+            }
+            if let Term::Positive(set) = &assignment.term
+                && let Some(version) = set.first()
+            {
+                resolution
+                    .packages
+                    .insert(assignment.package.clone(), version.clone());
+            }
+        }
+        for package in resolution.packages.keys() {
+            let dependencies = self.dependencies.get(package).cloned().unwrap_or_default();
+            resolution
+                .dependencies
+                .insert(package.clone(), dependencies);
+        }
+        // An extra's requirements are reported as requirements of its base package
+        for (package, dependencies) in &self.dependencies {
+            if let (base, Some(_)) = split_extra(package)
+                && self.is_decided(package)
+                && let Some(base_dependencies) = resolution.dependencies.get_mut(base)
+            {
+                for dependency in dependencies {
+                    if dependency != base && !base_dependencies.contains(dependency) {
+                        base_dependencies.push(dependency.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(resolution)
+    }
+
+    fn unit_propagation(&mut self, package: String) -> Result<(), ResolveError> {
+        let mut changed = vec![package];
+
+        while let Some(package) = changed.pop() {
+            let ids: Vec<IncompatibilityId> = self
+                .incompatibilities_by_package
+                .get(&package)
+                .map(|ids| ids.iter().rev().copied().collect())
+                .unwrap_or_default();
+
+            for id in ids {
+                match self.relation(id, None) {
+                    Relation::Satisfied => {
+                        let root_cause = self.resolve_conflict(id)?;
+                        // After backtracking the learned incompatibility is almost
+                        // satisfied, which forces the negation of its remaining term
+                        let Relation::AlmostSatisfied(forced) = self.relation(root_cause, None)
+                        else {
+                            return Err(ResolveError::Provider(
+                                "conflict resolution left an unusable incompatibility".into(),
+                            ));
+                        };
+                        self.derive(&forced, root_cause);
+                        changed.clear();
+                        changed.push(forced);
+                        break;
+                    }
+                    Relation::AlmostSatisfied(forced) => {
+                        self.derive(&forced, id);
+                        if !changed.contains(&forced) {
+                            changed.push(forced);
+                        }
+                    }
+                    Relation::Contradicted | Relation::Inconclusive => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_conflict(
+        &mut self,
+        id: IncompatibilityId,
+    ) -> Result<IncompatibilityId, ResolveError> {
+        let mut current = id;
+        let mut learned = false;
+
+        loop {
+            if self.is_terminal(current) {
+                return Err(ResolveError::NoSolution(self.explain(current)));
+            }
+
+            let incompatibility = self.incompatibilities[current].clone();
+
+            // The satisfier is the assignment that made the incompatibility true
+            let mut satisfiers = BTreeMap::new();
+            for (package, term) in &incompatibility.terms {
+                let index = self.satisfier(package, term, &Term::any()).ok_or_else(|| {
+                    ResolveError::Provider(format!("no satisfier found for {}", package))
+                })?;
+                satisfiers.insert(package.clone(), index);
+            }
+            let (satisfier_package, satisfier_index) = satisfiers
+                .iter()
+                .max_by_key(|(_, index)| **index)
+                .map(|(package, index)| (package.clone(), *index))
+                .expect("incompatibility has at least one term");
+
+            let satisfier = self.assignments[satisfier_index].clone();
+
+            // The latest decision level at which the incompatibility was already
+            // satisfied, ignoring the satisfier itself
+            let start_term = match satisfier.cause {
+                Some(cause) => self.incompatibilities[cause].terms[&satisfier_package].negate(),
+                None => satisfier.term.clone(),
+            };
+            satisfiers.remove(&satisfier_package);
+            let mut previous_level = 1;
+            for index in satisfiers.values() {
+                previous_level = previous_level.max(self.assignments[*index].decision_level);
+            }
+            if let Some(index) = self.satisfier(
+                &satisfier_package,
+                &incompatibility.terms[&satisfier_package],
+                &start_term,
+            ) {
+                previous_level = previous_level.max(self.assignments[index].decision_level);
+            }
+
+            let Some(satisfier_cause) = satisfier.cause else {
+                self.backtrack(previous_level);
+                if learned {
+                    self.index(current);
+                }
+                return Ok(current);
+            };
+            if previous_level < satisfier.decision_level {
+                self.backtrack(previous_level);
+                if learned {
+                    self.index(current);
+                }
+                return Ok(current);
+            }
+
+            // Resolve the incompatibility with the satisfier's cause to learn a new one
+            let cause_terms = &self.incompatibilities[satisfier_cause].terms;
+            let mut terms = incompatibility.terms.clone();
+            let own_term = terms
+                .remove(&satisfier_package)
+                .expect("satisfier package is part of the incompatibility");
+            for (package, term) in cause_terms {
+                if *package == satisfier_package {
+                    continue;
+                }
+                let merged = match terms.get(package) {
+                    Some(existing) => existing.intersection(term),
+                    None => term.clone(),
+                };
+                terms.insert(package.clone(), merged);
+            }
+            let merged = own_term.union(&cause_terms[&satisfier_package]);
+            if merged != Term::any() {
+                terms.insert(satisfier_package, merged);
+            }
+
+            current = self.store(Incompatibility {
+                terms,
+                cause: Cause::Derived(current, satisfier_cause),
+            });
+            learned = true;
+        }
+    }
+
+    fn choose_package_version(&mut self) -> Result<Option<String>, ResolveError> {
+        // Among packages that must be selected but aren't decided yet, pick the
+        // most constrained one first since it is the most likely to conflict
+        let mut candidates: Option<(String, VersionSet)> = None;
+        let mut seen = HashSet::new();
+        for assignment in &self.assignments {
+            if !seen.insert(assignment.package.as_str()) || self.is_decided(&assignment.package) {
+                continue;
+            }
+            if let Term::Positive(set) = self.accumulated(&assignment.package)
+                && candidates
+                    .as_ref()
+                    .is_none_or(|(_, best)| set.len() < best.len())
+            {
+                candidates = Some((assignment.package.clone(), set));
+            }
+        }
+
+        let Some((package, versions)) = candidates else {
+            return Ok(None);
+        };
+
+        let preferred = self
+            .provider
+            .preferred_version(split_extra(&package).0)
+            .filter(|v| versions.contains(v));
+        let Some(version) = preferred.or_else(|| {
+            versions
+                .iter()
+                .rev()
+                .find(|v| !v.is_prerelease())
+                .or_else(|| versions.iter().next_back())
+                .cloned()
+        }) else {
+            let id = self.store(Incompatibility {
+                terms: BTreeMap::from([(package.clone(), Term::Positive(versions))]),
+                cause: Cause::NoVersions,
+            });
+            self.index(id);
+            return Ok(Some(package));
+        };
+
+        let requirements = self.requirements_for(&package, &version);
+
+        let requirements = match requirements {
+            Ok(requirements) => requirements,
+            Err(reason) => {
+                let id = self.store(Incompatibility {
+                    terms: BTreeMap::from([(package.clone(), Term::exact(version))]),
+                    cause: Cause::Unavailable(reason),
+                });
+                self.index(id);
+                return Ok(Some(package));
+            }
+        };
+
+        let mut new_ids = Vec::new();
+        let mut dependency_names = Vec::new();
+        for requirement in requirements {
+            let base = normalize_name(&requirement.name);
+            let mut dependencies = vec![base.clone()];
+            for extra in &requirement.extras {
+                dependencies.push(format!("{}[{}]", base, normalize_name(extra)));
+            }
+
+            for dependency in dependencies {
+                if dependency == package {
+                    continue;
+                }
+                let id = self.add_dependency(&package, &version, &dependency, &requirement);
+                new_ids.push(id);
+            }
+
+            if base != package && !dependency_names.contains(&base) {
+                dependency_names.push(base);
+            }
+        }
+        self.dependencies.insert(package.clone(), dependency_names);
+
+        // Only decide if none of the new dependencies immediately conflicts;
+        // otherwise propagation will rule this version out
+        let decision = Term::exact(version.clone());
+        let conflicts = new_ids
+            .iter()
+            .any(|id| self.relation(*id, Some((&package, &decision))) == Relation::Satisfied);
+        if !conflicts {
+            self.decision_level += 1;
+            self.assignments.push(Assignment {
+                package: package.clone(),
+                term: decision,
+                decision_level: self.decision_level,
+                cause: None,
+            });
+        }
+
+        Ok(Some(package))
+    }
+
+    fn add_dependency(
+        &mut self,
+        package: &str,
+        version: &Version,
+        dependency: &str,
+        requirement: &Requirement,
+    ) -> IncompatibilityId {
+        let allowed = self.versions_matching(dependency, requirement);
+
+        let mut terms = BTreeMap::from([(package.to_string(), Term::exact(version.clone()))]);
+        // With nothing matching, the dependency alone rules this version out
+        if !allowed.is_empty() {
+            terms.insert(dependency.to_string(), Term::Negative(allowed));
+        }
+        let id = self.store(Incompatibility {
+            terms,
+            cause: Cause::Dependency(requirement.to_string()),
+        });
+        self.index(id);
+        id
+    }
+
+    // The requirements of `package` that apply on the target environment
+    fn requirements_for(
+        &mut self,
+        package: &str,
+        version: &Version,
+    ) -> Result<Vec<Requirement>, String> {
+        if package == ROOT {
+            let env = &self.env;
+            return Ok(self
+                .root_requirements
+                .iter()
+                .filter(|r| r.applies_to(env, &[]))
+                .cloned()
+                .collect());
+        }
+
+        let (base, extra) = split_extra(package);
+        let key = (base.to_string(), version.clone());
+        if !self.requirements.contains_key(&key) {
+            let requirements = self.provider.dependencies(base, version)?;
+            self.requirements.insert(key.clone(), requirements);
+        }
+        let requirements = &self.requirements[&key];
+
+        let Some(extra) = extra else {
+            return Ok(requirements
+                .iter()
+                .filter(|r| r.applies_to(&self.env, &[]))
+                .cloned()
+                .collect());
+        };
+
+        // An extra is its base package at the same version, plus whatever only
+        // applies when the extra is requested
+        let extras = [extra.to_string()];
+        let mut extra_requirements = vec![Requirement {
+            name: base.to_string(),
+            extras: Vec::new(),
+            specifiers: format!("=={}", version).parse::<SpecifierSet>()?,
+            url: None,
+            marker: None,
+        }];
+        extra_requirements.extend(
+            requirements
+                .iter()
+                .filter(|r| r.applies_to(&self.env, &extras) && !r.applies_to(&self.env, &[]))
+                .cloned(),
+        );
+        Ok(extra_requirements)
+    }
+
+    fn versions_matching(&mut self, package: &str, requirement: &Requirement) -> VersionSet {
+        let universe = self.universe(package);
+
+        // Pre-releases only count when nothing else satisfies the requirement
+        let matching: VersionSet = universe
+            .iter()
+            .filter(|v| requirement.specifiers.matches(v))
+            .cloned()
+            .collect();
+        if !matching.is_empty() {
+            return matching;
+        }
+        universe
+            .iter()
+            .filter(|v| requirement.specifiers.contains(v))
+            .cloned()
+            .collect()
+    }
+
+    fn universe(&mut self, package: &str) -> VersionSet {
+        if let Some(universe) = self.universes.get(package) {
+            return universe.clone();
+        }
+
+        let versions = match self.provider.available_versions(split_extra(package).0) {
+            Ok(versions) => versions.into_iter().collect(),
+            Err(e) => {
+                // Surfaces as "no versions of X match" in the explanation
+                println!("Failed to list versions of {}: {}", package, e);
+                VersionSet::new()
+            }
+        };
+        self.universes.insert(package.to_string(), versions);
+        self.universes[package].clone()
+    }
+
+    fn store(&mut self, incompatibility: Incompatibility) -> IncompatibilityId {
+        self.incompatibilities.push(incompatibility);
+        self.incompatibilities.len() - 1
+    }
+
+    fn index(&mut self, id: IncompatibilityId) {
+        for package in self.incompatibilities[id].terms.keys() {
+            self.incompatibilities_by_package
+                .entry(package.clone())
+                .or_default()
+                .push(id);
+        }
+    }
+
+    fn derive(&mut self, package: &str, cause: IncompatibilityId) {
+        let term = self.incompatibilities[cause].terms[package].negate();
+        self.assignments.push(Assignment {
+            package: package.to_string(),
+            term,
+            decision_level: self.decision_level,
+            cause: Some(cause),
+        });
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        self.assignments.retain(|a| a.decision_level <= level);
+        self.decision_level = level;
+    }
+
+    fn is_decided(&self, package: &str) -> bool {
+        self.assignments
+            .iter()
+            .any(|a| a.package == package && a.cause.is_none())
+    }
+
+    fn accumulated(&self, package: &str) -> Term {
+        self.assignments
+            .iter()
+            .filter(|a| a.package == package)
+            .fold(Term::any(), |acc, a| acc.intersection(&a.term))
+    }
+
+    // Index of the earliest assignment after which `start` and the assignments
+    // so far for `package` imply `term`
+    fn satisfier(&self, package: &str, term: &Term, start: &Term) -> Option<usize> {
+        if start.subset_of(term) {
+            return None;
+        }
+
+        let mut accumulated = start.clone();
+        for (index, assignment) in self.assignments.iter().enumerate() {
+            if assignment.package != package {
+                continue;
+            }
+            accumulated = accumulated.intersection(&assignment.term);
+            if accumulated.subset_of(term) {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn relation(&self, id: IncompatibilityId, overlay: Option<(&str, &Term)>) -> Relation {
+        let mut unsatisfied = None;
+
+        for (package, term) in &self.incompatibilities[id].terms {
+            let accumulated = match overlay {
+                Some((overlay_package, overlay_term)) if overlay_package == package => {
+                    overlay_term.clone()
+                }
+                _ => self.accumulated(package),
+            };
+
+            if accumulated.subset_of(term) {
+                continue;
+            }
+            if accumulated.is_disjoint(term) {
+                return Relation::Contradicted;
+            }
+            if unsatisfied.is_some() {
+                return Relation::Inconclusive;
+            }
+            unsatisfied = Some(package.clone());
+        }
+
+        match unsatisfied {
+            Some(package) => Relation::AlmostSatisfied(package),
+            None => Relation::Satisfied,
+        }
+    }
+
+    fn is_terminal(&self, id: IncompatibilityId) -> bool {
+        let terms = &self.incompatibilities[id].terms;
+        terms.is_empty()
+            || (terms.len() == 1
+                && terms
+                    .get(ROOT)
+                    .is_some_and(|term| term.contains(&self.root_version)))
+    }
+
+    fn explain(&self, id: IncompatibilityId) -> String {
+        let mut lines = Vec::new();
+        let mut explained = HashSet::new();
+
+        if let Cause::Derived(..) = self.incompatibilities[id].cause {
+            self.explain_derived(id, &mut lines, &mut explained);
+        } else {
+            lines.push(format!(
+                "Because {}, version solving failed.",
+                self.describe(id)
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    fn explain_derived(
+        &self,
+        id: IncompatibilityId,
+        lines: &mut Vec<String>,
+        explained: &mut HashSet<IncompatibilityId>,
+    ) {
+        let Cause::Derived(left, right) = self.incompatibilities[id].cause else {
+            return;
+        };
+
+        // Derived causes are spelled out before the line that relies on them
+        for cause in [left, right] {
+            if matches!(self.incompatibilities[cause].cause, Cause::Derived(..))
+                && explained.insert(cause)
+            {
+                self.explain_derived(cause, lines, explained);
+            }
+        }
+
+        lines.push(format!(
+            "Because {} and {}, {}.",
+            self.describe(left),
+            self.describe(right),
+            self.describe(id)
+        ));
+    }
+
+    fn describe(&self, id: IncompatibilityId) -> String {
+        let incompatibility = &self.incompatibilities[id];
+        let terms: Vec<(&String, &Term)> = incompatibility.terms.iter().collect();
+
+        match &incompatibility.cause {
+            Cause::Root => format!("{} is being resolved", self.project_name),
+            Cause::NoVersions => {
+                let (package, term) = terms[0];
+                format!("no versions of {} match", self.describe_term(package, term))
+            }
+            Cause::Unavailable(reason) => {
+                let (package, term) = terms[0];
+                format!(
+                    "{} is unavailable ({})",
+                    self.describe_term(package, term),
+                    reason
+                )
+            }
+            Cause::Dependency(requirement) => {
+                let depender = terms
+                    .iter()
+                    .find(|(_, term)| matches!(term, Term::Positive(_)))
+                    .map(|(package, term)| self.describe_term(package, term))
+                    .unwrap_or_default();
+                if terms.len() == 1 {
+                    // The dependency term was dropped because nothing matched it
+                    format!(
+                        "{} depends on {}, which no available version satisfies",
+                        depender, requirement
+                    )
+                } else {
+                    format!("{} depends on {}", depender, requirement)
+                }
+            }
+            Cause::Derived(..) => {
+                if self.is_terminal(id) {
+                    return "version solving failed".to_string();
+                }
+
+                let positive: Vec<String> = terms
+                    .iter()
+                    .filter(|(_, term)| matches!(term, Term::Positive(_)))
+                    .map(|(package, term)| self.describe_term(package, term))
+                    .collect();
+                let negative: Vec<String> = terms
+                    .iter()
+                    .filter(|(_, term)| matches!(term, Term::Negative(_)))
+                    .map(|(package, term)| self.describe_term(package, &term.negate()))
+                    .collect();
+
+                match (positive.len(), negative.len()) {
+                    (1, 0) => format!("{} is forbidden", positive[0]),
+                    (0, 1) => format!("{} is required", negative[0]),
+                    (1, 1) => format!("{} depends on {}", positive[0], negative[0]),
+                    (_, 0) => format!("{} are incompatible", positive.join(" and ")),
+                    _ => format!(
+                        "{} requires one of {}",
+                        positive.join(" and "),
+                        negative.join(" or ")
+                    ),
+                }
+            }
+        }
+    }
+
+    // Renders a positive term's version set as compactly as possible
+    fn describe_term(&self, package: &str, term: &Term) -> String {
+        if package == ROOT {
+            return self.project_name.clone();
+        }
+
+        let set = match term {
+            Term::Positive(set) => set,
+            Term::Negative(_) => {
+                return format!("not {}", self.describe_term(package, &term.negate()));
+            }
+        };
+        let universe = self.universes.get(package).cloned().unwrap_or_default();
+
+        if set.is_empty() {
+            return format!("{} (no versions)", package);
+        }
+        if *set == universe {
+            return package.to_string();
+        }
+        if set.len() == 1 {
+            return format!("{}=={}", package, set.first().expect("non-empty"));
+        }
+
+        // A contiguous run of the known versions reads best as a range
+        let positions: Vec<usize> = universe
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| set.contains(v))
+            .map(|(i, _)| i)
+            .collect();
+        let contiguous =
+            positions.len() == set.len() && positions.windows(2).all(|pair| pair[1] == pair[0] + 1);
+        let lowest = set.first().expect("non-empty");
+        let highest = set.last().expect("non-empty");
+
+        if contiguous {
+            let includes_min = positions.first() == Some(&0);
+            let includes_max = positions.last() == Some(&(universe.len() - 1));
+            return match (includes_min, includes_max) {
+                (true, false) => format!("{}<={}", package, highest),
+                (false, true) => format!("{}>={}", package, lowest),
+                _ => format!("{}>={},<={}", package, lowest, highest),
+            };
+        }
+
+        let listed: Vec<String> = set.iter().take(5).map(|v| v.to_string()).collect();
+        let more = if set.len() > 5 { ", ..." } else { "" };
+        format!("{} in ({}{})", package, listed.join(", "), more)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An in-memory index: package => [(version, requirements)]
+    #[derive(Default)]
+    struct TestProvider {
+        packages: HashMap<String, Vec<(Version, Vec<Requirement>)>>,
+        preferred: HashMap<String, Version>,
+    }
+
+    impl TestProvider {
+        fn add(&mut self, package: &str, version: &str, requirements: &[&str]) -> &mut Self {
+            self.packages.entry(package.to_string()).or_default().push((
+                version.parse().unwrap(),
+                requirements.iter().map(|r| r.parse().unwrap()).collect(),
+            ));
+            self
+        }
+    }
+
+    impl DependencyProvider for TestProvider {
+        fn available_versions(&mut self, package: &str) -> Result<Vec<Version>, String> {
+            if package == "broken" {
+                return Err("index unreachable".to_string());
+            }
+            Ok(self
+                .packages
+                .get(package)
+                .map(|releases| releases.iter().map(|(v, _)| v.clone()).collect())
+                .unwrap_or_default())
+        }
+
+        fn dependencies(
+            &mut self,
+            package: &str,
+            version: &Version,
+        ) -> Result<Vec<Requirement>, String> {
+            self.packages[package]
+                .iter()
+                .find(|(v, _)| v == version)
+                .map(|(_, requirements)| requirements.clone())
+                .ok_or_else(|| format!("{} {} not found", package, version))
+        }
+
+        fn preferred_version(&self, package: &str) -> Option<Version> {
+            self.preferred.get(package).cloned()
+        }
+    }
+
+    fn linux() -> MarkerEnvironment {
+        MarkerEnvironment {
+            python_version: "3.11".to_string(),
+            python_full_version: "3.11.7".to_string(),
+            sys_platform: "linux".to_string(),
+            platform_system: "Linux".to_string(),
+            os_name: "posix".to_string(),
+            ..MarkerEnvironment::default()
+        }
+    }
+
+    fn run(provider: &mut TestProvider, requirements: &[&str]) -> Result<Resolution, ResolveError> {
+        let requirements: Vec<Requirement> =
+            requirements.iter().map(|r| r.parse().unwrap()).collect();
+        resolve("project", &requirements, &linux(), provider)
+    }
+
+    fn versions(resolution: &Resolution) -> Vec<String> {
+        resolution
+            .packages
+            .iter()
+            .map(|(name, version)| format!("{}=={}", name, version))
+            .collect()
+    }
+
+    #[test]
+    fn picks_newest_versions_of_the_dependency_graph() {
+        let mut provider = TestProvider::default();
+        provider
+            .add("a", "1.0", &["b>=1"])
+            .add("a", "2.0", &["b>=2", "c"])
+            .add("b", "1.0", &[])
+            .add("b", "2.0", &[])
+            .add("b", "3.0b1", &[])
+            .add("c", "0.1", &[]);
+
+        let resolution = run(&mut provider, &["a"]).unwrap();
+        assert_eq!(versions(&resolution), ["a==2.0", "b==2.0", "c==0.1"]);
+        assert_eq!(resolution.dependencies["a"], ["b", "c"]);
+    }
+
+    #[test]
+    fn avoids_conflicts_during_decision_making() {
+        // From the PubGrub documentation: foo 1.1 would need bar 2
+        let mut provider = TestProvider::default();
+        provider
+            .add("foo", "1.0.0", &[])
+            .add("foo", "1.1.0", &["bar>=2.0,<3"])
+            .add("bar", "1.0.0", &[])
+            .add("bar", "1.1.0", &[])
+            .add("bar", "2.0.0", &[]);
+
+        let resolution = run(&mut provider, &["foo>=1.0,<2", "bar>=1.0,<2"]).unwrap();
+        assert_eq!(versions(&resolution), ["bar==1.1.0", "foo==1.0.0"]);
+    }
+
+    #[test]
+    fn backtracks_out_of_a_conflict() {
+        // From the PubGrub documentation: bar 1 depends back on foo 1
+        let mut provider = TestProvider::default();
+        provider
+            .add("foo", "1.0.0", &[])
+            .add("foo", "2.0.0", &["bar>=1.0,<2"])
+            .add("bar", "1.0.0", &["foo>=1.0,<2"]);
+
+        let resolution = run(&mut provider, &["foo"]).unwrap();
+        assert_eq!(versions(&resolution), ["foo==1.0.0"]);
+    }
+
+    #[test]
+    fn explains_conflicts() {
+        let mut provider = TestProvider::default();
+        provider
+            .add("foo", "1.0.0", &["bar>=2.0,<3"])
+            .add("bar", "2.0.0", &["baz>=3.0,<4"])
+            .add("baz", "1.0.0", &[])
+            .add("baz", "3.0.0", &[]);
+
+        let error = run(&mut provider, &["foo>=1.0,<2", "baz>=1.0,<2"]).unwrap_err();
+        assert!(matches!(error, ResolveError::NoSolution(_)));
+        assert_eq!(
+            error.to_string(),
+            "No solution found:\n\
+             Because foo depends on bar>=2.0,<3 and bar depends on baz>=3.0,<4, \
+             foo depends on baz==3.0.0.\n\
+             Because foo depends on baz==3.0.0 and project depends on foo>=1.0,<2, \
+             project depends on baz==3.0.0.\n\
+             Because project depends on baz==3.0.0 and project depends on baz>=1.0,<2, \
+             version solving failed."
+        );
+    }
+
+    #[test]
+    fn explains_missing_versions() {
+        let mut provider = TestProvider::default();
+        provider.add("foo", "1.0", &[]);
+
+        let error = run(&mut provider, &["foo>=2"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No solution found:\n\
+             Because project depends on foo>=2, which no available version satisfies, \
+             version solving failed."
+        );
+
+        // A package the provider can't list has no versions
+        let error = run(&mut provider, &["broken"]).unwrap_err();
+        let ResolveError::NoSolution(explanation) = error else {
+            panic!("expected a conflict");
+        };
+        assert!(explanation.contains("broken"), "{}", explanation);
+    }
+
+    #[test]
+    fn prefers_locked_versions_while_allowed() {
+        let mut provider = TestProvider::default();
+        provider.add("a", "1.0", &[]).add("a", "2.0", &[]);
+        provider
+            .preferred
+            .insert("a".to_string(), "1.0".parse().unwrap());
+
+        assert_eq!(versions(&run(&mut provider, &["a"]).unwrap()), ["a==1.0"]);
+        assert_eq!(
+            versions(&run(&mut provider, &["a>1.0"]).unwrap()),
+            ["a==2.0"]
+        );
+    }
+
+    #[test]
+    fn skips_requirements_for_other_environments() {
+        let mut provider = TestProvider::default();
+        provider
+            .add("a", "1.0", &["pywin32; sys_platform == 'win32'", "b"])
+            .add("b", "1.0", &[]);
+
+        let resolution = run(&mut provider, &["a", "colorama; os_name == 'nt'"]).unwrap();
+        assert_eq!(versions(&resolution), ["a==1.0", "b==1.0"]);
+    }
+
+    #[test]
+    fn extras_pull_in_their_requirements() {
+        let mut provider = TestProvider::default();
+        provider
+            .add("a", "1.0", &["b"])
+            .add("a", "2.0", &["b", "c; extra == 'fast'"])
+            .add("b", "1.0", &[])
+            .add("c", "1.0", &[]);
+
+        let plain = run(&mut provider, &["a"]).unwrap();
+        assert_eq!(versions(&plain), ["a==2.0", "b==1.0"]);
+
+        let with_extra = run(&mut provider, &["a[fast]"]).unwrap();
+        assert_eq!(with_extra.packages["a"], "2.0".parse().unwrap());
+        assert!(with_extra.packages.contains_key("c"));
+    }
+}
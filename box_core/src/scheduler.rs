@@ -0,0 +1,538 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::index::{DistributionFile, DistributionKind};
+use crate::{build_sdist, download_artifact, move_wheel, sha256_file};
+
+/// One package to bring into the cache: downloaded, and built if it's an sdist.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub package: String,
+    pub artifact: DistributionFile,
+    pub cache_dir: PathBuf,
+    pub depends_on: Vec<String>, // packages of the same run to build first
+    pub prebuilt: Option<PathBuf>, // a wheel already built from `artifact`, e.g. to read its metadata
+}
+
+#[derive(Debug, Clone)]
+pub struct JobOutput {
+    pub wheel: PathBuf,      // in the job's cache_dir
+    pub source_hash: String, // of the downloaded sdist or wheel, e.g. "sha256:ab12..."
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Downloading,
+    Downloaded,
+    Building,
+    Done,
+    Failed(String),
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Progress::Downloading => write!(f, "downloading"),
+            Progress::Downloaded => write!(f, "downloaded"),
+            Progress::Building => write!(f, "building"),
+            Progress::Done => write!(f, "done"),
+            Progress::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+/// The packages that couldn't be downloaded or built, and why.
+#[derive(Debug)]
+pub struct ScheduleError {
+    pub failures: BTreeMap<String, String>,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failures: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(package, e)| format!("{}: {}", package, e))
+            .collect();
+        write!(f, "Failed to prepare {}", failures.join("; "))
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    Downloading,
+    Downloaded,
+    Building,
+    Done,
+    Failed,
+}
+
+enum Task {
+    Download(usize),
+    Build(usize),
+}
+
+struct Shared {
+    states: Vec<State>,
+    sources: Vec<Option<PathBuf>>,
+    outputs: BTreeMap<String, JobOutput>,
+    failures: BTreeMap<String, String>,
+    running: usize,
+}
+
+/// Downloads every job's artifact and builds the sdists, using up to `max_jobs`
+/// threads. Downloads start straight away; a build waits until the packages it
+/// depends on are built. Sdists download into `build_dir` and build under
+/// `build_dir`/{package}, with build requirements from `index_url`. `progress`
+/// is called as each package moves along.
+pub fn run_jobs(
+    jobs: &[Job],
+    build_dir: &Path,
+    index_url: &str,
+    max_jobs: usize,
+    progress: &(dyn Fn(&str, &Progress) + Sync),
+) -> Result<BTreeMap<String, JobOutput>, ScheduleError> {
+    let shared = Mutex::new(Shared {
+        states: vec![State::Pending; jobs.len()],
+        sources: vec![None; jobs.len()],
+        outputs: BTreeMap::new(),
+        failures: BTreeMap::new(),
+        running: 0,
+    });
+    let changed = Condvar::new();
+    let workers = max_jobs.max(1).min(jobs.len());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| worker(jobs, build_dir, index_url, &shared, &changed, progress));
+        }
+    });
+
+    let shared = shared.into_inner().unwrap_or_else(|e| e.into_inner());
+    if shared.failures.is_empty() {
+        Ok(shared.outputs)
+    } else {
+        Err(ScheduleError {
+            failures: shared.failures,
+        })
+    }
+}
+
+fn worker(
+    jobs: &[Job],
+    build_dir: &Path,
+    index_url: &str,
+    shared: &Mutex<Shared>,
+    changed: &Condvar,
+    progress: &(dyn Fn(&str, &Progress) + Sync),
+) {
+    loop {
+        let task = {
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                if let Some(task) = next_task(jobs, &mut state, progress) {
+                    state.running += 1;
+                    break Some(task);
+                }
+                if state
+                    .states
+                    .iter()
+                    .all(|s| matches!(s, State::Done | State::Failed))
+                {
+                    break None;
+                }
+                state = changed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        let Some(task) = task else {
+            changed.notify_all();
+            return;
+        };
+
+        match task {
+            Task::Download(i) => {
+                let result = download(&jobs[i], build_dir);
+                let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+                match result {
+                    // Wheels need no build, they went straight into the cache
+                    Ok(Downloaded::Wheel(output)) => {
+                        state.states[i] = State::Done;
+                        state.outputs.insert(jobs[i].package.clone(), output);
+                        progress(&jobs[i].package, &Progress::Done);
+                    }
+                    Ok(Downloaded::Sdist(source)) => {
+                        state.states[i] = State::Downloaded;
+                        state.sources[i] = Some(source);
+                        progress(&jobs[i].package, &Progress::Downloaded);
+                    }
+                    Err(e) => fail(jobs, &mut state, i, e, progress),
+                }
+                state.running -= 1;
+            }
+            Task::Build(i) => {
+                let source = {
+                    let state = shared.lock().unwrap_or_else(|e| e.into_inner());
+                    state.sources[i].clone().unwrap_or_default()
+                };
+                let result = build(&jobs[i], &source, build_dir, index_url);
+                let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+                match result {
+                    Ok(output) => {
+                        state.states[i] = State::Done;
+                        state.outputs.insert(jobs[i].package.clone(), output);
+                        progress(&jobs[i].package, &Progress::Done);
+                    }
+                    Err(e) => fail(jobs, &mut state, i, e, progress),
+                }
+                state.running -= 1;
+            }
+        }
+        changed.notify_all();
+    }
+}
+
+fn next_task(
+    jobs: &[Job],
+    state: &mut Shared,
+    progress: &(dyn Fn(&str, &Progress) + Sync),
+) -> Option<Task> {
+    let index: BTreeMap<&str, usize> = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| (job.package.as_str(), i))
+        .collect();
+    let dependency_states = |i: usize, states: &[State]| -> Vec<(String, State)> {
+        jobs[i]
+            .depends_on
+            .iter()
+            .filter_map(|dep| index.get(dep.as_str()).map(|&j| (dep.clone(), states[j])))
+            .collect()
+    };
+
+    // Nothing that depends on a failed package is worth building
+    for i in 0..jobs.len() {
+        if state.states[i] != State::Downloaded {
+            continue;
+        }
+        if let Some((dep, _)) = dependency_states(i, &state.states)
+            .into_iter()
+            .find(|(_, s)| *s == State::Failed)
+        {
+            fail(
+                jobs,
+                state,
+                i,
+                format!("dependency {} failed", dep),
+                progress,
+            );
+        }
+    }
+
+    // Builds first, so a finished download doesn't wait behind the other downloads
+    let ready = (0..jobs.len()).find(|&i| {
+        state.states[i] == State::Downloaded
+            && dependency_states(i, &state.states)
+                .iter()
+                .all(|(_, s)| *s == State::Done)
+    });
+    if let Some(i) = ready {
+        state.states[i] = State::Building;
+        progress(&jobs[i].package, &Progress::Building);
+        return Some(Task::Build(i));
+    }
+
+    if let Some(i) = state.states.iter().position(|s| *s == State::Pending) {
+        state.states[i] = State::Downloading;
+        progress(&jobs[i].package, &Progress::Downloading);
+        return Some(Task::Download(i));
+    }
+
+    // Dependency cycles happen in Python; with nothing else left, build the
+    // package waiting on the fewest others, which is one inside the cycle
+    let waiting = (0..jobs.len())
+        .filter(|&i| state.states[i] == State::Downloaded)
+        .min_by_key(|&i| {
+            dependency_states(i, &state.states)
+                .iter()
+                .filter(|(_, s)| *s != State::Done)
+                .count()
+        });
+    if state.running == 0
+        && let Some(i) = waiting
+    {
+        state.states[i] = State::Building;
+        progress(&jobs[i].package, &Progress::Building);
+        return Some(Task::Build(i));
+    }
+
+    None
+}
+
+fn fail(
+    jobs: &[Job],
+    state: &mut Shared,
+    i: usize,
+    error: String,
+    progress: &(dyn Fn(&str, &Progress) + Sync),
+) {
+    state.states[i] = State::Failed;
+    progress(&jobs[i].package, &Progress::Failed(error.clone()));
+    state.failures.insert(jobs[i].package.clone(), error);
+}
+
+enum Downloaded {
+    Wheel(JobOutput),
+    Sdist(PathBuf),
+}
+
+fn download(job: &Job, build_dir: &Path) -> Result<Downloaded, String> {
+    if job.artifact.kind == DistributionKind::Wheel {
+        let wheel = download_artifact(&job.artifact, &job.cache_dir).map_err(|e| e.to_string())?;
+        let source_hash = format!("sha256:{}", sha256_file(&wheel).map_err(|e| e.to_string())?);
+        return Ok(Downloaded::Wheel(JobOutput { wheel, source_hash }));
+    }
+
+    // The sdist of a prebuilt wheel was downloaded (and verified) alongside it
+    let source = build_dir.join(&job.artifact.filename);
+    if job.prebuilt.is_some() && source.exists() {
+        return Ok(Downloaded::Sdist(source));
+    }
+    download_artifact(&job.artifact, build_dir)
+        .map(Downloaded::Sdist)
+        .map_err(|e| e.to_string())
+}
+
+fn build(job: &Job, source: &Path, build_dir: &Path, index_url: &str) -> Result<JobOutput, String> {
+    // Keep the source next to its wheel so install can check it against box.lock
+    let source_hash = format!("sha256:{}", sha256_file(source).map_err(|e| e.to_string())?);
+    fs::copy(source, job.cache_dir.join(&job.artifact.filename)).map_err(|e| e.to_string())?;
+
+    let wheel = match &job.prebuilt {
+        Some(wheel) => wheel.clone(),
+        None => build_sdist(source, &build_dir.join(&job.package), index_url)
+            .map_err(|e| e.to_string())?,
+    };
+    let wheel = move_wheel(&wheel, &job.cache_dir).map_err(|e| e.to_string())?;
+
+    Ok(JobOutput { wheel, source_hash })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::collections::BTreeSet;
+
+    // A job for `package` 1.0 served from a file:// url. Sdists come with a
+    // prebuilt wheel, so building one only moves that into the cache.
+    fn job(dir: &Path, package: &str, kind: DistributionKind, depends_on: &[&str]) -> Job {
+        let filename = match kind {
+            DistributionKind::Wheel => format!("{}-1.0-py3-none-any.whl", package),
+            DistributionKind::Sdist => format!("{}-1.0.tar.gz", package),
+        };
+        let index = dir.join("index");
+        fs::create_dir_all(&index).unwrap();
+        fs::write(index.join(&filename), format!("{} source", package)).unwrap();
+
+        let prebuilt = (kind == DistributionKind::Sdist).then(|| {
+            let wheel = dir
+                .join("prebuilt")
+                .join(package)
+                .join(format!("{}-1.0-py3-none-any.whl", package));
+            fs::create_dir_all(wheel.parent().unwrap()).unwrap();
+            fs::write(&wheel, format!("{} wheel", package)).unwrap();
+            wheel
+        });
+        let cache_dir = dir.join("cache").join(package);
+        fs::create_dir_all(&cache_dir).unwrap();
+
+        Job {
+            package: package.to_string(),
+            artifact: DistributionFile {
+                url: format!("file://{}/{}", index.display(), filename),
+                filename,
+                kind,
+                version: "1.0".parse().unwrap(),
+                hashes: BTreeMap::new(),
+                requires_python: None,
+                yanked: false,
+                core_metadata: false,
+            },
+            cache_dir,
+            depends_on: depends_on.iter().map(|dep| dep.to_string()).collect(),
+            prebuilt,
+        }
+    }
+
+    type Outputs = Result<BTreeMap<String, JobOutput>, ScheduleError>;
+
+    // Runs the jobs, returning the result and every progress report in order
+    fn run(dir: &Path, jobs: &[Job], max_jobs: usize) -> (Outputs, Vec<(String, Progress)>) {
+        let events = Mutex::new(Vec::new());
+        let build_dir = dir.join("build");
+        fs::create_dir_all(&build_dir).unwrap();
+        let result = run_jobs(
+            jobs,
+            &build_dir,
+            "https://pypi.org/simple/",
+            max_jobs,
+            &|package: &str, progress: &Progress| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push((package.to_string(), progress.clone()))
+            },
+        );
+        (result, events.into_inner().unwrap())
+    }
+
+    fn position(events: &[(String, Progress)], package: &str, progress: Progress) -> usize {
+        events
+            .iter()
+            .position(|(p, event)| p == package && *event == progress)
+            .unwrap_or_else(|| panic!("{} never got {:?}: {:?}", package, progress, events))
+    }
+
+    #[test]
+    fn builds_dependencies_first() {
+        let dir = TempDir::new("order");
+        let jobs = [
+            job(&dir.0, "app", DistributionKind::Sdist, &["lib"]),
+            job(&dir.0, "lib", DistributionKind::Sdist, &["base", "wheel"]),
+            job(&dir.0, "base", DistributionKind::Sdist, &[]),
+            job(&dir.0, "wheel", DistributionKind::Wheel, &[]),
+        ];
+        let (result, events) = run(&dir.0, &jobs, 4);
+        let outputs = result.unwrap();
+
+        for (package, dependency) in [("app", "lib"), ("lib", "base"), ("lib", "wheel")] {
+            assert!(
+                position(&events, dependency, Progress::Done)
+                    < position(&events, package, Progress::Building),
+                "{} built before {}: {:?}",
+                package,
+                dependency,
+                events
+            );
+        }
+        // Wheels aren't built
+        assert!(!events.contains(&("wheel".to_string(), Progress::Building)));
+
+        assert_eq!(outputs.len(), 4);
+        for job in &jobs {
+            let output = &outputs[&job.package];
+            assert_eq!(output.wheel.parent(), Some(job.cache_dir.as_path()));
+            assert!(output.wheel.is_file());
+            let source = job.cache_dir.join(&job.artifact.filename);
+            assert_eq!(
+                output.source_hash,
+                format!("sha256:{}", sha256_file(&source).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn a_failure_stops_only_its_dependents() {
+        let dir = TempDir::new("failure");
+        let mut broken = job(&dir.0, "broken", DistributionKind::Sdist, &[]);
+        broken.artifact.url = format!("file://{}/missing.tar.gz", dir.0.display());
+        let jobs = [
+            job(&dir.0, "app", DistributionKind::Sdist, &["broken", "lib"]),
+            broken,
+            job(&dir.0, "lib", DistributionKind::Sdist, &[]),
+            job(&dir.0, "tool", DistributionKind::Sdist, &["lib"]),
+        ];
+        let (result, events) = run(&dir.0, &jobs, 2);
+
+        let failures = result.unwrap_err().failures;
+        assert_eq!(
+            failures.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["app", "broken"]
+        );
+        assert_eq!(failures["app"], "dependency broken failed");
+        assert!(!events.contains(&("app".to_string(), Progress::Building)));
+        position(&events, "lib", Progress::Done);
+        position(&events, "tool", Progress::Done);
+    }
+
+    #[test]
+    fn a_dependency_cycle_still_finishes() {
+        let dir = TempDir::new("cycle");
+        let jobs = [
+            job(&dir.0, "a", DistributionKind::Sdist, &["b"]),
+            job(&dir.0, "b", DistributionKind::Sdist, &["a"]),
+            job(&dir.0, "c", DistributionKind::Sdist, &["a"]),
+        ];
+        let (result, events) = run(&dir.0, &jobs, 3);
+
+        let outputs = result.unwrap();
+        assert_eq!(outputs.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        // The cycle is broken one package at a time, then c follows a
+        let first = ["a", "b"]
+            .into_iter()
+            .min_by_key(|package| position(&events, package, Progress::Building))
+            .unwrap();
+        let second = if first == "a" { "b" } else { "a" };
+        assert!(
+            position(&events, first, Progress::Done)
+                < position(&events, second, Progress::Building)
+        );
+        assert!(
+            position(&events, "a", Progress::Done) < position(&events, "c", Progress::Building)
+        );
+    }
+
+    #[test]
+    fn runs_at_most_max_jobs_at_once() {
+        let dir = TempDir::new("limit");
+        let packages: Vec<String> = (0..8).map(|i| format!("pkg{}", i)).collect();
+        let jobs: Vec<Job> = packages
+            .iter()
+            .enumerate()
+            .map(|(i, package)| {
+                let kind = if i % 2 == 0 {
+                    DistributionKind::Sdist
+                } else {
+                    DistributionKind::Wheel
+                };
+                job(&dir.0, package, kind, &[])
+            })
+            .collect();
+
+        for max_jobs in [1, 2] {
+            for job in &jobs {
+                if let Some(wheel) = &job.prebuilt {
+                    fs::write(wheel, "wheel").unwrap();
+                }
+            }
+            let (result, events) = run(&dir.0, &jobs, max_jobs);
+            assert_eq!(result.unwrap().len(), jobs.len());
+
+            // Downloads and builds in flight, as the reports tell it
+            let mut running = 0;
+            let mut most = 0;
+            for (_, progress) in &events {
+                match progress {
+                    Progress::Downloading | Progress::Building => running += 1,
+                    Progress::Downloaded | Progress::Done | Progress::Failed(_) => running -= 1,
+                }
+                most = most.max(running);
+            }
+            assert!(most <= max_jobs, "{} at once with -j{}", most, max_jobs);
+
+            let done: BTreeSet<&str> = events
+                .iter()
+                .filter(|(_, progress)| *progress == Progress::Done)
+                .map(|(package, _)| package.as_str())
+                .collect();
+            assert_eq!(done.len(), jobs.len());
+        }
+    }
+}
@@ -1,14 +1,52 @@
 use std::process::Command;
 
-pub fn detect_platform() -> String {
-    let info = os_info::get();
-    let arch = info.architecture().unwrap_or("unknown");
-    println!("Type: {}", info.os_type());
-    // println!("Architecture: {}", info.architecture());
+use crate::markers::MarkerEnvironment;
+use serde::Deserialize;
 
-    let key = format!("{}-{}", info.os_type(), arch);
-    return key;
-}
+use crate::tags::{Tags, manylinux_platforms, musllinux_platforms};
+
+// Prints the PEP 508 marker variables as JSON, as `packaging.markers.default_environment` does
+const MARKER_ENVIRONMENT_SCRIPT: &str = r#"
+import json, os, platform, sys
+
+def format_full_version(info):
+    version = "{0.major}.{0.minor}.{0.micro}".format(info)
+    if info.releaselevel != "final":
+        version += info.releaselevel[0] + str(info.serial)
+    return version
+
+print(json.dumps({
+    "implementation_name": sys.implementation.name,
+    "implementation_version": format_full_version(sys.implementation.version),
+    "os_name": os.name,
+    "platform_machine": platform.machine(),
+    "platform_python_implementation": platform.python_implementation(),
+    "platform_release": platform.release(),
+    "platform_system": platform.system(),
+    "platform_version": platform.version(),
+    "python_full_version": platform.python_version(),
+    "python_version": ".".join(platform.python_version_tuple()[:2]),
+    "sys_platform": sys.platform,
+}))
+"#;
+
+// Prints what PEP 425 tags are built from, as `packaging.tags.sys_tags` reads them
+const TAGS_SCRIPT: &str = r#"
+import json, platform, sys, sysconfig
+
+print(json.dumps({
+    "implementation_name": sys.implementation.name,
+    "version": [sys.version_info[0], sys.version_info[1]],
+    "soabi": sysconfig.get_config_var("SOABI") or "",
+    "debug": bool(sysconfig.get_config_var("Py_DEBUG")) or hasattr(sys, "gettotalrefcount"),
+    "pymalloc": bool(sysconfig.get_config_var("WITH_PYMALLOC")),
+    "free_threaded": bool(sysconfig.get_config_var("Py_GIL_DISABLED")),
+    "platform": sysconfig.get_platform(),
+    "libc": list(platform.libc_ver()),
+    "mac_version": platform.mac_ver()[0],
+    "is_32bit": sys.maxsize <= 2**32,
+}))
+"#;
 
 pub fn detect_python_version() -> Option<String> {
     let output = Command::new("python3")
@@ -26,22 +64,66 @@ pub fn detect_python_version() -> Option<String> {
     }
 }
 
-pub fn detect_abi_tag() -> Option<String> {
+// Runs `script` with python3, or python, returning its stdout if it succeeded
+fn run_python(script: &str) -> Option<Vec<u8>> {
+    let output = Command::new("python3")
+        .args(["-c", script])
+        .output()
+        .or_else(|_| Command::new("python").args(["-c", script]).output())
+        .ok()?;
+
+    output.status.success().then_some(output.stdout)
+}
+
+pub fn detect_marker_environment(python_version: &str) -> MarkerEnvironment {
+    if let Some(stdout) = run_python(MARKER_ENVIRONMENT_SCRIPT)
+        && let Ok(env) = serde_json::from_slice::<MarkerEnvironment>(&stdout)
+    {
+        return env;
+    }
+
+    // No interpreter to ask, so approximate from what this binary was built for
+    println!("Failed to query the interpreter, guessing marker environment");
+    let short_version: Vec<&str> = python_version.split('.').take(2).collect();
+    let (os_name, sys_platform, platform_system) = match std::env::consts::OS {
+        "windows" => ("nt", "win32", "Windows"),
+        "macos" => ("posix", "darwin", "Darwin"),
+        "linux" => ("posix", "linux", "Linux"),
+        other => ("posix", other, other),
+    };
+
+    MarkerEnvironment {
+        implementation_name: "cpython".to_string(),
+        implementation_version: python_version.to_string(),
+        os_name: os_name.to_string(),
+        platform_machine: std::env::consts::ARCH.to_string(),
+        platform_python_implementation: "CPython".to_string(),
+        platform_release: String::new(),
+        platform_system: platform_system.to_string(),
+        platform_version: String::new(),
+        python_full_version: python_version.to_string(),
+        python_version: short_version.join("."),
+        sys_platform: sys_platform.to_string(),
+    }
+}
+
+/// The C compiler native extensions would be built with, e.g. "gcc13_2_0".
+pub fn detect_compiler() -> Option<String> {
     let try_gcc = Command::new("gcc").arg("-dumpfullversion").output().ok();
-    if let Some(output) = try_gcc {
-        if output.status.success() {
-            let ver = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            return Some(format!("gcc{}", ver.replace('.', "_")));
-        }
+    if let Some(output) = try_gcc
+        && output.status.success()
+    {
+        let ver = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return Some(format!("gcc{}", ver.replace('.', "_")));
     }
 
     let try_clang = Command::new("clang").arg("--version").output().ok();
-    if let Some(output) = try_clang {
-        if output.status.success() {
-            let version_line = String::from_utf8_lossy(&output.stdout);
-            if let Some(ver) = version_line.split_whitespace().nth(2) {
-                return Some(format!("clang{}", ver.replace('.', "_")));
-            }
+    if let Some(output) = try_clang
+        && output.status.success()
+    {
+        let version_line = String::from_utf8_lossy(&output.stdout);
+        if let Some(ver) = version_line.split_whitespace().nth(2) {
+            return Some(format!("clang{}", ver.replace('.', "_")));
         }
     }
 
@@ -58,3 +140,255 @@ pub fn detect_abi_tag() -> Option<String> {
 
     None
 }
+
+/// The PEP 425 identity of the target interpreter, before it is expanded into `Tags`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpreterTags {
+    pub implementation: String, // e.g. "cp", "pp"
+    pub python: (u32, u32),
+    pub abis: Vec<String>, // e.g. ["cp311", "abi3"], ["cp311d", "cp311", "abi3"]
+    pub platforms: Vec<String>, // e.g. ["manylinux_2_35_x86_64", ..., "linux_x86_64"]
+}
+
+impl InterpreterTags {
+    /// e.g. "cp311"
+    pub fn interpreter(&self) -> String {
+        format!("{}{}{}", self.implementation, self.python.0, self.python.1)
+    }
+
+    pub fn tags(&self) -> Tags {
+        Tags::from_interpreter(
+            &self.implementation,
+            self.python,
+            &self.abis,
+            &self.platforms,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct InterpreterInfo {
+    implementation_name: String,
+    version: (u32, u32),
+    soabi: String,
+    debug: bool,
+    pymalloc: bool,
+    free_threaded: bool,
+    platform: String,
+    libc: (String, String),
+    mac_version: String,
+    is_32bit: bool,
+}
+
+/// Asks the interpreter for its tags, falling back to what `markers` imply
+/// (CPython's plain ABI, and the libc `ldd` reports) when it can't be run.
+pub fn detect_interpreter_tags(markers: &MarkerEnvironment) -> InterpreterTags {
+    if let Some(stdout) = run_python(TAGS_SCRIPT)
+        && let Ok(info) = serde_json::from_slice::<InterpreterInfo>(&stdout)
+    {
+        return interpreter_tags(&info);
+    }
+
+    println!("Failed to query the interpreter, guessing wheel tags");
+    let implementation = short_implementation_name(&markers.implementation_name);
+    let mut version = markers
+        .python_version
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let python = (version.next().unwrap_or(3), version.next().unwrap_or(0));
+
+    let abis = if implementation == "cp" {
+        vec![format!("cp{}{}", python.0, python.1), "abi3".to_string()]
+    } else {
+        Vec::new()
+    };
+
+    let machine = markers.platform_machine.as_str();
+    let platforms = match markers.sys_platform.as_str() {
+        "linux" => linux_platforms(&normalize_arch(machine), None),
+        "darwin" => {
+            // Darwin 20 is macOS 11, before that Darwin N was macOS 10.(N - 4)
+            let darwin: u32 = markers
+                .platform_release
+                .split('.')
+                .next()
+                .and_then(|major| major.parse().ok())
+                .unwrap_or(20);
+            let macos = if darwin >= 20 {
+                (darwin - 9, 0)
+            } else {
+                (10, darwin.saturating_sub(4))
+            };
+            macos_platforms(macos, machine)
+        }
+        "win32" => match machine {
+            "AMD64" | "x86_64" => vec!["win_amd64".to_string()],
+            "ARM64" | "aarch64" => vec!["win_arm64".to_string()],
+            _ => vec!["win32".to_string()],
+        },
+        other => vec![format!("{}_{}", other, machine).replace(['.', '-', ' '], "_")],
+    };
+
+    InterpreterTags {
+        implementation: implementation.to_string(),
+        python,
+        abis,
+        platforms,
+    }
+}
+
+fn interpreter_tags(info: &InterpreterInfo) -> InterpreterTags {
+    let implementation = short_implementation_name(&info.implementation_name);
+    let (major, minor) = info.version;
+
+    let abis = if implementation == "cp" {
+        // Same flags as packaging's _cpython_abis: t(hreading), d(ebug), m (pymalloc, < 3.8)
+        let version = format!("{}{}", major, minor);
+        let threading = if info.free_threaded { "t" } else { "" };
+        let debug = if info.debug { "d" } else { "" };
+        let pymalloc = if info.pymalloc && (major, minor) < (3, 8) {
+            "m"
+        } else {
+            ""
+        };
+
+        let mut abis = vec![format!("cp{}{}{}{}", version, threading, debug, pymalloc)];
+        // Debug builds can load normal extension modules too
+        if info.debug {
+            abis.push(format!("cp{}{}", version, threading));
+        }
+        // The free-threaded build can't load the stable ABI
+        if !info.free_threaded {
+            abis.push("abi3".to_string());
+        }
+        abis
+    } else {
+        // e.g. "pypy310-pp73-x86_64-linux-gnu" -> "pypy310_pp73"
+        let parts: Vec<&str> = info.soabi.split('-').take(2).collect();
+        if info.soabi.is_empty() {
+            Vec::new()
+        } else {
+            vec![parts.join("_").replace('.', "_")]
+        }
+    };
+
+    // e.g. "linux-x86_64", "macosx-11.0-arm64", "win-amd64"
+    let platform = info.platform.as_str();
+    let platforms = if let Some(arch) = platform.strip_prefix("linux-") {
+        // A 32-bit interpreter on a 64-bit kernel
+        let arch = match (arch, info.is_32bit) {
+            ("x86_64", true) => "i686",
+            ("aarch64", true) => "armv7l",
+            _ => arch,
+        };
+        let glibc = match info.libc.0.as_str() {
+            "glibc" => parse_two(&info.libc.1),
+            _ => None,
+        };
+        linux_platforms(&normalize_arch(arch), glibc)
+    } else if let Some(rest) = platform.strip_prefix("macosx-") {
+        let arch = rest.rsplit('-').next().unwrap_or("x86_64");
+        let version = parse_two(&info.mac_version)
+            .or_else(|| parse_two(rest.split('-').next().unwrap_or_default()))
+            .unwrap_or((11, 0));
+        macos_platforms(version, arch)
+    } else {
+        vec![platform.replace(['.', '-'], "_")]
+    };
+
+    InterpreterTags {
+        implementation: implementation.to_string(),
+        python: (major, minor),
+        abis,
+        platforms,
+    }
+}
+
+fn short_implementation_name(name: &str) -> &'static str {
+    match name {
+        "cpython" => "cp",
+        "pypy" => "pp",
+        "ironpython" => "ip",
+        "jython" => "jy",
+        _ => "py",
+    }
+}
+
+fn normalize_arch(machine: &str) -> String {
+    match machine {
+        "amd64" | "AMD64" => "x86_64",
+        "i386" | "i586" => "i686",
+        "arm64" => "aarch64",
+        other => other,
+    }
+    .to_string()
+}
+
+// Python only reports glibc, so musl (and a glibc it didn't see) is read from ldd
+fn linux_platforms(arch: &str, glibc: Option<(u32, u32)>) -> Vec<String> {
+    match glibc.map(Libc::Glibc).or_else(detect_libc) {
+        Some(Libc::Glibc(version)) => manylinux_platforms(version, arch),
+        Some(Libc::Musl(version)) => musllinux_platforms(version, arch),
+        None => vec![format!("linux_{}", arch)],
+    }
+}
+
+// macOS 11+ wheels are tagged with the major version only, older ones with 10.x
+fn macos_platforms(version: (u32, u32), machine: &str) -> Vec<String> {
+    let (macos_major, macos_minor) = version;
+
+    let formats: &[&str] = if machine == "arm64" {
+        &["arm64", "universal2"]
+    } else {
+        &["x86_64", "intel", "universal2", "universal"]
+    };
+
+    let mut versions: Vec<(u32, u32)> = (11..=macos_major).rev().map(|major| (major, 0)).collect();
+    // Apple silicon never ran anything before 11
+    if machine != "arm64" {
+        let newest_10 = if macos_major > 10 { 16 } else { macos_minor };
+        versions.extend((4..=newest_10).rev().map(|minor| (10, minor)));
+    }
+
+    versions
+        .iter()
+        .flat_map(|(major, minor)| {
+            formats
+                .iter()
+                .map(move |format| format!("macosx_{}_{}_{}", major, minor, format))
+        })
+        .collect()
+}
+
+enum Libc {
+    Glibc((u32, u32)),
+    Musl((u32, u32)),
+}
+
+// `ldd --version` names the C library on both glibc (stdout) and musl (stderr)
+fn detect_libc() -> Option<Libc> {
+    let output = Command::new("ldd").arg("--version").output().ok()?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    if text.to_ascii_lowercase().contains("musl") {
+        let version = text
+            .lines()
+            .find_map(|line| line.strip_prefix("Version "))
+            .and_then(|version| parse_two(version.trim()))?;
+        return Some(Libc::Musl(version));
+    }
+
+    // e.g. "ldd (Ubuntu GLIBC 2.35-0ubuntu3.6) 2.35"
+    let first_line = text.lines().next()?;
+    parse_two(first_line.split_whitespace().last()?).map(Libc::Glibc)
+}
+
+// "2.35" -> (2, 35)
+fn parse_two(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
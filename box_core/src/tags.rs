@@ -0,0 +1,375 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// One PEP 425 compatibility tag, e.g. `cp311-cp311-manylinux_2_17_x86_64`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tag {
+    pub interpreter: String, // e.g. "cp311", "py3"
+    pub abi: String,         // e.g. "cp311", "abi3", "none"
+    pub platform: String,    // e.g. "manylinux_2_17_x86_64", "win_amd64", "any"
+}
+
+impl Tag {
+    pub fn new(interpreter: &str, abi: &str, platform: &str) -> Tag {
+        Tag {
+            interpreter: interpreter.to_string(),
+            abi: abi.to_string(),
+            platform: platform.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.interpreter, self.abi, self.platform)
+    }
+}
+
+/// The tags an interpreter can install, most preferred first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Tags {
+    tags: Vec<Tag>,
+}
+
+impl Tags {
+    pub fn new(tags: Vec<Tag>) -> Tags {
+        Tags { tags }
+    }
+
+    /// Orders tags the way `packaging.tags.sys_tags` does. `implementation` is the
+    /// short interpreter name ("cp", "pp"), `abis` the ABIs it can load, most
+    /// preferred first (e.g. "cp311", "abi3"), and `platforms` the platform tags,
+    /// most specific first.
+    pub fn from_interpreter(
+        implementation: &str,
+        python: (u32, u32),
+        abis: &[String],
+        platforms: &[String],
+    ) -> Tags {
+        let (major, minor) = python;
+        let interpreter = format!("{}{}{}", implementation, major, minor);
+        let mut tags = Vec::new();
+
+        for abi in abis {
+            for platform in platforms {
+                tags.push(Tag::new(&interpreter, abi, platform));
+            }
+        }
+        for platform in platforms {
+            tags.push(Tag::new(&interpreter, "none", platform));
+        }
+        // An interpreter that loads the stable ABI loads any older abi3 wheel too
+        if abis.iter().any(|abi| abi == "abi3") {
+            for older in (2..minor).rev() {
+                for platform in platforms {
+                    tags.push(Tag::new(&format!("cp3{}", older), "abi3", platform));
+                }
+            }
+        }
+
+        // Pure python wheels, e.g. py311, py3, py310 ... py30
+        let mut py_versions = vec![format!("py{}{}", major, minor), format!("py{}", major)];
+        py_versions.extend(
+            (0..minor)
+                .rev()
+                .map(|older| format!("py{}{}", major, older)),
+        );
+
+        for py in &py_versions {
+            for platform in platforms {
+                tags.push(Tag::new(py, "none", platform));
+            }
+        }
+        tags.push(Tag::new(&interpreter, "none", "any"));
+        for py in &py_versions {
+            tags.push(Tag::new(py, "none", "any"));
+        }
+
+        Tags { tags }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tag> {
+        self.tags.iter()
+    }
+
+    /// Lower is better; None when no tag of the wheel is supported here.
+    pub fn priority(&self, wheel: &WheelFilename) -> Option<usize> {
+        wheel
+            .tags()
+            .filter_map(|tag| {
+                self.tags.iter().position(|supported| {
+                    supported.interpreter == tag.interpreter
+                        && supported.abi == tag.abi
+                        && normalize_platform(&supported.platform)
+                            == normalize_platform(&tag.platform)
+                })
+            })
+            .min()
+    }
+
+    pub fn is_compatible(&self, wheel: &WheelFilename) -> bool {
+        self.priority(wheel).is_some()
+    }
+}
+
+/// The parts of `{name}-{version}(-{build})?-{python}-{abi}-{platform}.whl`.
+/// Each tag part may be a compressed set such as `py2.py3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WheelFilename {
+    pub name: String,
+    pub version: String,
+    pub build: Option<String>,
+    pub python_tags: Vec<String>,
+    pub abi_tags: Vec<String>,
+    pub platform_tags: Vec<String>,
+}
+
+impl WheelFilename {
+    /// Every tag the wheel claims, expanding the compressed sets.
+    pub fn tags(&self) -> impl Iterator<Item = Tag> + '_ {
+        self.python_tags.iter().flat_map(move |python| {
+            self.abi_tags.iter().flat_map(move |abi| {
+                self.platform_tags
+                    .iter()
+                    .map(move |platform| Tag::new(python, abi, platform))
+            })
+        })
+    }
+}
+
+impl FromStr for WheelFilename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stem = s
+            .strip_suffix(".whl")
+            .ok_or_else(|| format!("Not a wheel filename: {}", s))?;
+        let parts: Vec<&str> = stem.split('-').collect();
+
+        let (name, version, build, tags) = match parts.as_slice() {
+            [name, version, python, abi, platform] => {
+                (name, version, None, [python, abi, platform])
+            }
+            [name, version, build, python, abi, platform] => (
+                name,
+                version,
+                Some(build.to_string()),
+                [python, abi, platform],
+            ),
+            _ => return Err(format!("Invalid wheel filename: {}", s)),
+        };
+        let [python, abi, platform] =
+            tags.map(|part| part.split('.').map(str::to_string).collect());
+
+        Ok(WheelFilename {
+            name: name.to_string(),
+            version: version.to_string(),
+            build,
+            python_tags: python,
+            abi_tags: abi,
+            platform_tags: platform,
+        })
+    }
+}
+
+/// Spells the PEP 513/571/599 manylinux aliases as their PEP 600 equivalents,
+/// e.g. `manylinux2014_x86_64` as `manylinux_2_17_x86_64`.
+pub fn normalize_platform(platform: &str) -> String {
+    for (legacy, glibc) in [
+        ("manylinux1_", "manylinux_2_5_"),
+        ("manylinux2010_", "manylinux_2_12_"),
+        ("manylinux2014_", "manylinux_2_17_"),
+    ] {
+        if let Some(arch) = platform.strip_prefix(legacy) {
+            return format!("{}{}", glibc, arch);
+        }
+    }
+    platform.to_string()
+}
+
+/// `manylinux_2_{minor}_{arch}` from the given glibc down to the oldest one
+/// manylinux ever targeted for `arch`, then the bare `linux_{arch}`.
+pub fn manylinux_platforms(glibc: (u32, u32), arch: &str) -> Vec<String> {
+    let (major, minor) = glibc;
+    // manylinux1 (glibc 2.5) only ever existed for x86
+    let oldest = if matches!(arch, "x86_64" | "i686") {
+        5
+    } else {
+        17
+    };
+
+    let mut platforms: Vec<String> = if major == 2 {
+        (oldest..=minor)
+            .rev()
+            .map(|minor| format!("manylinux_2_{}_{}", minor, arch))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    platforms.push(format!("linux_{}", arch));
+    platforms
+}
+
+/// `musllinux_1_{minor}_{arch}` from the given musl down to 1.0, then `linux_{arch}`.
+pub fn musllinux_platforms(musl: (u32, u32), arch: &str) -> Vec<String> {
+    let (major, minor) = musl;
+    let mut platforms: Vec<String> = if major == 1 {
+        (0..=minor)
+            .rev()
+            .map(|minor| format!("musllinux_1_{}_{}", minor, arch))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    platforms.push(format!("linux_{}", arch));
+    platforms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wheel(filename: &str) -> WheelFilename {
+        filename.parse().unwrap()
+    }
+
+    // A CPython 3.11 on x86_64 Linux with glibc 2.17
+    fn cp311_linux() -> Tags {
+        Tags::new(vec![
+            Tag::new("cp311", "cp311", "manylinux_2_17_x86_64"),
+            Tag::new("cp311", "cp311", "linux_x86_64"),
+            Tag::new("cp311", "abi3", "manylinux_2_17_x86_64"),
+            Tag::new("cp311", "none", "manylinux_2_17_x86_64"),
+            Tag::new("py3", "none", "manylinux_2_17_x86_64"),
+            Tag::new("cp311", "none", "any"),
+            Tag::new("py3", "none", "any"),
+        ])
+    }
+
+    #[test]
+    fn parses_wheel_filenames() {
+        let parsed =
+            wheel("numpy-1.26.4-cp311-cp311-manylinux_2_17_x86_64.manylinux2014_x86_64.whl");
+        assert_eq!(parsed.name, "numpy");
+        assert_eq!(parsed.version, "1.26.4");
+        assert_eq!(parsed.build, None);
+        assert_eq!(parsed.platform_tags.len(), 2);
+
+        let parsed = wheel("six-1.16.0-1-py2.py3-none-any.whl");
+        assert_eq!(parsed.build.as_deref(), Some("1"));
+        assert_eq!(parsed.python_tags, ["py2", "py3"]);
+        let tags: Vec<String> = parsed.tags().map(|t| t.to_string()).collect();
+        assert_eq!(tags, ["py2-none-any", "py3-none-any"]);
+
+        for bad in [
+            "six-1.16.0.tar.gz",
+            "six-py3-none-any.whl",
+            "a-1-2-3-4-5-6.whl",
+        ] {
+            assert!(bad.parse::<WheelFilename>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn ranks_wheels_by_tag_priority() {
+        let tags = cp311_linux();
+        let native = wheel("pkg-1.0-cp311-cp311-manylinux_2_17_x86_64.whl");
+        let stable_abi = wheel("pkg-1.0-cp37-abi3-manylinux_2_17_x86_64.whl");
+        let stable_abi_here = wheel("pkg-1.0-cp311-abi3-manylinux_2_17_x86_64.whl");
+        let pure = wheel("pkg-1.0-py3-none-any.whl");
+
+        assert_eq!(tags.priority(&native), Some(0));
+        assert!(tags.priority(&stable_abi_here) < tags.priority(&pure));
+        // Only the tags listed count, so cp37-abi3 isn't taken here
+        assert_eq!(tags.priority(&stable_abi), None);
+
+        // A wheel ranks by its best tag
+        let multi = wheel("pkg-1.0-py2.py3-none-any.whl");
+        assert_eq!(tags.priority(&multi), tags.priority(&pure));
+    }
+
+    #[test]
+    fn rejects_incompatible_wheels() {
+        let tags = cp311_linux();
+        for filename in [
+            "pkg-1.0-cp310-cp310-manylinux_2_17_x86_64.whl",
+            "pkg-1.0-cp311-cp311-win_amd64.whl",
+            "pkg-1.0-cp311-cp311-manylinux_2_17_aarch64.whl",
+            "pkg-1.0-py2-none-any.whl",
+        ] {
+            assert!(!tags.is_compatible(&wheel(filename)), "{}", filename);
+        }
+    }
+
+    #[test]
+    fn orders_interpreter_tags_like_packaging() {
+        let abis = ["cp311".to_string(), "abi3".to_string()];
+        let platforms = [
+            "manylinux_2_17_x86_64".to_string(),
+            "linux_x86_64".to_string(),
+        ];
+        let tags = Tags::from_interpreter("cp", (3, 11), &abis, &platforms);
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+
+        assert_eq!(
+            tags[..7],
+            [
+                "cp311-cp311-manylinux_2_17_x86_64",
+                "cp311-cp311-linux_x86_64",
+                "cp311-abi3-manylinux_2_17_x86_64",
+                "cp311-abi3-linux_x86_64",
+                "cp311-none-manylinux_2_17_x86_64",
+                "cp311-none-linux_x86_64",
+                "cp310-abi3-manylinux_2_17_x86_64",
+            ]
+        );
+        let position = |tag: &str| tags.iter().position(|t| t == tag).unwrap();
+        assert!(position("cp32-abi3-linux_x86_64") < position("py311-none-manylinux_2_17_x86_64"));
+        assert!(position("py3-none-linux_x86_64") < position("py310-none-manylinux_2_17_x86_64"));
+        assert!(position("py30-none-linux_x86_64") < position("cp311-none-any"));
+        assert_eq!(
+            tags[tags.len() - 14..tags.len() - 12],
+            ["cp311-none-any", "py311-none-any"]
+        );
+        assert_eq!(tags.last().unwrap(), "py30-none-any");
+        assert!(!tags.iter().any(|t| t == "cp311-abi3-any"));
+    }
+
+    #[test]
+    fn lists_manylinux_platforms_down_to_the_oldest() {
+        assert_eq!(
+            manylinux_platforms((2, 7), "x86_64"),
+            [
+                "manylinux_2_7_x86_64",
+                "manylinux_2_6_x86_64",
+                "manylinux_2_5_x86_64",
+                "linux_x86_64",
+            ]
+        );
+        let aarch64 = manylinux_platforms((2, 31), "aarch64");
+        assert_eq!(aarch64.first().unwrap(), "manylinux_2_31_aarch64");
+        assert_eq!(aarch64[aarch64.len() - 2], "manylinux_2_17_aarch64");
+        assert_eq!(manylinux_platforms((3, 1), "x86_64"), ["linux_x86_64"]);
+        assert_eq!(
+            musllinux_platforms((1, 1), "x86_64"),
+            [
+                "musllinux_1_1_x86_64",
+                "musllinux_1_0_x86_64",
+                "linux_x86_64"
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_manylinux_names_match_their_glibc() {
+        let tags = cp311_linux();
+        assert_eq!(
+            normalize_platform("manylinux2014_x86_64"),
+            "manylinux_2_17_x86_64"
+        );
+        assert_eq!(normalize_platform("manylinux1_i686"), "manylinux_2_5_i686");
+        assert_eq!(normalize_platform("win_amd64"), "win_amd64");
+        assert!(tags.is_compatible(&wheel("pkg-1.0-cp311-cp311-manylinux2014_x86_64.whl")));
+        assert!(!tags.is_compatible(&wheel("pkg-1.0-cp311-cp311-manylinux2010_x86_64.whl")));
+    }
+}
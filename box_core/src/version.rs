@@ -0,0 +1,708 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PreRelease {
+    Alpha,
+    Beta,
+    Rc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LocalSegment {
+    // Alphanumeric segments sort before numeric ones
+    Alpha(String),
+    Number(u64),
+}
+
+/// A PEP 440 version, e.g. "1!2.0.1rc1.post2.dev3+ubuntu.1".
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub epoch: u64,
+    pub release: Vec<u64>,
+    pub pre: Option<(PreRelease, u64)>,
+    pub post: Option<u64>,
+    pub dev: Option<u64>,
+    pub local: Option<Vec<LocalSegment>>,
+}
+
+// Sort position of the pre-release part, see PEP 440 "Summary of permitted suffixes"
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum PreKey {
+    DevOfFinal,
+    Pre(PreRelease, u64),
+    Final,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum DevKey {
+    Dev(u64),
+    NotDev,
+}
+
+impl Version {
+    pub fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    pub fn is_postrelease(&self) -> bool {
+        self.post.is_some()
+    }
+
+    /// The version with its local label dropped, which is what specifiers compare against.
+    pub fn public(&self) -> Version {
+        Version {
+            local: None,
+            ..self.clone()
+        }
+    }
+
+    /// Epoch and release only, e.g. "1.2.0" for "1.2.0rc1.post3".
+    pub fn base(&self) -> Version {
+        Version {
+            epoch: self.epoch,
+            release: self.release.clone(),
+            pre: None,
+            post: None,
+            dev: None,
+            local: None,
+        }
+    }
+
+    fn trimmed_release(&self) -> &[u64] {
+        let len = self
+            .release
+            .iter()
+            .rposition(|part| *part != 0)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        &self.release[..len]
+    }
+
+    fn pre_key(&self) -> PreKey {
+        match self.pre {
+            Some((kind, n)) => PreKey::Pre(kind, n),
+            None if self.post.is_none() && self.dev.is_some() => PreKey::DevOfFinal,
+            None => PreKey::Final,
+        }
+    }
+
+    fn dev_key(&self) -> DevKey {
+        match self.dev {
+            Some(n) => DevKey::Dev(n),
+            None => DevKey::NotDev,
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| self.trimmed_release().cmp(other.trimmed_release()))
+            .then_with(|| self.pre_key().cmp(&other.pre_key()))
+            .then_with(|| self.post.cmp(&other.post))
+            .then_with(|| self.dev_key().cmp(&other.dev_key()))
+            .then_with(|| self.local.cmp(&other.local))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl Hash for Version {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Must agree with `Eq`, where "1.0" and "1.0.0" are the same version
+        self.epoch.hash(state);
+        self.trimmed_release().hash(state);
+        self.pre.hash(state);
+        self.post.hash(state);
+        self.dev.hash(state);
+        self.local.hash(state);
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch != 0 {
+            write!(f, "{}!", self.epoch)?;
+        }
+
+        let release: Vec<String> = self.release.iter().map(|n| n.to_string()).collect();
+        write!(f, "{}", release.join("."))?;
+
+        if let Some((kind, n)) = self.pre {
+            let tag = match kind {
+                PreRelease::Alpha => "a",
+                PreRelease::Beta => "b",
+                PreRelease::Rc => "rc",
+            };
+            write!(f, "{}{}", tag, n)?;
+        }
+        if let Some(n) = self.post {
+            write!(f, ".post{}", n)?;
+        }
+        if let Some(n) = self.dev {
+            write!(f, ".dev{}", n)?;
+        }
+        if let Some(local) = &self.local {
+            let segments: Vec<String> = local
+                .iter()
+                .map(|segment| match segment {
+                    LocalSegment::Alpha(s) => s.clone(),
+                    LocalSegment::Number(n) => n.to_string(),
+                })
+                .collect();
+            write!(f, "+{}", segments.join("."))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().to_ascii_lowercase();
+        let mut cursor = Cursor {
+            input: normalized.strip_prefix('v').unwrap_or(&normalized),
+            pos: 0,
+        };
+        let invalid = || format!("Invalid version: {}", s);
+
+        let first = cursor.number().ok_or_else(invalid)?;
+        let (epoch, mut release) = if cursor.eat("!") {
+            (first, vec![cursor.number().ok_or_else(invalid)?])
+        } else {
+            (0, vec![first])
+        };
+
+        while cursor.peek_is(".") && cursor.peek_digit_at(1) {
+            cursor.eat(".");
+            release.push(cursor.number().ok_or_else(invalid)?);
+        }
+
+        let mut version = Version {
+            epoch,
+            release,
+            pre: None,
+            post: None,
+            dev: None,
+            local: None,
+        };
+
+        // Pre-release, with the alternate spellings PEP 440 normalizes
+        let save = cursor.pos;
+        cursor.separator();
+        let pre_kind = [
+            ("preview", PreRelease::Rc),
+            ("alpha", PreRelease::Alpha),
+            ("beta", PreRelease::Beta),
+            ("pre", PreRelease::Rc),
+            ("rc", PreRelease::Rc),
+            ("a", PreRelease::Alpha),
+            ("b", PreRelease::Beta),
+            ("c", PreRelease::Rc),
+        ]
+        .iter()
+        .find(|(tag, _)| cursor.eat(tag))
+        .map(|(_, kind)| *kind);
+        match pre_kind {
+            Some(kind) => version.pre = Some((kind, cursor.optional_number())),
+            None => cursor.pos = save,
+        }
+
+        // Post-release, including the implicit "1.0-1" form
+        let save = cursor.pos;
+        if cursor.eat("-") && cursor.peek_digit_at(0) {
+            version.post = cursor.number();
+        } else {
+            cursor.pos = save;
+            cursor.separator();
+            if ["post", "rev", "r"].iter().any(|tag| cursor.eat(tag)) {
+                version.post = Some(cursor.optional_number());
+            } else {
+                cursor.pos = save;
+            }
+        }
+
+        let save = cursor.pos;
+        cursor.separator();
+        if cursor.eat("dev") {
+            version.dev = Some(cursor.optional_number());
+        } else {
+            cursor.pos = save;
+        }
+
+        if cursor.eat("+") {
+            let label = cursor.rest();
+            let segments: Vec<LocalSegment> = label
+                .split(['.', '-', '_'])
+                .map(|part| match part.parse::<u64>() {
+                    Ok(n) => LocalSegment::Number(n),
+                    Err(_) => LocalSegment::Alpha(part.to_string()),
+                })
+                .collect();
+            let valid = segments.iter().all(|segment| match segment {
+                LocalSegment::Alpha(part) => {
+                    !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric())
+                }
+                LocalSegment::Number(_) => true,
+            });
+            if !valid {
+                return Err(invalid());
+            }
+            version.local = Some(segments);
+            cursor.pos = cursor.input.len();
+        }
+
+        if !cursor.rest().is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(version)
+    }
+}
+
+struct Cursor<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek_is(&self, token: &str) -> bool {
+        self.rest().starts_with(token)
+    }
+
+    fn peek_digit_at(&self, offset: usize) -> bool {
+        self.rest()
+            .as_bytes()
+            .get(offset)
+            .map(|b| b.is_ascii_digit())
+            .unwrap_or(false)
+    }
+
+    fn separator(&mut self) {
+        if self.rest().starts_with(['.', '-', '_']) {
+            self.pos += 1;
+        }
+    }
+
+    fn number(&mut self) -> Option<u64> {
+        let len = self
+            .rest()
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return None;
+        }
+        let n = self.rest()[..len].parse().ok()?;
+        self.pos += len;
+        Some(n)
+    }
+
+    // "1.0a" and "1.0.post" imply a number of 0
+    fn optional_number(&mut self) -> u64 {
+        let save = self.pos;
+        self.separator();
+        match self.number() {
+            Some(n) => n,
+            None => {
+                self.pos = save;
+                0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Compatible,     // ~=
+    Equal,          // ==
+    NotEqual,       // !=
+    LessEqual,      // <=
+    GreaterEqual,   // >=
+    Less,           // <
+    Greater,        // >
+    ArbitraryEqual, // ===
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Compatible => "~=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::LessEqual => "<=",
+            Operator::GreaterEqual => ">=",
+            Operator::Less => "<",
+            Operator::Greater => ">",
+            Operator::ArbitraryEqual => "===",
+        }
+    }
+}
+
+/// A single version clause such as ">=1.2" or "==3.1.*".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Specifier {
+    pub operator: Operator,
+    pub version: Version,
+    pub wildcard: bool,
+    raw_version: String,
+}
+
+impl Specifier {
+    pub fn contains(&self, candidate: &Version) -> bool {
+        // Local labels are ignored unless the specifier itself pins one
+        let public = candidate.public();
+
+        match self.operator {
+            Operator::Compatible => {
+                let prefix = &self.version.release[..self.version.release.len() - 1];
+                public >= self.version
+                    && release_prefix_matches(candidate, self.version.epoch, prefix)
+            }
+            Operator::Equal => self.equals(candidate),
+            Operator::NotEqual => !self.equals(candidate),
+            Operator::LessEqual => public <= self.version,
+            Operator::GreaterEqual => public >= self.version,
+            Operator::Less => {
+                // "<3.0" must not admit "3.0rc1"
+                public < self.version
+                    && !(!self.version.is_prerelease()
+                        && candidate.is_prerelease()
+                        && candidate.base() == self.version.base())
+            }
+            Operator::Greater => {
+                // ">3.0" must not admit "3.0.post1" or "3.0+local"
+                public > self.version
+                    && !(!self.version.is_postrelease()
+                        && candidate.is_postrelease()
+                        && candidate.base() == self.version.base())
+            }
+            Operator::ArbitraryEqual => candidate.to_string() == self.raw_version,
+        }
+    }
+
+    fn equals(&self, candidate: &Version) -> bool {
+        if self.wildcard {
+            release_prefix_matches(candidate, self.version.epoch, &self.version.release)
+        } else if self.version.local.is_some() {
+            *candidate == self.version
+        } else {
+            candidate.public() == self.version
+        }
+    }
+}
+
+fn release_prefix_matches(candidate: &Version, epoch: u64, prefix: &[u64]) -> bool {
+    if candidate.epoch != epoch {
+        return false;
+    }
+    prefix
+        .iter()
+        .enumerate()
+        .all(|(i, part)| candidate.release.get(i).copied().unwrap_or(0) == *part)
+}
+
+impl fmt::Display for Specifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.operator.as_str(), self.raw_version)
+    }
+}
+
+impl FromStr for Specifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let operators = [
+            Operator::ArbitraryEqual,
+            Operator::Compatible,
+            Operator::Equal,
+            Operator::NotEqual,
+            Operator::LessEqual,
+            Operator::GreaterEqual,
+            Operator::Less,
+            Operator::Greater,
+        ];
+        let operator = operators
+            .into_iter()
+            .find(|op| s.starts_with(op.as_str()))
+            .ok_or_else(|| format!("Invalid specifier (missing operator): {}", s))?;
+        let raw_version = s[operator.as_str().len()..].trim().to_string();
+
+        if operator == Operator::ArbitraryEqual {
+            // "===" compares strings, so the version doesn't have to be PEP 440 valid
+            let version = raw_version.parse().unwrap_or(Version {
+                epoch: 0,
+                release: vec![0],
+                pre: None,
+                post: None,
+                dev: None,
+                local: None,
+            });
+            return Ok(Specifier {
+                operator,
+                version,
+                wildcard: false,
+                raw_version,
+            });
+        }
+
+        let (version_str, wildcard) = match raw_version.strip_suffix(".*") {
+            Some(prefix) => (prefix, true),
+            None => (raw_version.as_str(), false),
+        };
+
+        if wildcard && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+            return Err(format!("Wildcards are only allowed with == and !=: {}", s));
+        }
+
+        let version: Version = version_str.parse()?;
+
+        if operator == Operator::Compatible && version.release.len() < 2 {
+            return Err(format!("~= requires at least two release segments: {}", s));
+        }
+        if version.local.is_some() && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+            return Err(format!(
+                "Local versions are only allowed with == and !=: {}",
+                s
+            ));
+        }
+
+        Ok(Specifier {
+            operator,
+            version,
+            wildcard,
+            raw_version,
+        })
+    }
+}
+
+/// A comma separated list of specifiers that must all match, e.g. ">=1.0,<2".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SpecifierSet {
+    pub specifiers: Vec<Specifier>,
+}
+
+impl SpecifierSet {
+    pub fn is_empty(&self) -> bool {
+        self.specifiers.is_empty()
+    }
+
+    /// Whether `version` satisfies every specifier, regardless of pre-release status.
+    pub fn contains(&self, version: &Version) -> bool {
+        self.specifiers.iter().all(|spec| spec.contains(version))
+    }
+
+    /// Like `contains`, but pre-releases only match when a specifier
+    /// explicitly names one (e.g. ">=2.0b1"), as pip does.
+    pub fn matches(&self, version: &Version) -> bool {
+        if version.is_prerelease() && !self.mentions_prerelease() {
+            return false;
+        }
+        self.contains(version)
+    }
+
+    pub fn mentions_prerelease(&self) -> bool {
+        self.specifiers
+            .iter()
+            .any(|spec| spec.operator != Operator::NotEqual && spec.version.is_prerelease())
+    }
+}
+
+impl fmt::Display for SpecifierSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.specifiers.iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl FromStr for SpecifierSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // "*" is how an unconstrained dependency is written in the manifest
+        if s.is_empty() || s == "*" {
+            return Ok(SpecifierSet::default());
+        }
+
+        let specifiers = s
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<Specifier>, String>>()?;
+
+        Ok(SpecifierSet { specifiers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    fn set(s: &str) -> SpecifierSet {
+        s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+    }
+
+    #[test]
+    fn orders_versions_as_pep_440_does() {
+        // The ordering example from PEP 440, plus epochs and local labels
+        let ordered = [
+            "1.0.dev456",
+            "1.0a1",
+            "1.0a2.dev456",
+            "1.0a12.dev456",
+            "1.0a12",
+            "1.0b1.dev456",
+            "1.0b2",
+            "1.0b2.post345.dev456",
+            "1.0b2.post345",
+            "1.0rc1.dev456",
+            "1.0rc1",
+            "1.0",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+5",
+            "1.0.post456.dev34",
+            "1.0.post456",
+            "1.1.dev1",
+            "2.0",
+            "1!0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn trailing_zeros_are_equal() {
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert_eq!(v("1"), v("1.0.0.0"));
+        let mut seen = std::collections::HashSet::new();
+        seen.insert(v("2.0"));
+        assert!(seen.contains(&v("2")));
+    }
+
+    #[test]
+    fn normalizes_spellings() {
+        assert_eq!(v("1.0-alpha1").to_string(), "1.0a1");
+        assert_eq!(v("1.0.PREVIEW.2").to_string(), "1.0rc2");
+        assert_eq!(v("v1.0c1").to_string(), "1.0rc1");
+        assert_eq!(v("1.0-1").to_string(), "1.0.post1");
+        assert_eq!(v("1.0.post").to_string(), "1.0.post0");
+        assert_eq!(v("1.0-dev").to_string(), "1.0.dev0");
+        assert_eq!(v("1.0+Ubuntu-1").to_string(), "1.0+ubuntu.1");
+        assert_eq!(v(" 2!3.4 ").to_string(), "2!3.4");
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for bad in ["", "abc", "1.0.", "1..0", "1.0+", "1.0b2-foo", "latest"] {
+            assert!(bad.parse::<Version>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn compatible_release() {
+        let spec = set("~=2.2");
+        assert!(spec.contains(&v("2.2")));
+        assert!(spec.contains(&v("2.9.1")));
+        assert!(!spec.contains(&v("3.0")));
+        assert!(!spec.contains(&v("2.1")));
+
+        let spec = set("~=1.4.5");
+        assert!(spec.contains(&v("1.4.9")));
+        assert!(!spec.contains(&v("1.5.0")));
+
+        assert!("~=1".parse::<Specifier>().is_err());
+    }
+
+    #[test]
+    fn exclusive_comparisons_skip_pre_and_post_releases_of_the_bound() {
+        assert!(!set("<3.0").contains(&v("3.0rc1")));
+        assert!(!set("<3.0").contains(&v("3.0.dev1")));
+        assert!(set("<3.0").contains(&v("2.9rc1")));
+        assert!(set("<3.0rc2").contains(&v("3.0rc1")));
+
+        assert!(!set(">3.0").contains(&v("3.0.post1")));
+        assert!(!set(">3.0").contains(&v("3.0+local")));
+        assert!(set(">3.0").contains(&v("3.0.1")));
+        assert!(set(">3.0.post1").contains(&v("3.0.post2")));
+    }
+
+    #[test]
+    fn equality_wildcards_and_local_labels() {
+        assert!(set("==1.1.*").contains(&v("1.1.post1")));
+        assert!(set("==1.1.*").contains(&v("1.1a1")));
+        assert!(!set("==1.1.*").contains(&v("1.10")));
+        assert!(set("!=1.1.*").contains(&v("1.2")));
+        assert!(!set("!=1.1.*").contains(&v("1.1.3")));
+
+        // Local labels only count when the specifier has one
+        assert!(set("==1.0").contains(&v("1.0+local.7")));
+        assert!(set("==1.0+local.7").contains(&v("1.0+local.7")));
+        assert!(!set("==1.0+local.7").contains(&v("1.0+local.8")));
+        assert!(">=1.0+local".parse::<Specifier>().is_err());
+        assert!(">=1.*".parse::<Specifier>().is_err());
+
+        assert!(!set("===1.0-foo").contains(&v("1.0")));
+        assert!(set("===1.0").contains(&v("1.0")));
+    }
+
+    #[test]
+    fn pre_releases_match_only_when_asked_for() {
+        assert!(!set(">=1.0").matches(&v("2.0b1")));
+        assert!(set(">=1.0").contains(&v("2.0b1")));
+        assert!(set(">=2.0b1").matches(&v("2.0b2")));
+        assert!(set("==2.0.dev3").matches(&v("2.0.dev3")));
+        assert!(!set("!=2.0b1").mentions_prerelease());
+    }
+
+    #[test]
+    fn specifier_sets() {
+        let spec = set(">=1.0, <2, !=1.5");
+        assert_eq!(spec.specifiers.len(), 3);
+        assert!(spec.contains(&v("1.4")));
+        assert!(!spec.contains(&v("1.5")));
+        assert!(!spec.contains(&v("2.0")));
+        assert_eq!(spec.to_string(), ">=1.0,<2,!=1.5");
+
+        assert!(set("").is_empty());
+        assert!(set("*").contains(&v("0.0.1")));
+        assert!(">=1.0,".parse::<SpecifierSet>().is_err());
+        assert!("1.0".parse::<SpecifierSet>().is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! `box lock --check`: how box.lock differs from the manifests.
+
+use std::collections::BTreeMap;
+
+use box_core::{Version, normalize_name};
+
+use crate::workspace::{ProjectPaths, Workspace};
+use crate::{
+    GIT_SOURCE, Lockfile, LockfileDependency, MAIN_GROUP, PATH_SOURCE, URL_SOURCE, lock_graph,
+    package_groups, same_source, workspace_requirements, workspace_roots, workspace_sources,
+};
+
+/// How box.lock falls short of the manifests, worked out from the lock
+/// alone: every requirement that applies here must be met by a locked
+/// version, every direct source must be the one locked, every locked
+/// dependency must be locked too, and nothing may be locked that no group
+/// needs.
+pub(crate) fn lock_differences(
+    paths: &ProjectPaths,
+    workspace: &Workspace,
+    lockfile: &Lockfile,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut differences = Vec::new();
+    let locked: BTreeMap<String, &LockfileDependency> = lockfile
+        .dependencies
+        .iter()
+        .map(|(dep, info)| (normalize_name(dep), info))
+        .collect();
+
+    for requirement in workspace_requirements(workspace)? {
+        if !requirement.applies_to(&system_info.markers, &[]) {
+            continue;
+        }
+        let name = requirement.normalized_name();
+        let Some(info) = locked.get(&name) else {
+            differences.push(format!("+ {}", requirement));
+            continue;
+        };
+        // An editable member's version may be dynamic, and so empty
+        if let Ok(version) = info.version.parse::<Version>()
+            && !requirement.specifiers.contains(&version)
+        {
+            differences.push(format!(
+                "~ {} {} doesn't satisfy {}",
+                name, info.version, requirement
+            ));
+        }
+    }
+
+    let sources = workspace_sources(workspace)?;
+    for (name, source) in &sources {
+        let Some(info) = locked.get(name) else {
+            continue;
+        };
+        if !same_source(paths, source, info) {
+            let location = source
+                .path
+                .iter()
+                .chain(&source.git)
+                .chain(&source.url)
+                .next()
+                .cloned()
+                .unwrap_or_default();
+            let rev = source
+                .rev
+                .as_ref()
+                .map(|rev| format!(" ({})", rev))
+                .unwrap_or_default();
+            differences.push(format!(
+                "~ {} source -> {} {}{}",
+                name,
+                source.kind()?,
+                location,
+                rev
+            ));
+        }
+    }
+    for (name, info) in &locked {
+        if let Some(kind @ (PATH_SOURCE | GIT_SOURCE | URL_SOURCE)) = info.source.as_deref()
+            && !sources.contains_key(name)
+        {
+            differences.push(format!("~ {} source {} -> index", name, kind));
+        }
+    }
+
+    for (dep, info) in &lockfile.dependencies {
+        let Some(dependencies) = &info.dependencies else {
+            differences.push(format!("~ {} has no dependencies recorded", dep));
+            continue;
+        };
+        for dependency in dependencies {
+            if !locked.contains_key(&normalize_name(dependency)) {
+                differences.push(format!("+ {} (required by {})", dependency, dep));
+            }
+        }
+    }
+
+    let groups = package_groups(&workspace_roots(workspace), &lock_graph(lockfile));
+    for (dep, info) in &lockfile.dependencies {
+        let Some(needed) = groups.get(&normalize_name(dep)) else {
+            differences.push(format!("- {} {}", dep, info.version));
+            continue;
+        };
+        let mut needed = needed.clone();
+        needed.sort();
+        let mut locked_groups = info
+            .groups
+            .clone()
+            .unwrap_or_else(|| vec![MAIN_GROUP.to_string()]);
+        locked_groups.sort();
+        if locked_groups != needed {
+            differences.push(format!(
+                "~ {} groups [{}] -> [{}]",
+                dep,
+                locked_groups.join(", "),
+                needed.join(", ")
+            ));
+        }
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use box_core::get_system_info;
+
+    use crate::MANIFEST_FILE;
+    use crate::test_support::TempDir;
+
+    // The differences between a project depending on `dependencies` and a
+    // lock of attrs 23.1 and requests 2.31, which needs idna 3.4
+    fn differences(dir: &Path, dependencies: &str) -> Vec<String> {
+        differences_from(dir, dependencies, &lockfile())
+    }
+
+    fn differences_from(dir: &Path, dependencies: &str, lockfile: &Lockfile) -> Vec<String> {
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            format!(
+                "[project]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}",
+                dependencies
+            ),
+        )
+        .unwrap();
+        let paths = ProjectPaths::new(dir);
+        let workspace = Workspace::load(&paths).unwrap();
+        lock_differences(&paths, &workspace, lockfile, &get_system_info()).unwrap()
+    }
+
+    fn lockfile() -> Lockfile {
+        toml::from_str(
+            r#"
+[python]
+python_version = "3.11"
+
+[dependencies.attrs]
+version = "23.1"
+path = ""
+dependencies = []
+groups = ["main"]
+
+[dependencies.requests]
+version = "2.31"
+path = ""
+dependencies = ["idna"]
+groups = ["main"]
+
+[dependencies.idna]
+version = "3.4"
+path = ""
+dependencies = []
+groups = ["main"]
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn an_up_to_date_lock_has_no_differences() {
+        let dir = TempDir::new("up-to-date");
+        let differences = differences(&dir.0, "attrs = \">=23\"\nRequests = \"*\"\n");
+        assert!(differences.is_empty(), "{:?}", differences);
+    }
+
+    #[test]
+    fn finds_added_changed_and_removed_dependencies() {
+        let dir = TempDir::new("changed");
+        let added = "attrs = \">=23\"\nrequests = \"*\"\nrich = \">=13\"\n";
+        assert_eq!(differences(&dir.0, added), ["+ rich>=13"]);
+
+        let changed = "attrs = \">=24\"\nrequests = \"*\"\n";
+        assert_eq!(
+            differences(&dir.0, changed),
+            ["~ attrs 23.1 doesn't satisfy attrs>=24"]
+        );
+
+        // Dropping requests leaves idna, which only it needed, locked too
+        let removed = "attrs = \">=23\"\n";
+        assert_eq!(
+            differences(&dir.0, removed),
+            ["- idna 3.4", "- requests 2.31"]
+        );
+    }
+
+    #[test]
+    fn sources_back_to_the_index_come_out_in_name_order() {
+        let dir = TempDir::new("to-index");
+        let mut lockfile = lockfile();
+        for (dep, source) in [
+            ("requests", URL_SOURCE),
+            ("attrs", GIT_SOURCE),
+            ("idna", PATH_SOURCE),
+        ] {
+            lockfile.dependencies.get_mut(dep).unwrap().source = Some(source.to_string());
+        }
+        let expected = [
+            "~ attrs source git -> index",
+            "~ idna source path -> index",
+            "~ requests source url -> index",
+        ];
+        assert_eq!(
+            differences_from(&dir.0, "attrs = \">=23\"\nrequests = \"*\"\n", &lockfile),
+            expected
+        );
+    }
+}
@@ -1,18 +1,48 @@
+mod lock_check;
+mod sync;
+#[cfg(test)]
+pub(crate) mod test_support;
+mod workspace;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
 
-use box_core::create_python_env;
 use box_core::install_wheel;
-use clap::{Parser, Subcommand};
+use box_core::{installed_distributions, uninstall};
+use clap::{Args, Parser, Subcommand};
 
+use lock_check::lock_differences;
 use serde::Deserialize;
 use serde::Serialize;
 use std::env;
+use sync::plan_sync;
 use toml::de::from_str;
+use toml_edit::DocumentMut;
+use workspace::{ProjectPaths, Workspace, find_project, is_pyproject};
+
+const MANIFEST_FILE: &str = "mypkg.toml";
+const PYPROJECT_FILE: &str = "pyproject.toml";
+const LOCK_FILE: &str = "box.lock";
+// box.lock's name for the project's own dependencies, next to its dependency groups
+const MAIN_GROUP: &str = "main";
+const DEV_GROUP: &str = "dev";
+// box.lock's `source` for a workspace member, built from its directory
+const WORKSPACE_SOURCE: &str = "workspace";
+// box.lock's `source` for dependencies that don't come from the index
+const PATH_SOURCE: &str = "path";
+const GIT_SOURCE: &str = "git";
+const URL_SOURCE: &str = "url";
+// What an editable install depends on besides the code it imports
+const BUILD_CONFIG_FILES: [&str; 3] = ["pyproject.toml", "setup.py", "setup.cfg"];
 
 use box_core::{
-    create_venv_and_build, download_source, extract_tar_gz, get_build_tuple, get_system_info,
-    move_wheel,
+    BinaryPolicy, DEFAULT_INDEX_URL, DistributionFile, DistributionKind, IndexClient,
+    IndexDependencyProvider, Job, JobOutput, Marker, Progress, Requirement, SpecifierSet, Version,
+    build_editable, build_sdist, build_wheel, download_source, get_build_tuple, get_system_info,
+    git_checkout, has_build_system, hash_directory, move_wheel, normalize_name,
+    read_wheel_metadata, resolve, run_jobs, select_artifact, setup_python_env, sha256_file,
+    verify_hash,
 };
 
 #[derive(Parser)]
@@ -29,275 +59,3537 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// Project root to use instead of searching upwards from the current directory for
+    /// mypkg.toml or a pyproject.toml with a [project] table
+    #[arg(long, global = true, value_name = "DIR")]
+    project_dir: Option<PathBuf>,
+
+    /// Workspace member to work on instead of the one in the current directory;
+    /// install and sync work on every member without it
+    #[arg(long, global = true, value_name = "NAME")]
+    package: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Create mypkg.toml, box.lock and .box/ for a new project
     Init {
-        #[arg(short, long)]
-        path: bool,
+        /// Project name (defaults to the directory's name)
+        name: Option<String>,
+
+        /// Overwrite an existing mypkg.toml
+        #[arg(long)]
+        force: bool,
+
+        /// Also create a src/<package>/__init__.py skeleton
+        #[arg(long)]
+        src: bool,
+
+        /// Also add .box/ to the project's .gitignore
+        #[arg(long)]
+        gitignore: bool,
     },
     Add {
-        name: Option<String>,
+        /// Requirement to add, e.g. "lz4", "lz4==4.3.2" or "lz4>=4,<5"
+        requirement: Option<String>,
+
+        /// Add it to this extra's optional dependencies instead
+        #[arg(long, value_name = "EXTRA")]
+        optional: Option<String>,
+
+        /// Add it to this dependency group instead, e.g. "dev"
+        #[arg(long, value_name = "GROUP", conflicts_with = "optional")]
+        group: Option<String>,
+
+        #[command(flatten)]
+        index: IndexArgs,
     },
+    /// Install what box.lock pins, and the project itself in editable mode
     Install {
+        /// Downloads and builds to run at once (defaults to the number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        #[command(flatten)]
+        groups: GroupArgs,
+    },
+    /// Remove a dependency, the packages only it needed, and their installed files
+    Remove {
+        /// Name of the dependency to remove, as in the manifest
+        name: String,
+
+        /// Remove it from this extra's optional dependencies instead
+        #[arg(long, value_name = "EXTRA")]
+        optional: Option<String>,
+
+        /// Remove it from this dependency group instead
+        #[arg(long, value_name = "GROUP", conflicts_with = "optional")]
+        group: Option<String>,
+    },
+    /// Resolve the manifest and write box.lock, without installing anything
+    Lock {
+        #[command(flatten)]
+        index: IndexArgs,
+
+        /// Don't write box.lock, exit with an error if it's out of date
+        #[arg(long)]
+        check: bool,
+    },
+    /// Upgrade one locked package, or all of them, within the manifest's constraints
+    Update {
+        /// Package to update; every package when left out
+        name: Option<String>,
+
+        #[command(flatten)]
+        index: IndexArgs,
+    },
+    /// Make the virtual environment match box.lock exactly
+    Sync {
+        /// Downloads and builds to run at once (defaults to the number of CPUs)
         #[arg(short, long)]
-        path: bool,
+        jobs: Option<usize>,
+
+        #[command(flatten)]
+        groups: GroupArgs,
     },
 }
 
+/// Which of box.lock's dependency groups go into the venv. By default that's
+/// the project's dependencies plus the "dev" group.
+#[derive(Args)]
+struct GroupArgs {
+    /// Also install this dependency group
+    #[arg(long, value_name = "GROUP")]
+    group: Vec<String>,
+
+    /// Leave out the "dev" group
+    #[arg(long)]
+    no_dev: bool,
+
+    /// Install only this dependency group, without the project's dependencies
+    #[arg(long, value_name = "GROUP", conflicts_with_all = ["group", "no_dev"])]
+    only_group: Vec<String>,
+}
+
+/// Where to resolve packages from, on top of the manifest's [tool.boxpkg].
+#[derive(Args)]
+struct IndexArgs {
+    /// Simple Repository API (PEP 503/691) index to resolve packages from [default: [tool.boxpkg]
+    /// index-url, or PyPI]
+    #[arg(long, value_name = "URL")]
+    index_url: Option<String>,
+
+    /// Build these packages from source even if a compatible wheel exists (":all:" for every package)
+    #[arg(long, value_name = "PACKAGE", value_delimiter = ',')]
+    no_binary: Vec<String>,
+
+    /// Never build these packages from source, only install wheels (":all:" for every package)
+    #[arg(long, value_name = "PACKAGE", value_delimiter = ',')]
+    only_binary: Vec<String>,
+
+    /// Downloads and builds to run at once (defaults to the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
 fn main() {
     let cli = Cli::parse();
-    let dis = env::current_dir();
 
-    match dis {
-        Ok(dis) => {
-            println!("current_dir! {}", dis.display());
+    if cli.debug > 0 {
+        match env::current_dir() {
+            Ok(dir) => println!("current_dir: {}", dir.display()),
+            Err(e) => println!("Failed to check current_dir: {}", e),
+        }
+        if let Some(name) = cli.name.as_deref() {
+            println!("Value for name: {name}");
         }
-        Err(e) => {
-            println!("Failed to check current_dir: {}", e);
+        if let Some(config_path) = cli.config.as_deref() {
+            println!("Value for config: {}", config_path.display());
         }
     }
 
-    // You can check the value provided by positional arguments, or option arguments
-    if let Some(name) = cli.name.as_deref() {
-        println!("Value for name: {name}");
+    // init creates the project, everything else works on an existing one
+    let project_paths = match &cli.command {
+        Some(Commands::Init { .. }) => {
+            let root = match &cli.project_dir {
+                Some(dir) => dir.clone(),
+                None => env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            };
+            ProjectPaths::new(&root)
+        }
+        _ => match find_project(cli.project_dir.as_deref()) {
+            Ok(paths) => paths,
+            Err(e) => {
+                if cli.command.is_some() {
+                    eprintln!("Error occurred: {}", e);
+                    std::process::exit(1);
+                }
+                ProjectPaths::new(Path::new("."))
+            }
+        },
+    };
+    let package = cli.package.as_deref();
+
+    let system_info = get_system_info();
+    if cli.debug > 0 {
+        println!("Project root: {}", project_paths.root.display());
+        println!(
+            "platform {} python_version {} interpreter {} abi_tag {} compiler {}",
+            system_info.platform,
+            system_info.python_version,
+            system_info.interpreter_tag,
+            system_info.abi_tag,
+            system_info.compiler.as_deref().unwrap_or("none")
+        );
     }
 
-    if let Some(config_path) = cli.config.as_deref() {
-        println!("Value for config: {}", config_path.display());
+    // You can check for the existence of subcommands, and if found use their
+    // matches just as you would the top level cmd
+    match &cli.command {
+        Some(Commands::Init {
+            name,
+            force,
+            src,
+            gitignore,
+        }) => {
+            let options = InitOptions {
+                name: name.clone(),
+                force: *force,
+                src: *src,
+                gitignore: *gitignore,
+            };
+            if let Err(e) = init(&project_paths, &options, &system_info) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Add {
+            requirement,
+            optional,
+            group,
+            index,
+        }) => {
+            let target = DependencyTarget::new(optional, group);
+            if let Err(e) = add(
+                &project_paths,
+                package,
+                requirement,
+                &target,
+                index,
+                &system_info,
+            ) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Install { jobs, groups }) => {
+            if let Err(e) = install(
+                &project_paths,
+                package,
+                groups,
+                &system_info,
+                jobs.unwrap_or_else(default_jobs),
+            ) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Lock { index, check }) => {
+            if let Err(e) = lock(&project_paths, index, *check, &system_info) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Update { name, index }) => {
+            if let Err(e) = update(&project_paths, name, index, &system_info) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Remove {
+            name,
+            optional,
+            group,
+        }) => {
+            let target = DependencyTarget::new(optional, group);
+            if let Err(e) = remove(&project_paths, package, name, &target) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Sync { jobs, groups }) => {
+            if let Err(e) = sync(
+                &project_paths,
+                package,
+                groups,
+                &system_info,
+                jobs.unwrap_or_else(default_jobs),
+            ) {
+                eprintln!("Error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {}
     }
+}
 
-    let project = Project {
-        name: "myproject".into(),
-        version: "0.1.0".into(),
-    };
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
 
-    let manifest: Manifest = Manifest {
-        project,
-        dependencies: std::collections::HashMap::new(),
-    };
+/// Where packages are resolved from and how they may be brought in.
+struct LockSettings {
+    index_url: String,
+    policy: BinaryPolicy,
+    jobs: usize, // downloads and builds at once
+}
 
-    let system_info = get_system_info();
+impl LockSettings {
+    /// The command line's settings, falling back to the manifest's [tool.boxpkg].
+    fn new(args: &IndexArgs, tool: &ToolSettings) -> LockSettings {
+        let no_binary: Vec<String> = tool
+            .no_binary
+            .iter()
+            .chain(&args.no_binary)
+            .cloned()
+            .collect();
+        let only_binary: Vec<String> = tool
+            .only_binary
+            .iter()
+            .chain(&args.only_binary)
+            .cloned()
+            .collect();
+        LockSettings {
+            index_url: args
+                .index_url
+                .clone()
+                .unwrap_or_else(|| configured_index_url(tool)),
+            policy: BinaryPolicy::new(&no_binary, &only_binary),
+            jobs: args.jobs.unwrap_or_else(default_jobs),
+        }
+    }
+}
 
-    let python: PythonConfig = PythonConfig {
-        python_version: system_info.python_version.clone(),
-    };
+/// The index [tool.boxpkg] names, or PyPI. Builds get their requirements
+/// from it too.
+fn configured_index_url(tool: &ToolSettings) -> String {
+    tool.index_url
+        .clone()
+        .unwrap_or_else(|| DEFAULT_INDEX_URL.to_string())
+}
 
-    let lockfile: Lockfile = Lockfile {
-        python,
-        dependencies: std::collections::HashMap::new(),
-    };
+#[derive(Serialize, Deserialize, Debug)]
+struct Project {
+    name: String,
+    version: String,
+    #[serde(
+        rename = "requires-python",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    requires_python: Option<String>,
+}
 
-    // You can see how many times a particular flag or argument occurred
-    // Note, only flags can have multiple occurrences
-    // match cli.debug {
-    //     0 => println!("Debug mode is off"),
-    //     1 => println!("Debug mode is kind of on"),
-    //     2 => println!("Debug mode is on"),
-    //     _ => println!("Don't be crazy"),
-    // }
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    project: Project,
+    /// Dependency name -> its constraints, several when markers split it
+    /// (e.g. one version for Python < 3.10 and another after)
+    dependencies: BTreeMap<String, Vec<String>>,
+    /// Extra name -> its dependencies, written like [dependencies]
+    #[serde(
+        rename = "optional-dependencies",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    optional_dependencies: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    /// PEP 735 group name -> its dependencies, written like [dependencies]
+    #[serde(
+        rename = "dependency-groups",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    dependency_groups: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    /// Group name -> the groups it includes (pyproject.toml's `{include-group = ...}`)
+    #[serde(skip)]
+    group_includes: BTreeMap<String, Vec<String>>,
+    /// Normalized name -> where that dependency comes from instead of the index
+    #[serde(skip)]
+    sources: BTreeMap<String, DirectSource>,
+    #[serde(default, skip_serializing)]
+    tool: ToolTable,
+}
 
-    // You can check for the existence of subcommands, and if found use their
-    // matches just as you would the top level cmd
-    match &cli.command {
-        Some(Commands::Init { path }) => {
-            if *path {
-                let _ = init(manifest, lockfile);
-            } else {
-                println!("Not initializing...");
+impl Manifest {
+    /// The dependencies `target` names, created empty if it's a new extra or group.
+    fn dependencies_mut(
+        &mut self,
+        target: &DependencyTarget,
+    ) -> &mut BTreeMap<String, Vec<String>> {
+        match target {
+            DependencyTarget::Project => &mut self.dependencies,
+            DependencyTarget::Extra(extra) => {
+                self.optional_dependencies.entry(extra.clone()).or_default()
+            }
+            DependencyTarget::Group(group) => {
+                self.dependency_groups.entry(group.clone()).or_default()
             }
         }
-        Some(Commands::Add { name }) => {
-            let _ = add(name, manifest, system_info, lockfile);
+    }
+}
+
+/// Which part of the manifest `add` and `remove` edit.
+enum DependencyTarget {
+    Project,
+    Extra(String),
+    Group(String),
+}
+
+impl DependencyTarget {
+    fn new(optional: &Option<String>, group: &Option<String>) -> DependencyTarget {
+        match (optional, group) {
+            (Some(extra), _) => DependencyTarget::Extra(extra.clone()),
+            (None, Some(group)) => DependencyTarget::Group(group.clone()),
+            (None, None) => DependencyTarget::Project,
         }
-        Some(Commands::Install { path }) => {
-            if *path {
-                install();
-            } else {
-                println!("Not installing...");
-            }
+    }
+}
+
+impl std::fmt::Display for DependencyTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DependencyTarget::Project => write!(f, "dependencies"),
+            DependencyTarget::Extra(extra) => write!(f, "optional dependencies {}", extra),
+            DependencyTarget::Group(group) => write!(f, "dependency group {}", group),
         }
-        None => {}
     }
+}
 
-    // Continued program logic goes here...
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Project {
-        name: String,
-        version: String,
+#[derive(Deserialize, Debug, Default)]
+struct ToolTable {
+    #[serde(default)]
+    boxpkg: ToolSettings,
+}
+
+/// [tool.boxpkg]: defaults for settings the command line can also give.
+#[derive(Deserialize, Debug, Default)]
+struct ToolSettings {
+    #[serde(rename = "index-url", default)]
+    index_url: Option<String>,
+    #[serde(rename = "no-binary", default)]
+    no_binary: Vec<String>,
+    #[serde(rename = "only-binary", default)]
+    only_binary: Vec<String>,
+    workspace: Option<WorkspaceSettings>,
+    /// Dependency name -> where it comes from instead of the index
+    #[serde(default)]
+    sources: BTreeMap<String, DirectSource>,
+}
+
+/// A dependency on a local directory, a git repository or an archive url,
+/// e.g. `{ git = "https://example.com/repo.git", rev = "v1.0" }`. In
+/// mypkg.toml it takes the place of the constraint; pyproject.toml lists
+/// it under [tool.boxpkg.sources].
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct DirectSource {
+    /// A project directory, relative to the manifest that names it
+    path: Option<String>,
+    git: Option<String>,
+    /// Commit, tag or branch of `git`; its default branch when missing
+    rev: Option<String>,
+    /// An sdist (.tar.gz, .tar.bz2, .tar.xz or .zip) or wheel
+    url: Option<String>,
+}
+
+impl DirectSource {
+    /// The box.lock `source` this is, checking it names exactly one.
+    fn kind(&self) -> Result<&'static str, String> {
+        let kind = match (&self.path, &self.git, &self.url) {
+            (Some(_), None, None) => PATH_SOURCE,
+            (None, Some(_), None) => GIT_SOURCE,
+            (None, None, Some(_)) => URL_SOURCE,
+            _ => return Err("a source needs exactly one of path, git or url".to_string()),
+        };
+        if self.rev.is_some() && kind != GIT_SOURCE {
+            return Err("rev only applies to git sources".to_string());
+        }
+        Ok(kind)
+    }
+
+    /// The source a PEP 508 `name @ url` points at: `git+<repo>[@rev]`
+    /// is a git source, `file://<dir>` a path and anything else a url.
+    fn from_url(url: &str) -> Result<DirectSource, String> {
+        let mut source = DirectSource {
+            path: None,
+            git: None,
+            rev: None,
+            url: None,
+        };
+        if let Some(repo) = url.strip_prefix("git+") {
+            // `#egg=name` and the like say nothing boxpkg needs
+            let repo = repo.split('#').next().unwrap_or(repo);
+            // A rev follows the last `@` of the path, not the `user@host`
+            let path_start = repo
+                .find("://")
+                .and_then(|i| repo[i + 3..].find('/').map(|j| i + 3 + j))
+                .unwrap_or(0);
+            match repo[path_start..].rfind('@') {
+                Some(i) => {
+                    source.git = Some(repo[..path_start + i].to_string());
+                    source.rev = Some(repo[path_start + i + 1..].to_string());
+                }
+                None => source.git = Some(repo.to_string()),
+            }
+        } else if let Some(path) = url.strip_prefix("file://") {
+            source.path = Some(path.to_string());
+        } else if url
+            .split_once("://")
+            .is_some_and(|(scheme, _)| scheme.contains('+'))
+        {
+            return Err(format!(
+                "{}: only git+ version control URLs are supported",
+                url
+            ));
+        } else {
+            source.url = Some(url.to_string());
+        }
+        Ok(source)
     }
+}
+
+/// [tool.boxpkg.workspace] of a workspace root.
+#[derive(Deserialize, Debug, Default, Clone)]
+struct WorkspaceSettings {
+    /// Member directories relative to the root; `*` and `?` match within a path component
+    #[serde(default)]
+    members: Vec<String>,
+    /// Directories `members` matches that aren't members, written the same way
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// The parts of a PEP 621 pyproject.toml boxpkg reads.
+#[derive(Deserialize, Debug)]
+struct PyProject {
+    project: PyProjectTable,
+    #[serde(rename = "dependency-groups", default)]
+    dependency_groups: BTreeMap<String, Vec<DependencyGroupItem>>,
+    #[serde(default)]
+    tool: ToolTable,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DependencyGroupItem {
+    Requirement(String),
+    Include {
+        #[serde(rename = "include-group")]
+        include_group: String,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct PyProjectTable {
+    name: String,
+    /// None when it's listed in `dynamic`
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(rename = "requires-python", default)]
+    requires_python: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    #[serde(rename = "optional-dependencies", default)]
+    optional_dependencies: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PythonConfig {
+    python_version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct LockfileDependency {
+    version: String,
+    path: String,
+    /// sha256 of the downloaded sdist or wheel, e.g. "sha256:ab12...", or
+    /// of the source tree (see hash_directory) of a path or git source
+    #[serde(default)]
+    hash: String,
+    /// Where the sdist or wheel was downloaded from, or the git repository
+    #[serde(default)]
+    url: String,
+    /// The commit a git source was locked at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    /// The `rev` the manifest gives a git source; `rev` stays put until
+    /// this changes or the package is updated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requested_rev: Option<String>,
+    #[serde(default)]
+    cache_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    marker: Option<String>,
+    /// Names of the locked packages this one depends on; None in locks written
+    /// before they were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Vec<String>>,
+    /// The groups that need it: "main" for the project's own dependencies,
+    /// or a dependency group. None in locks written before groups, meaning "main"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<String>>,
+    /// Where it comes from when that isn't the index: "workspace" for a
+    /// workspace member and "path" for a path dependency, whose `path` is
+    /// their directory, or "git" or "url"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Manifest {
-        project: Project,
-        dependencies: std::collections::HashMap<String, String>,
+impl LockfileDependency {
+    /// Whether it's built from a local directory as that is at install time.
+    fn is_local(&self) -> bool {
+        matches!(self.source.as_deref(), Some(WORKSPACE_SOURCE | PATH_SOURCE))
     }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Lockfile {
+    python: PythonConfig,
+    dependencies: BTreeMap<String, LockfileDependency>,
+}
+
+// #[derive(Serialize)]
+// struct Config {
+//     name: String,
+//     version: String,
+//     authors: Vec<String>,
+//     debug: bool,
+// }
+
+struct InitOptions {
+    name: Option<String>,
+    force: bool,
+    src: bool,       // create a src layout skeleton
+    gitignore: bool, // add .box/ to .gitignore
+}
+
+fn init(
+    paths: &ProjectPaths,
+    options: &InitOptions,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("initializing...");
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct PythonConfig {
-        python_version: String,
+    let lockfile = Lockfile {
+        python: PythonConfig {
+            python_version: system_info.python_version.clone(),
+        },
+        dependencies: BTreeMap::new(),
+    };
+    let lockfile_string = toml::to_string_pretty(&lockfile)?;
+
+    // A PEP 621 project already has its manifest, it only needs the rest
+    if paths.is_pyproject() {
+        let name = load_manifest(&paths.manifest)?.project.name;
+        println!("Using [project] from {}", paths.manifest.display());
+        if !paths.lockfile.exists() {
+            std::fs::write(&paths.lockfile, lockfile_string)?;
+            println!("box.lock written successfully.");
+        }
+        std::fs::create_dir_all(&paths.cache)?;
+        println!("Initialized project {} in {}", name, paths.root.display());
+        return Ok(());
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct LockfileDependency {
-        version: String,
-        path: String,
-        hash: String,
+    if paths.manifest.exists() && !options.force {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            paths.manifest.display()
+        )
+        .into());
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Lockfile {
-        python: PythonConfig,
-        dependencies: std::collections::HashMap<String, LockfileDependency>,
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => paths
+            .root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| valid_project_name(name))
+            .ok_or("Can't name the project after its directory, pass a name")?,
+    };
+    if !valid_project_name(&name) {
+        return Err(format!(
+            "{:?} isn't a valid project name: use letters and digits, with -, _ or . between them",
+            name
+        )
+        .into());
     }
 
-    // #[derive(Serialize)]
-    // struct Config {
-    //     name: String,
-    //     version: String,
-    //     authors: Vec<String>,
-    //     debug: bool,
-    // }
+    // The interpreter the project is started with, e.g. 3.11.7 gives ">=3.11"
+    let requires_python = system_info
+        .python_version
+        .parse::<Version>()
+        .ok()
+        .and_then(|version| {
+            let release = version.base().to_string();
+            let mut parts = release.split('.');
+            Some(format!(">={}.{}", parts.next()?, parts.next()?))
+        });
 
-    fn init(manifest: Manifest, lockfile: Lockfile) -> Result<(), Box<dyn std::error::Error>> {
-        println!("initializing...");
+    let manifest = Manifest {
+        project: Project {
+            name: name.clone(),
+            version: "0.1.0".into(),
+            requires_python,
+        },
+        dependencies: BTreeMap::new(),
+        optional_dependencies: BTreeMap::new(),
+        dependency_groups: BTreeMap::new(),
+        group_includes: BTreeMap::new(),
+        sources: BTreeMap::new(),
+        tool: ToolTable::default(),
+    };
 
-        // Serialize to a TOML string
-        let toml_string = toml::to_string_pretty(&manifest)?;
-        let lockfile_string = toml::to_string_pretty(&lockfile)?;
+    // Serialize to a TOML string
+    let toml_string = toml::to_string_pretty(&manifest)?;
 
-        // Write to a file
-        std::fs::write("./temp/mypkg.toml", toml_string)?;
-        println!("mypkg.toml written successfully.");
+    // Write to a file
+    std::fs::create_dir_all(&paths.root)?;
+    std::fs::write(&paths.manifest, toml_string)?;
+    println!("mypkg.toml written successfully.");
 
-        std::fs::write("./temp/box.lock", lockfile_string)?;
+    // Even with --force, whatever is already locked stays
+    if !paths.lockfile.exists() {
+        std::fs::write(&paths.lockfile, lockfile_string)?;
         println!("box.lock written successfully.");
+    }
 
-        std::fs::create_dir("./temp/.box")?;
-        std::fs::create_dir_all("./temp/.box/cache")?;
+    std::fs::create_dir_all(&paths.cache)?;
 
-        Ok(())
+    if options.src {
+        // An import name can't have dashes or dots
+        let module = normalize_name(&name).replace('-', "_");
+        let package_dir = paths.root.join("src").join(&module);
+        std::fs::create_dir_all(&package_dir)?;
+        let init_file = package_dir.join("__init__.py");
+        if !init_file.exists() {
+            std::fs::write(&init_file, "")?;
+        }
+        println!("Created {}", init_file.display());
     }
 
-    fn add(
-        name: &Option<String>,
-        mut manifest: Manifest,
-        system_info: box_core::SystemEnvironmentInfo,
-        mut lockfile: Lockfile,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(name) = name {
-            if !matches!(name.as_str(), "lz4" | "lz4-python") {
-                return Err("Invalid name".into());
+    if options.gitignore {
+        let gitignore = paths.root.join(".gitignore");
+        let mut contents = std::fs::read_to_string(&gitignore).unwrap_or_default();
+        if !contents
+            .lines()
+            .any(|line| matches!(line.trim(), ".box" | ".box/" | "/.box" | "/.box/"))
+        {
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
             }
+            contents.push_str(".box/\n");
+            std::fs::write(&gitignore, contents)?;
+            println!("Added .box/ to {}", gitignore.display());
+        }
+    }
 
-            println!("Adding... {}", name);
+    println!("Initialized project {} in {}", name, paths.root.display());
+    Ok(())
+}
 
-            let build_folder = std::path::Path::new("./temp/.box/build/");
-            let pkg_build_folder = build_folder.join(name);
-            println!("pkg_build_folder: {}", pkg_build_folder.display());
+// A PEP 508 name: ASCII letters and digits, with -, _ and . only between them
+fn valid_project_name(name: &str) -> bool {
+    let alphanumeric = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    alphanumeric(name.chars().next())
+        && alphanumeric(name.chars().last())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
 
-            std::fs::create_dir_all(&pkg_build_folder)?;
+/// Adds `requirement` to the manifest's dependencies, an extra or a
+/// dependency group, and locks it. In a workspace, the manifest is that of
+/// `package` or the member the command runs in.
+fn add(
+    paths: &ProjectPaths,
+    package: Option<&str>,
+    requirement: &Option<String>,
+    target: &DependencyTarget,
+    args: &IndexArgs,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(requirement) = requirement {
+        let requirement: Requirement = requirement.parse()?;
+        println!("Adding... {}", requirement);
 
-            let dl_res = download_source(
-                "https://files.pythonhosted.org/packages/source/l/lz4/lz4-4.3.2.tar.gz",
-                &build_folder,
+        if !requirement.applies_to(&system_info.markers, &[]) {
+            println!(
+                "{} doesn't apply to this environment, recording it without installing",
+                requirement
             );
+        }
+
+        let mut workspace = Workspace::load(paths)?;
+        let member = workspace.target(paths, package)?;
+        let old_lockfile = load_lockfile(paths, system_info)?;
+        let settings = LockSettings::new(args, &workspace.root().manifest.tool.boxpkg);
+
+        if let DependencyTarget::Group(group) = target
+            && normalize_name(group) == MAIN_GROUP
+        {
+            return Err(format!("\"{}\" can't be a dependency group name", MAIN_GROUP).into());
+        }
+        add_requirement(
+            &mut workspace.members[member].manifest,
+            target,
+            &requirement,
+        );
+
+        // What's already locked stays put unless the new requirement needs it to move
+        let preferences = locked_versions(&old_lockfile, None);
+        let lockfile = relock(
+            paths,
+            &workspace,
+            &old_lockfile,
+            &preferences,
+            &settings,
+            system_info,
+        )?;
+
+        let member = &workspace.members[member];
+        write_manifest(&member.manifest_path, &member.manifest)?;
+        write_lockfile(paths, &lockfile)?;
+        println!("box.lock updated successfully.");
+    }
+    Ok(())
+}
+
+/// Puts `requirement` into the manifest's `target`. Adding a package again
+/// replaces its constraints.
+fn add_requirement(manifest: &mut Manifest, target: &DependencyTarget, requirement: &Requirement) {
+    let dependencies = manifest.dependencies_mut(target);
+    // Under the name it already has, so the manifest's entry is edited in place
+    let name = dependencies
+        .keys()
+        .find(|name| normalize_name(name) == requirement.normalized_name())
+        .cloned()
+        .unwrap_or_else(|| requirement.name.to_string());
+    dependencies.insert(name, vec![requirement.constraint()]);
+}
+
+/// Resolves every dependency of the workspace and writes box.lock, keeping
+/// the locked versions the manifests still allow and fetching and building
+/// into the cache whatever the lock points at. With `check`, nothing is
+/// resolved, fetched or written; it fails if box.lock no longer matches the
+/// manifests.
+fn lock(
+    paths: &ProjectPaths,
+    args: &IndexArgs,
+    check: bool,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("locking...");
+
+    let workspace = Workspace::load(paths)?;
+    let old_lockfile = load_lockfile(paths, system_info)?;
+    let settings = LockSettings::new(args, &workspace.root().manifest.tool.boxpkg);
+
+    if check {
+        let differences = lock_differences(paths, &workspace, &old_lockfile, system_info)?;
+        if !differences.is_empty() {
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            return Err(format!(
+                "box.lock is out of date with {}, run `box lock`",
+                paths.manifest_name()
+            )
+            .into());
+        }
+        println!("box.lock is up to date");
+        return Ok(());
+    }
+
+    // Upgrading is `box update`'s job
+    let preferences = locked_versions(&old_lockfile, None);
+    let lockfile = relock(
+        paths,
+        &workspace,
+        &old_lockfile,
+        &preferences,
+        &settings,
+        system_info,
+    )?;
+    write_lockfile(paths, &lockfile)?;
+    println!(
+        "box.lock written with {} packages",
+        lockfile.dependencies.len()
+    );
+    Ok(())
+}
+
+/// Moves `name`, or every package when None, to the newest versions the
+/// manifests allow. Updating one package keeps the others at their locked
+/// versions unless it needs them to move too.
+fn update(
+    paths: &ProjectPaths,
+    name: &Option<String>,
+    args: &IndexArgs,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("updating...");
+
+    let workspace = Workspace::load(paths)?;
+    let old_lockfile = load_lockfile(paths, system_info)?;
+    let settings = LockSettings::new(args, &workspace.root().manifest.tool.boxpkg);
+
+    let preferences = match name {
+        Some(name) => {
+            if !old_lockfile
+                .dependencies
+                .keys()
+                .any(|dep| normalize_name(dep) == normalize_name(name))
+            {
+                return Err(format!("{} isn't in box.lock", name).into());
+            }
+            locked_versions(&old_lockfile, Some(name))
+        }
+        None => BTreeMap::new(),
+    };
+
+    let lockfile = relock(
+        paths,
+        &workspace,
+        &old_lockfile,
+        &preferences,
+        &settings,
+        system_info,
+    )?;
+    write_lockfile(paths, &lockfile)?;
+    println!("box.lock updated successfully.");
+    Ok(())
+}
+
+/// The locked version of every package but `except`.
+fn locked_versions(lockfile: &Lockfile, except: Option<&str>) -> BTreeMap<String, Version> {
+    lockfile
+        .dependencies
+        .iter()
+        .filter(|(dep, _)| except.is_none_or(|name| normalize_name(dep) != normalize_name(name)))
+        .filter_map(|(dep, info)| Some((dep.clone(), info.version.parse().ok()?)))
+        .collect()
+}
+
+/// What locking the workspace comes to: the packages to bring in from the
+/// index, which groups need each package, and the entries of what doesn't
+/// come from the index: the members other members depend on and the
+/// direct sources.
+struct LockPlan {
+    requirements: Vec<Requirement>, // every member's, including those on other members
+    jobs: Vec<Job>,
+    groups: HashMap<String, Vec<String>>,
+    entries: BTreeMap<String, LockfileDependency>,
+}
+
+fn plan_lock(
+    paths: &ProjectPaths,
+    workspace: &Workspace,
+    old_lockfile: &Lockfile,
+    settings: &LockSettings,
+    preferences: &BTreeMap<String, Version>,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<LockPlan, Box<dyn std::error::Error>> {
+    let requirements = workspace_requirements(workspace)?;
+    // Members come from the workspace, not the index
+    let index_requirements: Vec<Requirement> = requirements
+        .iter()
+        .filter(|requirement| workspace.member(&requirement.name).is_none())
+        .cloned()
+        .collect();
+
+    let mut direct = BTreeMap::new();
+    for (name, source) in workspace_sources(workspace)? {
+        // The locked commit or hash holds unless the source changed or it's being updated
+        let locked = old_lockfile
+            .dependencies
+            .iter()
+            .find(|(dep, _)| normalize_name(dep) == name && preferences.contains_key(*dep))
+            .map(|(_, info)| info)
+            .filter(|info| same_source(paths, &source, info));
+        let package = fetch_direct(paths, &name, &source, locked, settings, system_info)
+            .map_err(|e| format!("Failed to fetch {}: {}", name, e))?;
+        direct.insert(name, package);
+    }
+    let jobs = plan_jobs(
+        paths,
+        &workspace.root().manifest.project.name,
+        &index_requirements,
+        &mut direct,
+        settings,
+        preferences,
+        system_info,
+    )?;
+
+    // Sources nothing resolved to aren't locked
+    direct.retain(|_, package| package.entry.dependencies.is_some());
+    let mut graph = job_graph(&jobs);
+    graph.extend(direct.iter().map(|(name, package)| {
+        (
+            name.clone(),
+            package.entry.dependencies.clone().unwrap_or_default(),
+        )
+    }));
+    let member_graph = member_graph(workspace, &graph);
+    graph.extend(member_graph.clone());
+    let groups = package_groups(&workspace_roots(workspace), &graph);
+
+    let mut entries: BTreeMap<String, LockfileDependency> = workspace
+        .members
+        .iter()
+        .filter(|member| groups.contains_key(&member.normalized_name()))
+        .map(|member| {
+            let name = member.normalized_name();
+            let entry = LockfileDependency {
+                version: member.manifest.project.version.clone(),
+                path: paths.to_locked(&member.dir),
+                marker: direct_marker(&requirements, &name),
+                dependencies: member_graph.get(&name).cloned(),
+                groups: groups.get(&name).cloned(),
+                ..empty_entry(WORKSPACE_SOURCE)
+            };
+            (name, entry)
+        })
+        .collect();
+    for (name, package) in direct {
+        let entry = LockfileDependency {
+            marker: direct_marker(&requirements, &name),
+            groups: groups.get(&name).cloned(),
+            ..package.entry
+        };
+        entries.insert(name, entry);
+    }
+
+    Ok(LockPlan {
+        requirements,
+        jobs,
+        groups,
+        entries,
+    })
+}
+
+/// The direct sources every member's manifest gives, by normalized name,
+/// with paths made absolute.
+fn workspace_sources(
+    workspace: &Workspace,
+) -> Result<BTreeMap<String, DirectSource>, Box<dyn std::error::Error>> {
+    // Which member each came from, for when two disagree
+    let mut sources: BTreeMap<String, (DirectSource, &Path)> = BTreeMap::new();
+    for member in &workspace.members {
+        for (name, source) in &member.manifest.sources {
+            if workspace.member(name).is_some() {
+                return Err(
+                    format!("{} is a workspace member and can't have a source", name).into(),
+                );
+            }
+            let mut source = source.clone();
+            if let Some(path) = &source.path {
+                let dir = member.dir.join(path);
+                let dir = dir
+                    .canonicalize()
+                    .map_err(|e| format!("Source of {}: {}: {}", name, dir.display(), e))?;
+                source.path = Some(dir.display().to_string());
+            }
+            if let Some((other, dir)) = sources.get(name)
+                && *other != source
+            {
+                return Err(format!(
+                    "{} has different sources in {} and {}",
+                    name,
+                    dir.display(),
+                    member.dir.display()
+                )
+                .into());
+            }
+            sources.insert(name.clone(), (source, &member.dir));
+        }
+    }
+    Ok(sources
+        .into_iter()
+        .map(|(name, (source, _))| (name, source))
+        .collect())
+}
 
-            match dl_res {
-                Ok(dl_file_path) => {
-                    println!(
-                        "Download succeeded, file saved at: {}",
-                        dl_file_path.display()
-                    );
-                    let _ = extract_tar_gz(&dl_file_path, &pkg_build_folder.as_path());
-                    let pkg_tuple = get_build_tuple(name, "v1.0.0", system_info);
+/// A dependency fetched and built from its own source: its lock entry,
+/// still without the dependencies, marker and groups, and what it requires.
+struct DirectPackage {
+    entry: LockfileDependency,
+    requires_dist: Vec<Requirement>,
+}
+
+/// Brings a direct source in the way an sdist from the index is: checked
+/// out or downloaded into .box/build, built, and (except for a local
+/// directory) its wheel moved into the cache. The commit or content hash
+/// it got is what box.lock pins. With `locked`, the entry box.lock already
+/// has for it, the locked commit is checked out and a download must still
+/// have the locked hash; its cached wheel is used when it's still there.
+fn fetch_direct(
+    paths: &ProjectPaths,
+    name: &str,
+    source: &DirectSource,
+    locked: Option<&LockfileDependency>,
+    settings: &LockSettings,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<DirectPackage, Box<dyn std::error::Error>> {
+    let kind = source.kind()?;
+    if let Some(locked) = locked
+        && kind != PATH_SOURCE
+        && paths.resolve_locked(&locked.path).is_file()
+    {
+        let metadata = read_wheel_metadata(&paths.resolve_locked(&locked.path))?;
+        return Ok(DirectPackage {
+            entry: LockfileDependency {
+                dependencies: None,
+                ..locked.clone()
+            },
+            requires_dist: metadata
+                .requires_dist
+                .iter()
+                .map(|requires_dist| requires_dist.parse())
+                .collect::<Result<Vec<Requirement>, _>>()?,
+        });
+    }
 
-                    println!("Cache key: {}", pkg_tuple.hash_key());
+    let build_dir = paths.build.join(name);
+    std::fs::create_dir_all(&build_dir)?;
+    let build_project = |dir: &Path| {
+        build_wheel(
+            dir,
+            &build_dir.join("env"),
+            &build_dir.join("wheels"),
+            &settings.index_url,
+        )
+    };
+    // A locked source has to come out the same as when it was locked
+    let check_locked = |hash: &str, what: &str| -> Result<(), String> {
+        match locked {
+            Some(locked) if locked.hash != hash => Err(format!(
+                "{} no longer matches box.lock: expected {}, got {}; \
+                 run `box update {}` to accept it",
+                what, locked.hash, hash, name
+            )),
+            _ => Ok(()),
+        }
+    };
 
-                    let unzipped_folder = dl_file_path
-                        .file_name()
-                        .and_then(|f| f.to_str())
-                        .map(|s| s.replace(".tar.gz", ""));
+    // The wheel, the source archive to keep next to it, and the entry so far
+    let (wheel, archive, mut entry) = match (&source.path, &source.git, &source.url) {
+        (Some(path), _, _) => {
+            let dir = PathBuf::from(path);
+            if !dir.is_dir() {
+                return Err(format!("{} isn't a directory", dir.display()).into());
+            }
+            let entry = LockfileDependency {
+                path: paths.to_locked(&dir),
+                hash: format!("sha256:{}", hash_directory(&dir)?),
+                ..empty_entry(kind)
+            };
+            (build_project(&dir)?, None, entry)
+        }
+        (_, Some(url), _) => {
+            let checkout = build_dir.join("checkout");
+            let rev = locked
+                .and_then(|locked| locked.rev.as_deref())
+                .or(source.rev.as_deref());
+            let commit = git_checkout(url, rev, &checkout)?;
+            println!("Checked out {} at {}", url, commit);
+            let hash = format!("sha256:{}", hash_directory(&checkout)?);
+            check_locked(&hash, &format!("{} at {}", url, commit))?;
+            let entry = LockfileDependency {
+                hash,
+                url: url.clone(),
+                rev: Some(commit),
+                requested_rev: source.rev.clone(),
+                ..empty_entry(kind)
+            };
+            (build_project(&checkout)?, None, entry)
+        }
+        (_, _, Some(url)) => {
+            let archive = download_source(url, &build_dir)?;
+            let hash = format!("sha256:{}", sha256_file(&archive)?);
+            check_locked(&hash, url)?;
+            let entry = LockfileDependency {
+                hash,
+                url: url.clone(),
+                ..empty_entry(kind)
+            };
+            let wheel = if url.ends_with(".whl") {
+                archive.clone()
+            } else {
+                build_sdist(&archive, &build_dir, &settings.index_url)?
+            };
+            (wheel, Some(archive), entry)
+        }
+        _ => unreachable!("checked by DirectSource::kind"),
+    };
 
-                    let project_source_folder = if let Some(folder) = unzipped_folder {
-                        pkg_build_folder.join(folder)
-                    } else {
-                        // Handle missing unzipped_folder appropriately
-                        panic!("unzipped_folder was None!");
-                    };
+    let metadata = read_wheel_metadata(&wheel)?;
+    if normalize_name(&metadata.name) != name {
+        return Err(format!("its source builds {}, not {}", metadata.name, name).into());
+    }
+    entry.version = metadata.version.clone();
+    let requires_dist = metadata
+        .requires_dist
+        .iter()
+        .map(|requires_dist| requires_dist.parse())
+        .collect::<Result<Vec<Requirement>, _>>()?;
 
-                    let package_final_path =
-                        Path::new("./temp/.box/cache/").join(&pkg_tuple.hash_key());
+    if kind != PATH_SOURCE {
+        // Another commit or download of the same version is built separately
+        let tuple = get_build_tuple(
+            name,
+            &format!("{} {}", metadata.version, entry.hash),
+            system_info.clone(),
+        );
+        let cache_dir = paths.cache.join(tuple.hash_key());
+        std::fs::create_dir_all(&cache_dir)?;
+        // Kept next to the wheel so install can check it against box.lock
+        if let Some(archive) = &archive
+            && *archive != wheel
+        {
+            std::fs::copy(
+                archive,
+                cache_dir.join(archive.file_name().unwrap_or_default()),
+            )?;
+        }
+        entry.path = paths.to_locked(&move_wheel(&wheel, &cache_dir)?);
+        entry.cache_key = tuple.hash_key();
+    }
+    Ok(DirectPackage {
+        entry,
+        requires_dist,
+    })
+}
 
-                    std::fs::create_dir_all(&package_final_path)?;
+// An entry with nothing but its `source` filled in yet
+fn empty_entry(source: &str) -> LockfileDependency {
+    LockfileDependency {
+        version: String::new(),
+        path: String::new(),
+        hash: String::new(),
+        url: String::new(),
+        rev: None,
+        requested_rev: None,
+        cache_key: String::new(),
+        marker: None,
+        dependencies: None,
+        groups: None,
+        source: Some(source.to_string()),
+    }
+}
 
-                    create_venv_and_build(&project_source_folder.as_path());
+/// Whether box.lock's `info` was locked from `source` as the manifests give
+/// it now (with an absolute path, as workspace_sources has it).
+fn same_source(paths: &ProjectPaths, source: &DirectSource, info: &LockfileDependency) -> bool {
+    let Ok(kind) = source.kind() else {
+        return false;
+    };
+    if info.source.as_deref() != Some(kind) {
+        return false;
+    }
+    match (&source.path, &source.git, &source.url) {
+        (Some(dir), _, _) => info.path == paths.to_locked(Path::new(dir)),
+        (_, Some(url), _) => info.url == *url && info.requested_rev == source.rev,
+        (_, _, Some(url)) => info.url == *url,
+        _ => false,
+    }
+}
 
-                    println!("built successfully.");
+/// Resolves the workspace again, preferring `preferences` where they still fit,
+/// and brings whatever changed into the cache. Packages that didn't change
+/// keep their lock entries and cached wheels.
+fn relock(
+    paths: &ProjectPaths,
+    workspace: &Workspace,
+    old_lockfile: &Lockfile,
+    preferences: &BTreeMap<String, Version>,
+    settings: &LockSettings,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let plan = plan_lock(
+        paths,
+        workspace,
+        old_lockfile,
+        settings,
+        preferences,
+        system_info,
+    )?;
+    print_update_table(old_lockfile, &plan);
 
-                    let move_res = move_wheel(
-                        project_source_folder.as_path(),
-                        package_final_path.as_path(),
-                    );
+    let changed: Vec<Job> = plan
+        .jobs
+        .iter()
+        .filter(|job| {
+            old_lockfile
+                .dependencies
+                .get(&job.package)
+                .is_none_or(|locked| {
+                    !same_version(&locked.version, &job.artifact.version.to_string())
+                        || locked.url != job.artifact.url
+                        || !paths.resolve_locked(&locked.path).exists()
+                })
+        })
+        .cloned()
+        .collect();
+    let outputs = run_jobs(
+        &changed,
+        &paths.build,
+        &settings.index_url,
+        settings.jobs,
+        &report_progress,
+    )?;
 
-                    match move_res {
-                        Ok(path) => {
-                            println!("Success! Path is: {}", path.display());
-                            manifest
-                                .dependencies
-                                .insert(name.to_string(), "v1.0.0".to_string());
+    let mut lockfile = Lockfile {
+        python: PythonConfig {
+            python_version: system_info.python_version.clone(),
+        },
+        dependencies: plan.entries,
+    };
+    for job in &plan.jobs {
+        let entry = match (
+            outputs.get(&job.package),
+            old_lockfile.dependencies.get(&job.package),
+        ) {
+            (Some(output), _) => locked_entry(paths, job, output, &plan.requirements, &plan.groups),
+            (None, Some(locked)) => LockfileDependency {
+                marker: direct_marker(&plan.requirements, &job.package),
+                dependencies: Some(job.depends_on.clone()),
+                groups: plan.groups.get(&job.package).cloned(),
+                ..locked.clone()
+            },
+            (None, None) => continue,
+        };
+        lockfile.dependencies.insert(job.package.clone(), entry);
+    }
+    Ok(lockfile)
+}
 
-                            let toml_string = toml::to_string_pretty(&manifest)?;
+/// Every member's requirements. Those on another member are checked
+/// against its version here, since the resolver never sees members.
+fn workspace_requirements(
+    workspace: &Workspace,
+) -> Result<Vec<Requirement>, Box<dyn std::error::Error>> {
+    let mut requirements = Vec::new();
+    for member in &workspace.members {
+        for requirement in manifest_requirements(&member.manifest)? {
+            if let Some(dependency) = workspace.member(&requirement.name)
+                && let Ok(version) = dependency.manifest.project.version.parse::<Version>()
+                && !requirement.specifiers.contains(&version)
+            {
+                return Err(format!(
+                    "{} requires {}, but the workspace has {} {}",
+                    member.manifest.project.name,
+                    requirement,
+                    dependency.manifest.project.name,
+                    version
+                )
+                .into());
+            }
+            requirements.push(requirement);
+        }
+    }
+    Ok(requirements)
+}
 
-                            // Write to a file
-                            std::fs::write("./temp/mypkg.toml", toml_string)?;
+/// What each member depends on (its own and optional dependencies) among
+/// the packages of `graph` and the other members.
+fn member_graph(
+    workspace: &Workspace,
+    graph: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Vec<String>> {
+    workspace
+        .members
+        .iter()
+        .map(|member| {
+            let manifest = &member.manifest;
+            let mut dependencies: Vec<String> = manifest
+                .dependencies
+                .keys()
+                .chain(
+                    manifest
+                        .optional_dependencies
+                        .values()
+                        .flat_map(|deps| deps.keys()),
+                )
+                .map(|dep| normalize_name(dep))
+                .filter(|dep| graph.contains_key(dep) || workspace.member(dep).is_some())
+                .collect();
+            dependencies.sort();
+            dependencies.dedup();
+            (member.normalized_name(), dependencies)
+        })
+        .collect()
+}
 
-                            let new_dep = LockfileDependency {
-                                version: "v1.0.0".into(),
-                                path: path.display().to_string(),
-                                hash: pkg_tuple.hash_key().to_string(),
-                            };
+/// group_roots of every member together.
+fn workspace_roots(workspace: &Workspace) -> BTreeMap<String, HashSet<String>> {
+    let mut roots: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+    for member in &workspace.members {
+        for (group, names) in group_roots(&member.manifest) {
+            roots.entry(group).or_default().extend(names);
+        }
+    }
+    roots
+}
 
-                            lockfile.dependencies.insert(name.to_string(), new_dep);
+/// Every dependency in the manifest, optional ones and dependency groups
+/// included, so one lock covers all of them.
+fn manifest_requirements(
+    manifest: &Manifest,
+) -> Result<Vec<Requirement>, Box<dyn std::error::Error>> {
+    Ok(manifest
+        .dependencies
+        .iter()
+        .chain(manifest.optional_dependencies.values().flatten())
+        .chain(manifest.dependency_groups.values().flatten())
+        .flat_map(|(name, constraints)| {
+            constraints
+                .iter()
+                .map(move |constraint| Requirement::from_constraint(name, constraint))
+        })
+        .collect::<Result<Vec<_>, _>>()?)
+}
 
-                            let lockfile_string = toml::to_string_pretty(&lockfile)?;
+/// The direct dependencies of every group box.lock tracks, by normalized
+/// name: "main" for the project's own (and optional) dependencies, then each
+/// dependency group with the groups it includes.
+fn group_roots(manifest: &Manifest) -> BTreeMap<String, HashSet<String>> {
+    let mut roots = BTreeMap::new();
+    roots.insert(
+        MAIN_GROUP.to_string(),
+        manifest
+            .dependencies
+            .keys()
+            .chain(
+                manifest
+                    .optional_dependencies
+                    .values()
+                    .flat_map(|deps| deps.keys()),
+            )
+            .map(|dep| normalize_name(dep))
+            .collect(),
+    );
 
-                            std::fs::write("./temp/box.lock", lockfile_string)?;
-                            println!("box.lock updated successfully.");
-                        }
-                        Err(e) => {
-                            println!("Error occurred: {}", e);
-                        }
-                    }
+    for group in manifest
+        .dependency_groups
+        .keys()
+        .chain(manifest.group_includes.keys())
+    {
+        let mut names = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![normalize_name(group)];
+        while let Some(current) = stack.pop() {
+            if !seen.insert(current.clone()) {
+                continue;
+            }
+            for (name, deps) in &manifest.dependency_groups {
+                if normalize_name(name) == current {
+                    names.extend(deps.keys().map(|dep| normalize_name(dep)));
                 }
-                Err(e) => {
-                    eprintln!("Error occurred: {}", e);
+            }
+            for (name, includes) in &manifest.group_includes {
+                if normalize_name(name) == current {
+                    stack.extend(includes.iter().map(|include| normalize_name(include)));
                 }
             }
         }
-        Ok(())
+        roots.insert(normalize_name(group), names);
+    }
+    roots
+}
+
+/// Which groups need each package of `graph` (package -> the packages it
+/// depends on), walking it from every group's direct dependencies.
+fn package_groups(
+    roots: &BTreeMap<String, HashSet<String>>,
+    graph: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Vec<String>> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for (group, names) in roots {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<String> = names.iter().cloned().collect();
+        while let Some(name) = stack.pop() {
+            if !graph.contains_key(&name) || !reachable.insert(name.clone()) {
+                continue;
+            }
+            stack.extend(graph[&name].iter().map(|dep| normalize_name(dep)));
+        }
+        for name in reachable {
+            groups.entry(name).or_default().push(group.clone());
+        }
+    }
+    groups
+}
+
+fn job_graph(jobs: &[Job]) -> HashMap<String, Vec<String>> {
+    jobs.iter()
+        .map(|job| (normalize_name(&job.package), job.depends_on.clone()))
+        .collect()
+}
+
+fn lock_graph(lockfile: &Lockfile) -> HashMap<String, Vec<String>> {
+    lockfile
+        .dependencies
+        .iter()
+        .map(|(dep, info)| {
+            (
+                normalize_name(dep),
+                info.dependencies.clone().unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+fn load_manifest(path: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let invalid = |e: &dyn std::fmt::Display| format!("Invalid {}: {}", path.display(), e);
+    if !is_pyproject(path) {
+        let mut table: toml::Table = from_str(&text).map_err(|e| invalid(&e))?;
+        let sources = take_sources(&mut table).map_err(|e| invalid(&e))?;
+        list_constraints(&mut table);
+        let mut manifest: Manifest = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| invalid(&e))?;
+        manifest.sources = named_sources(&manifest.tool.boxpkg.sources).map_err(|e| invalid(&e))?;
+        manifest.sources.extend(sources);
+        add_reference_sources(&mut manifest).map_err(|e| invalid(&e))?;
+        check_groups(&manifest).map_err(|e| invalid(&e))?;
+        return Ok(manifest);
     }
 
-    fn install() {
-        println!("installing...");
+    let pyproject: PyProject = from_str(&text).map_err(|e| invalid(&e))?;
+    let project = pyproject.project;
+    let mut optional_dependencies = BTreeMap::new();
+    for (extra, requirements) in &project.optional_dependencies {
+        optional_dependencies.insert(
+            extra.clone(),
+            requirement_table(requirements).map_err(|e| invalid(&e))?,
+        );
+    }
+    let mut dependency_groups = BTreeMap::new();
+    let mut group_includes = BTreeMap::new();
+    for (group, items) in pyproject.dependency_groups {
+        let mut requirements = Vec::new();
+        let mut includes = Vec::new();
+        for item in items {
+            match item {
+                DependencyGroupItem::Requirement(requirement) => requirements.push(requirement),
+                DependencyGroupItem::Include { include_group } => includes.push(include_group),
+            }
+        }
+        dependency_groups.insert(
+            group.clone(),
+            requirement_table(&requirements).map_err(|e| invalid(&e))?,
+        );
+        if !includes.is_empty() {
+            group_includes.insert(group, includes);
+        }
+    }
 
-        // Read the TOML file content
-        let toml_str = std::fs::read_to_string("./temp/box.lock").expect("Failed to read the file");
+    let mut manifest = Manifest {
+        dependencies: requirement_table(&project.dependencies).map_err(|e| invalid(&e))?,
+        optional_dependencies,
+        dependency_groups,
+        group_includes,
+        sources: named_sources(&pyproject.tool.boxpkg.sources).map_err(|e| invalid(&e))?,
+        project: Project {
+            name: project.name,
+            version: project.version.unwrap_or_default(),
+            requires_python: project.requires_python,
+        },
+        tool: pyproject.tool,
+    };
+    add_reference_sources(&mut manifest).map_err(|e| invalid(&e))?;
+    check_groups(&manifest).map_err(|e| invalid(&e))?;
+    Ok(manifest)
+}
 
-        // Parse the TOML string into a Config struct
-        let lockfile: Lockfile = from_str(&toml_str).expect("Failed to parse TOML");
+// mypkg.toml's [dependencies] and its [optional-dependencies.*] and
+// [dependency-groups.*] tables
+fn dependency_tables(table: &mut toml::Table) -> Vec<&mut toml::Table> {
+    let mut dependency_tables: Vec<&mut toml::Table> = Vec::new();
+    let mut nested = Vec::new();
+    for (key, value) in table.iter_mut() {
+        match (key.as_str(), value) {
+            ("dependencies", toml::Value::Table(dependencies)) => {
+                dependency_tables.push(dependencies)
+            }
+            ("optional-dependencies" | "dependency-groups", toml::Value::Table(tables)) => {
+                nested.push(tables)
+            }
+            _ => {}
+        }
+    }
+    for tables in nested {
+        dependency_tables.extend(
+            tables
+                .iter_mut()
+                .filter_map(|(_, value)| value.as_table_mut()),
+        );
+    }
+    dependency_tables
+}
 
-        let project_box_path = Path::new("./temp/.box/");
-        let _ = create_python_env(&project_box_path);
-        let project_box_path_venv = Path::new("./temp/.box/venv/");
-        println!("create_python_env finished!");
+// mypkg.toml's `name = { path = ... }` entries: each is replaced by a plain
+// "*" so the rest of the manifest reads as usual
+fn take_sources(table: &mut toml::Table) -> Result<BTreeMap<String, DirectSource>, String> {
+    let mut sources = BTreeMap::new();
+    for dependencies in dependency_tables(table) {
+        for (name, value) in dependencies.iter_mut() {
+            if !value.is_table() {
+                continue;
+            }
+            let source: DirectSource = std::mem::replace(value, toml::Value::from("*"))
+                .try_into()
+                .map_err(|e| format!("source of {}: {}", name, e))?;
+            source
+                .kind()
+                .map_err(|e| format!("source of {}: {}", name, e))?;
+            match sources.get(&normalize_name(name)) {
+                Some(other) if *other != source => {
+                    return Err(format!("{} is given two different sources", name));
+                }
+                _ => {
+                    sources.insert(normalize_name(name), source);
+                }
+            }
+        }
+    }
+    Ok(sources)
+}
 
-        for (dep, info) in &lockfile.dependencies {
-            println!("Dependency: {} Version: {}", dep, info.version);
-            let _ = install_wheel(&project_box_path_venv, Path::new(Path::new(&info.path)));
+// mypkg.toml's `name = "constraint"` as the one-entry list of constraints a
+// marker split would write as `name = ["constraint; marker", ...]`
+fn list_constraints(table: &mut toml::Table) {
+    for dependencies in dependency_tables(table) {
+        for (_, value) in dependencies.iter_mut() {
+            if value.is_str() {
+                let constraint = std::mem::replace(value, toml::Value::Array(Vec::new()));
+                *value = toml::Value::Array(vec![constraint]);
+            }
         }
     }
 }
+
+// [tool.boxpkg.sources], by normalized name
+fn named_sources(
+    sources: &BTreeMap<String, DirectSource>,
+) -> Result<BTreeMap<String, DirectSource>, String> {
+    let mut named = BTreeMap::new();
+    for (name, source) in sources {
+        source
+            .kind()
+            .map_err(|e| format!("source of {}: {}", name, e))?;
+        named.insert(normalize_name(name), source.clone());
+    }
+    Ok(named)
+}
+
+// Each `name @ url` requirement as the source it names; the requirement
+// keeps its URL, so writing the manifest back leaves it as it was
+fn add_reference_sources(manifest: &mut Manifest) -> Result<(), String> {
+    let tables = std::iter::once(&manifest.dependencies).chain(
+        manifest
+            .optional_dependencies
+            .values()
+            .chain(manifest.dependency_groups.values()),
+    );
+    let mut references = Vec::new();
+    for (name, constraints) in tables.flatten() {
+        for constraint in constraints {
+            let requirement = Requirement::from_constraint(name, constraint)
+                .map_err(|e| format!("invalid requirement on {}: {}", name, e))?;
+            if let Some(url) = &requirement.url {
+                let source = DirectSource::from_url(url)
+                    .map_err(|e| format!("source of {}: {}", name, e))?;
+                references.push((name.clone(), source));
+            }
+        }
+    }
+    for (name, source) in references {
+        match manifest.sources.get(&normalize_name(&name)) {
+            Some(other) if *other != source => {
+                return Err(format!("{} is given two different sources", name));
+            }
+            _ => {
+                manifest.sources.insert(normalize_name(&name), source);
+            }
+        }
+    }
+    Ok(())
+}
+
+// box.lock calls the project's own dependencies "main", and an included
+// group has to exist
+fn check_groups(manifest: &Manifest) -> Result<(), String> {
+    let groups: HashSet<String> = manifest
+        .dependency_groups
+        .keys()
+        .map(|group| normalize_name(group))
+        .collect();
+    if groups.contains(MAIN_GROUP) {
+        return Err(format!(
+            "\"{}\" can't be a dependency group name",
+            MAIN_GROUP
+        ));
+    }
+    for (group, includes) in &manifest.group_includes {
+        if let Some(missing) = includes
+            .iter()
+            .find(|include| !groups.contains(&normalize_name(include)))
+        {
+            return Err(format!(
+                "dependency group {} includes {}, which doesn't exist",
+                group, missing
+            ));
+        }
+    }
+    Ok(())
+}
+
+// PEP 508 strings keyed the way [dependencies] is: name -> the rest of each
+// requirement on it, under the name's first spelling
+fn requirement_table(requirements: &[String]) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let mut table: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for requirement in requirements {
+        let parsed: Requirement = requirement
+            .parse()
+            .map_err(|e| format!("invalid requirement \"{}\": {}", requirement, e))?;
+        let name = table
+            .keys()
+            .find(|name| normalize_name(name) == parsed.normalized_name())
+            .cloned()
+            .unwrap_or_else(|| parsed.name.clone());
+        table.entry(name).or_default().push(parsed.constraint());
+    }
+    Ok(table)
+}
+
+/// box.lock, or an empty lock when there isn't one yet.
+fn load_lockfile(
+    paths: &ProjectPaths,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    if !paths.lockfile.exists() {
+        return Ok(Lockfile {
+            python: PythonConfig {
+                python_version: system_info.python_version.clone(),
+            },
+            dependencies: BTreeMap::new(),
+        });
+    }
+    let text = std::fs::read_to_string(&paths.lockfile)?;
+    Ok(from_str(&text).map_err(|e| format!("Invalid {}: {}", paths.lockfile.display(), e))?)
+}
+
+/// box.lock as install, sync and remove need it: there, and readable.
+fn read_lockfile(paths: &ProjectPaths) -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(&paths.lockfile).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            format!(
+                "No {} found, run `box lock` first",
+                paths.lockfile.display()
+            )
+        } else {
+            format!("Can't read {}: {}", paths.lockfile.display(), e)
+        }
+    })?;
+    Ok(from_str(&text).map_err(|e| {
+        format!(
+            "Invalid {}, run `box lock` to regenerate it: {}",
+            paths.lockfile.display(),
+            e
+        )
+    })?)
+}
+
+/// Writes the manifest's dependencies into mypkg.toml or pyproject.toml,
+/// editing the file in place so comments, formatting and everything else
+/// in it survive.
+fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let mut document: DocumentMut = text.parse()?;
+
+    if is_pyproject(path) {
+        let project = document
+            .get_mut("project")
+            .and_then(|project| project.as_table_like_mut())
+            .ok_or("[project] in pyproject.toml isn't a table")?;
+        let dependencies = project
+            .entry("dependencies")
+            .or_insert(toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or("project.dependencies in pyproject.toml isn't an array")?;
+        sync_requirement_array(dependencies, &manifest.dependencies)?;
+
+        sync_requirement_arrays(
+            project,
+            "project.optional-dependencies",
+            &manifest.optional_dependencies,
+        )?;
+        sync_requirement_arrays(
+            document.as_table_mut(),
+            "dependency-groups",
+            &manifest.dependency_groups,
+        )?;
+    } else {
+        let dependencies = document
+            .entry("dependencies")
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .ok_or("[dependencies] in mypkg.toml isn't a table")?;
+        sync_dependency_table(dependencies, &manifest.dependencies);
+
+        sync_dependency_tables(
+            &mut document,
+            "optional-dependencies",
+            &manifest.optional_dependencies,
+        )?;
+        sync_dependency_tables(
+            &mut document,
+            "dependency-groups",
+            &manifest.dependency_groups,
+        )?;
+    }
+
+    std::fs::write(path, document.to_string())?;
+    Ok(())
+}
+
+// mypkg.toml's [<key>.<name>] tables, e.g. [dependency-groups.dev]
+fn sync_dependency_tables(
+    document: &mut DocumentMut,
+    key: &str,
+    tables: &BTreeMap<String, BTreeMap<String, Vec<String>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if tables.is_empty() {
+        return Ok(());
+    }
+    let parent = document
+        .entry(key)
+        .or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        })
+        .as_table_like_mut()
+        .ok_or_else(|| format!("[{}] in mypkg.toml isn't a table", key))?;
+    for (name, dependencies) in tables {
+        let table = parent
+            .entry(name)
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .ok_or_else(|| format!("[{}.{}] in mypkg.toml isn't a table", key, name))?;
+        sync_dependency_table(table, dependencies);
+    }
+    Ok(())
+}
+
+// pyproject.toml's `<key>.<name> = [...]` arrays, e.g. `dependency-groups.dev`
+fn sync_requirement_arrays(
+    table: &mut dyn toml_edit::TableLike,
+    key: &str,
+    arrays: &BTreeMap<String, BTreeMap<String, Vec<String>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if arrays.is_empty() {
+        return Ok(());
+    }
+    let last_key = key.rsplit('.').next().unwrap_or(key);
+    let parent = table
+        .entry(last_key)
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| format!("{} in pyproject.toml isn't a table", key))?;
+    for (name, dependencies) in arrays {
+        let array = parent
+            .entry(name)
+            .or_insert(toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| format!("{}.{} in pyproject.toml isn't an array", key, name))?;
+        sync_requirement_array(array, dependencies)?;
+    }
+    Ok(())
+}
+
+fn sync_dependency_table(
+    table: &mut dyn toml_edit::TableLike,
+    dependencies: &BTreeMap<String, Vec<String>>,
+) {
+    let stale: Vec<String> = table
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| !dependencies.contains_key(name))
+        .collect();
+    for name in &stale {
+        table.remove(name);
+    }
+    for (name, constraints) in dependencies {
+        // Untouched entries keep their formatting, and sources stay as written
+        let existing = table.get(name);
+        if existing.is_some_and(|item| item.is_inline_table() || item.is_table()) {
+            continue;
+        }
+        let written: Option<Vec<&str>> = existing.and_then(|item| match item.as_array() {
+            Some(array) => array.iter().map(|value| value.as_str()).collect(),
+            None => item.as_str().map(|constraint| vec![constraint]),
+        });
+        if written.is_some_and(|written| written == *constraints) {
+            continue;
+        }
+        // One constraint is a string, a marker split an array of them
+        let constraint: toml_edit::Value = match constraints.as_slice() {
+            [constraint] => constraint.as_str().into(),
+            constraints => constraints.iter().collect(),
+        };
+        match table.get_mut(name).and_then(|item| item.as_value_mut()) {
+            // The key, and a comment above or after it, stay as they are
+            Some(value) => {
+                let decor = value.decor().clone();
+                *value = constraint;
+                *value.decor_mut() = decor;
+            }
+            None => {
+                table.insert(name, toml_edit::value(constraint));
+            }
+        }
+    }
+}
+
+/// Makes a PEP 508 array hold `dependencies`: entries that stay keep their
+/// place, formatting and comments, new ones go at the end.
+fn sync_requirement_array(
+    array: &mut toml_edit::Array,
+    dependencies: &BTreeMap<String, Vec<String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Whatever isn't a requirement boxpkg understands is left alone
+    let parse = |value: &toml_edit::Value| {
+        value
+            .as_str()
+            .and_then(|requirement| requirement.parse::<Requirement>().ok())
+    };
+
+    // The constraints not in the array yet, by normalized name; an entry
+    // that already has one of them is kept as it is
+    let mut missing: HashMap<String, (&String, Vec<&String>)> = dependencies
+        .iter()
+        .map(|(name, constraints)| (normalize_name(name), (name, constraints.iter().collect())))
+        .collect();
+    let mut keep = Vec::new();
+    for value in array.iter() {
+        let kept = parse(value).is_none_or(|existing| {
+            let Some((_, constraints)) = missing.get_mut(&existing.normalized_name()) else {
+                return false;
+            };
+            let position = constraints
+                .iter()
+                .position(|constraint| **constraint == existing.constraint());
+            position.map(|i| constraints.remove(i)).is_some()
+        });
+        keep.push(kept);
+    }
+
+    let mut i = 0;
+    while i < array.len() {
+        if keep[i] {
+            i += 1;
+            continue;
+        }
+        let existing = array
+            .get(i)
+            .and_then(parse)
+            .expect("entries that aren't requirements are kept");
+        // One the manifest changed takes the next constraint on that name
+        match missing
+            .get_mut(&existing.normalized_name())
+            .filter(|(_, constraints)| !constraints.is_empty())
+        {
+            Some((name, constraints)) => {
+                let requirement = Requirement::from_constraint(name, constraints.remove(0))?;
+                array.replace(i, requirement.to_string());
+                i += 1;
+            }
+            None => {
+                let removed = array.remove(i);
+                // The next entry takes the place of the first one, spacing included
+                if i == 0
+                    && let Some(next) = array.get_mut(0)
+                {
+                    next.decor_mut()
+                        .set_prefix(removed.decor().prefix().cloned().unwrap_or_default());
+                }
+                keep.remove(i);
+            }
+        }
+    }
+
+    let added = dependencies.keys().flat_map(|name| {
+        let (_, constraints) = &missing[&normalize_name(name)];
+        constraints
+            .iter()
+            .map(move |constraint| (name, *constraint))
+    });
+    for (name, constraint) in added {
+        let requirement = Requirement::from_constraint(name, constraint)?.to_string();
+        // Lined up like the entries before it, one per line or all on one
+        let prefix = array
+            .iter()
+            .last()
+            .and_then(|last| last.decor().prefix()?.as_str().map(str::to_string))
+            .filter(|prefix| !prefix.is_empty());
+        match prefix {
+            Some(prefix) => {
+                // Only the line break and indentation, not a comment before it
+                let indent = prefix.rfind('\n').map_or(&prefix[..], |i| &prefix[i..]);
+                let mut value = toml_edit::Value::from(requirement);
+                value.decor_mut().set_prefix(indent);
+                array.push_formatted(value);
+            }
+            None => array.push(requirement),
+        }
+    }
+    Ok(())
+}
+
+/// Writes box.lock, keeping whatever the existing file has that boxpkg
+/// doesn't know about. Entries come out sorted, so the same lock is always
+/// written the same way.
+fn write_lockfile(
+    paths: &ProjectPaths,
+    lockfile: &Lockfile,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(&paths.lockfile).unwrap_or_default();
+    let mut document: DocumentMut = text.parse()?;
+
+    let python = document
+        .entry("python")
+        .or_insert(toml_edit::table())
+        .as_table_mut()
+        .ok_or("[python] in box.lock isn't a table")?;
+    python.set_position(0);
+    set_str(
+        python,
+        "python_version",
+        Some(&lockfile.python.python_version),
+    );
+
+    let dependencies = document
+        .entry("dependencies")
+        .or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        })
+        .as_table_mut()
+        .ok_or("[dependencies] in box.lock isn't a table")?;
+    // Only the [dependencies.<name>] headers are written
+    dependencies.set_implicit(true);
+    let stale: Vec<String> = dependencies
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| !lockfile.dependencies.contains_key(name))
+        .collect();
+    for name in &stale {
+        dependencies.remove(name);
+    }
+
+    for (position, (name, info)) in lockfile.dependencies.iter().enumerate() {
+        let entry = dependencies
+            .entry(name)
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or_else(|| format!("[dependencies.{}] in box.lock isn't a table", name))?;
+        entry.set_position(position + 1);
+        set_str(entry, "version", Some(&info.version));
+        set_str(entry, "path", Some(&info.path));
+        // Workspace members have no download to record
+        set_str(entry, "hash", non_empty(&info.hash));
+        set_str(entry, "url", non_empty(&info.url));
+        set_str(entry, "rev", info.rev.as_deref());
+        set_str(entry, "requested_rev", info.requested_rev.as_deref());
+        set_str(entry, "cache_key", non_empty(&info.cache_key));
+        set_str(entry, "marker", info.marker.as_deref());
+        set_str_array(entry, "dependencies", info.dependencies.as_deref());
+        set_str_array(entry, "groups", info.groups.as_deref());
+        set_str(entry, "source", info.source.as_deref());
+    }
+    dependencies.sort_values();
+
+    std::fs::write(&paths.lockfile, document.to_string())?;
+    Ok(())
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+// Leaves the value (and its formatting) alone when it hasn't changed
+fn set_str(table: &mut toml_edit::Table, key: &str, value: Option<&str>) {
+    match value {
+        Some(value) if table.get(key).and_then(|item| item.as_str()) != Some(value) => {
+            table.insert(key, toml_edit::value(value));
+        }
+        Some(_) => {}
+        None => {
+            table.remove(key);
+        }
+    }
+}
+
+fn set_str_array(table: &mut toml_edit::Table, key: &str, values: Option<&[String]>) {
+    let Some(values) = values else {
+        table.remove(key);
+        return;
+    };
+    let current: Option<Vec<&str>> = table
+        .get(key)
+        .and_then(|item| item.as_array())
+        .map(|array| array.iter().filter_map(|v| v.as_str()).collect());
+    if current.is_some_and(|current| current == values) {
+        return;
+    }
+    let array: toml_edit::Array = values.iter().map(String::as_str).collect();
+    table.insert(key, toml_edit::value(array));
+}
+
+fn print_update_table(old_lockfile: &Lockfile, plan: &LockPlan) {
+    let mut rows: BTreeMap<&str, (String, String)> = BTreeMap::new();
+    for (dep, locked) in &old_lockfile.dependencies {
+        rows.insert(dep, (locked.version.clone(), "-".to_string()));
+    }
+    let planned = plan
+        .jobs
+        .iter()
+        .map(|job| (job.package.as_str(), job.artifact.version.to_string()))
+        .chain(
+            plan.entries
+                .iter()
+                .map(|(name, entry)| (name.as_str(), entry.version.clone())),
+        );
+    for (package, version) in planned {
+        rows.entry(package)
+            .or_insert_with(|| ("-".to_string(), String::new()))
+            .1 = version;
+    }
+
+    let changed: Vec<(&str, String, String)> = rows
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(package, (old, new))| (package, old, new))
+        .collect();
+    if changed.is_empty() {
+        println!("Everything is up to date");
+        return;
+    }
+
+    let width = changed
+        .iter()
+        .map(|(package, _, _)| package.len())
+        .max()
+        .unwrap_or(0)
+        .max("Package".len());
+    let old_width = changed
+        .iter()
+        .map(|(_, old, _)| old.len())
+        .max()
+        .unwrap_or(0)
+        .max("Old".len());
+    println!("{:<width$}  {:<old_width$}     New", "Package", "Old");
+    for (package, old, new) in &changed {
+        println!("{:<width$}  {:<old_width$} ->  {}", package, old, new);
+    }
+}
+
+/// Resolves `requirements` and picks the artifact of every package in the
+/// resolution, as jobs that download (and build) them into the cache.
+fn plan_jobs(
+    paths: &ProjectPaths,
+    project_name: &str,
+    requirements: &[Requirement],
+    direct: &mut BTreeMap<String, DirectPackage>,
+    settings: &LockSettings,
+    preferences: &BTreeMap<String, Version>,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(&paths.build)?;
+
+    let index = IndexClient::new(&settings.index_url)?;
+    let mut provider = IndexDependencyProvider::new(
+        index,
+        &paths.build,
+        &system_info.python_version,
+        system_info.tags.clone(),
+        settings.policy.clone(),
+    );
+    for (package, version) in preferences {
+        provider.prefer(package, version.clone());
+    }
+    for (package, direct) in direct.iter() {
+        provider.pin(
+            package,
+            direct.entry.version.parse()?,
+            direct.requires_dist.clone(),
+        );
+    }
+
+    let resolution = resolve(
+        project_name,
+        requirements,
+        &system_info.markers,
+        &mut provider,
+    )?;
+
+    let mut jobs = Vec::new();
+    for (pkg_name, version) in &resolution.packages {
+        println!("Resolved {} {}", pkg_name, version);
+
+        // Already fetched and built, only its dependencies were missing
+        if let Some(package) = direct.get_mut(pkg_name) {
+            let mut depends_on = resolution
+                .dependencies
+                .get(pkg_name)
+                .cloned()
+                .unwrap_or_default();
+            depends_on.sort();
+            package.entry.dependencies = Some(depends_on);
+            continue;
+        }
+
+        let pkg_tuple = get_build_tuple(pkg_name, &version.to_string(), system_info.clone());
+        println!("Cache key: {}", pkg_tuple.hash_key());
+
+        let package_final_path = paths.cache.join(pkg_tuple.hash_key());
+        std::fs::create_dir_all(&package_final_path)?;
+
+        let pinned: SpecifierSet = format!("=={}", version).parse()?;
+        let artifact = select_artifact(
+            provider.files(pkg_name)?,
+            &pinned,
+            pkg_name,
+            &system_info.tags,
+            system_info.python_version.parse().ok().as_ref(),
+            &settings.policy,
+        )
+        .ok_or_else(|| format!("No usable distribution found for {}", pkg_name))?
+        .clone();
+
+        let mut depends_on = resolution
+            .dependencies
+            .get(pkg_name)
+            .cloned()
+            .unwrap_or_default();
+        depends_on.sort();
+
+        jobs.push(Job {
+            package: pkg_name.clone(),
+            artifact,
+            cache_dir: package_final_path,
+            depends_on,
+            // Sdists built during resolution to read their metadata aren't built twice
+            prebuilt: provider
+                .built_wheel(pkg_name, version)
+                .map(Path::to_path_buf),
+        });
+    }
+    Ok(jobs)
+}
+
+fn locked_entry(
+    paths: &ProjectPaths,
+    job: &Job,
+    output: &JobOutput,
+    requirements: &[Requirement],
+    groups: &HashMap<String, Vec<String>>,
+) -> LockfileDependency {
+    LockfileDependency {
+        version: job.artifact.version.to_string(),
+        path: paths.to_locked(&output.wheel),
+        hash: output.source_hash.clone(),
+        url: job.artifact.url.clone(),
+        rev: None,
+        requested_rev: None,
+        cache_key: job
+            .cache_dir
+            .file_name()
+            .map(|key| key.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        marker: direct_marker(requirements, &job.package),
+        dependencies: Some(job.depends_on.clone()),
+        groups: groups.get(&job.package).cloned(),
+        source: None,
+    }
+}
+
+// Only a direct requirement's marker is kept; transitive ones were
+// already evaluated during resolution. Requirements split by markers lock
+// the package wherever any of them applies.
+fn direct_marker(requirements: &[Requirement], package: &str) -> Option<String> {
+    let mut markers = Vec::new();
+    for requirement in requirements
+        .iter()
+        .filter(|requirement| requirement.normalized_name() == package)
+    {
+        // One without a marker applies everywhere
+        let marker = requirement.marker.clone()?;
+        if !markers.contains(&marker) {
+            markers.push(marker);
+        }
+    }
+    match markers.len() {
+        0 => None,
+        1 => markers.pop().map(|marker| marker.to_string()),
+        _ => Some(box_core::Marker::Or(markers).to_string()),
+    }
+}
+
+fn install(
+    paths: &ProjectPaths,
+    package: Option<&str>,
+    groups: &GroupArgs,
+    system_info: &box_core::SystemEnvironmentInfo,
+    jobs_count: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("installing...");
+
+    let lockfile = read_lockfile(paths)?;
+    let selection = select(paths, package, groups, &lockfile)?;
+    let index_url = configured_index_url(&Workspace::load(paths)?.root().manifest.tool.boxpkg);
+
+    setup_python_env(&paths.box_dir)?;
+    let project_box_path_venv = paths.venv.as_path();
+
+    let to_install = locked_for_environment(&lockfile, &selection, system_info);
+    let hashes = source_hashes(paths, &to_install)?;
+    let wheels = prepare_wheels(paths, &to_install, &index_url, jobs_count)?;
+
+    for (dep, info) in &to_install {
+        println!("Dependency: {} Version: {}", dep, info.version);
+        install_wheel(
+            project_box_path_venv,
+            &wheels[dep.as_str()],
+            Some(&hashes[dep.as_str()]),
+        )?;
+    }
+    Ok(())
+}
+
+/// Makes the venv hold exactly what box.lock says for the selected groups
+/// (and workspace member): packages it doesn't list are uninstalled, ones
+/// whose version or source hash changed are reinstalled, and nothing
+/// happens when it already matches.
+fn sync(
+    paths: &ProjectPaths,
+    package: Option<&str>,
+    groups: &GroupArgs,
+    system_info: &box_core::SystemEnvironmentInfo,
+    jobs_count: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("syncing...");
+
+    let lockfile = read_lockfile(paths)?;
+    let selection = select(paths, package, groups, &lockfile)?;
+    let index_url = configured_index_url(&Workspace::load(paths)?.root().manifest.tool.boxpkg);
+
+    setup_python_env(&paths.box_dir)?;
+    let project_box_path_venv = paths.venv.as_path();
+
+    let wanted = locked_for_environment(&lockfile, &selection, system_info);
+    let hashes = source_hashes(paths, &wanted)?;
+    let installed = installed_distributions(project_box_path_venv)?;
+    let plan = plan_sync(installed, &wanted, &hashes);
+    if plan.is_empty() {
+        println!("Environment already matches box.lock");
+        return Ok(());
+    }
+
+    // Everything is fetched and built before the environment is touched
+    let wheels = prepare_wheels(paths, &plan.to_install, &index_url, jobs_count)?;
+
+    for installed in &plan.to_remove {
+        println!("- {} {}", installed.name, installed.version);
+        uninstall(&installed.dist_info)?;
+    }
+    for (dep, info) in &plan.to_install {
+        println!("+ {} {}", dep, info.version);
+        install_wheel(
+            project_box_path_venv,
+            &wheels[dep.as_str()],
+            Some(&hashes[dep.as_str()]),
+        )?;
+    }
+    println!(
+        "Synced: {} removed, {} installed, {} unchanged",
+        plan.to_remove.len(),
+        plan.to_install.len(),
+        plan.unchanged
+    );
+    Ok(())
+}
+
+/// Removes `name` from the manifest's dependencies, an extra or a dependency group.
+fn remove(
+    paths: &ProjectPaths,
+    package: Option<&str>,
+    name: &str,
+    target: &DependencyTarget,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Removing... {}", name);
+
+    let mut workspace = Workspace::load(paths)?;
+    let member = workspace.target(paths, package)?;
+    let mut lockfile = read_lockfile(paths)?;
+
+    let manifest = &mut workspace.members[member].manifest;
+    let project_name = manifest.project.name.clone();
+    let dependencies = match target {
+        DependencyTarget::Project => Some(&mut manifest.dependencies),
+        DependencyTarget::Extra(extra) => manifest.optional_dependencies.get_mut(extra),
+        // Group names compare normalized (PEP 735), so --group Dev finds dev
+        DependencyTarget::Group(group) => manifest
+            .dependency_groups
+            .iter_mut()
+            .find(|(name, _)| normalize_name(name) == normalize_name(group))
+            .map(|(_, dependencies)| dependencies),
+    }
+    .ok_or_else(|| format!("{} has no {}", project_name, target))?;
+    let normalized = normalize_name(name);
+    let key = dependencies
+        .keys()
+        .find(|dep| normalize_name(dep) == normalized)
+        .cloned()
+        .ok_or_else(|| format!("{} isn't in the {} of {}", name, target, project_name))?;
+    dependencies.remove(&key);
+
+    // A member no longer depends on what it dropped
+    let member_graph = member_graph(&workspace, &lock_graph(&lockfile));
+    for (dep, info) in lockfile.dependencies.iter_mut() {
+        if info.source.as_deref() == Some(WORKSPACE_SOURCE) {
+            info.dependencies = member_graph.get(&normalize_name(dep)).cloned();
+        }
+    }
+
+    let removed = orphaned_entries(&workspace, &lockfile, &normalized);
+    for dep in &removed {
+        lockfile.dependencies.remove(dep);
+    }
+    // What's left may be needed by fewer groups now
+    if lockfile
+        .dependencies
+        .values()
+        .all(|info| info.dependencies.is_some())
+    {
+        let groups = package_groups(&workspace_roots(&workspace), &lock_graph(&lockfile));
+        for (dep, info) in lockfile.dependencies.iter_mut() {
+            info.groups = groups.get(&normalize_name(dep)).cloned();
+        }
+    }
+
+    // Only what was dropped from the lock leaves the environment
+    let project_box_path_venv = paths.venv.as_path();
+    if project_box_path_venv.exists() {
+        let removed_names: HashSet<String> =
+            removed.iter().map(|dep| normalize_name(dep)).collect();
+        for installed in installed_distributions(project_box_path_venv)? {
+            if removed_names.contains(&normalize_name(&installed.name)) {
+                println!("- {} {}", installed.name, installed.version);
+                uninstall(&installed.dist_info)?;
+            }
+        }
+    }
+
+    let member = &workspace.members[member];
+    write_manifest(&member.manifest_path, &member.manifest)?;
+    write_lockfile(paths, &lockfile)?;
+    println!("Removed {} ({} locked packages)", key, removed.len());
+    Ok(())
+}
+
+/// The lock entries nothing left in the workspace needs once `removed` is
+/// gone: everything no group reaches anymore.
+fn orphaned_entries(workspace: &Workspace, lockfile: &Lockfile, removed: &str) -> Vec<String> {
+    let by_name: HashMap<String, &String> = lockfile
+        .dependencies
+        .keys()
+        .map(|dep| (normalize_name(dep), dep))
+        .collect();
+
+    // Without the dependency graph, only the package itself is known to be unused
+    if lockfile
+        .dependencies
+        .values()
+        .any(|info| info.dependencies.is_none())
+    {
+        println!("Warning: box.lock doesn't record dependencies, its other entries are kept");
+        return by_name
+            .get(removed)
+            .map(|dep| vec![dep.to_string()])
+            .unwrap_or_default();
+    }
+
+    let groups = package_groups(&workspace_roots(workspace), &lock_graph(lockfile));
+    let mut orphaned: Vec<String> = by_name
+        .iter()
+        .filter(|(name, _)| !groups.contains_key(*name))
+        .map(|(_, dep)| dep.to_string())
+        .collect();
+    orphaned.sort();
+    orphaned
+}
+
+fn same_version(installed: &str, locked: &str) -> bool {
+    match (installed.parse::<Version>(), locked.parse::<Version>()) {
+        (Ok(installed), Ok(locked)) => installed == locked,
+        _ => installed == locked,
+    }
+}
+
+/// What install and sync put in the environment: the lock entries of
+/// `groups`, limited to what `packages` names when a member is picked,
+/// and the workspace's own projects, installed editable.
+struct Selection {
+    groups: HashSet<String>,
+    packages: Option<HashSet<String>>,
+    projects: Vec<(String, LockfileDependency)>,
+}
+
+/// The groups `args` picks out of the ones the workspace has, and with
+/// `package`, the lock entries that member needs from them.
+fn select(
+    paths: &ProjectPaths,
+    package: Option<&str>,
+    args: &GroupArgs,
+    lockfile: &Lockfile,
+) -> Result<Selection, Box<dyn std::error::Error>> {
+    let workspace = Workspace::load(paths)?;
+    let projects = if args.only_group.is_empty() {
+        editable_projects(paths, &workspace, package, lockfile)?
+    } else {
+        Vec::new()
+    };
+    let (name, known) = match package {
+        Some(package) => {
+            let member = &workspace.members[workspace.target(paths, Some(package))?];
+            (&member.manifest.project.name, group_roots(&member.manifest))
+        }
+        None => (
+            &workspace.root().manifest.project.name,
+            workspace_roots(&workspace),
+        ),
+    };
+    if let Some(unknown) = args
+        .group
+        .iter()
+        .chain(&args.only_group)
+        .find(|group| !known.contains_key(&normalize_name(group)))
+    {
+        return Err(format!("{} has no dependency group {}", name, unknown).into());
+    }
+
+    let groups: HashSet<String> = if !args.only_group.is_empty() {
+        args.only_group
+            .iter()
+            .map(|group| normalize_name(group))
+            .collect()
+    } else {
+        let mut selected: HashSet<String> = args
+            .group
+            .iter()
+            .map(|group| normalize_name(group))
+            .collect();
+        selected.insert(MAIN_GROUP.to_string());
+        if !args.no_dev && known.contains_key(DEV_GROUP) {
+            selected.insert(DEV_GROUP.to_string());
+        }
+        selected
+    };
+    if package.is_none() {
+        return Ok(Selection {
+            groups,
+            packages: None,
+            projects,
+        });
+    }
+
+    if lockfile
+        .dependencies
+        .values()
+        .any(|info| info.dependencies.is_none())
+    {
+        return Err("box.lock doesn't record dependencies, run box lock first".into());
+    }
+    let roots: BTreeMap<String, HashSet<String>> = known
+        .into_iter()
+        .filter(|(group, _)| groups.contains(group))
+        .collect();
+    let packages = package_groups(&roots, &lock_graph(lockfile))
+        .into_keys()
+        .collect();
+    Ok(Selection {
+        groups,
+        packages: Some(packages),
+        projects,
+    })
+}
+
+/// Entries for the members install and sync put into the environment in
+/// development mode: `package`, or every member. Those without a build
+/// system only hold dependencies and aren't installed, nor are members
+/// box.lock has an entry for already.
+fn editable_projects(
+    paths: &ProjectPaths,
+    workspace: &Workspace,
+    package: Option<&str>,
+    lockfile: &Lockfile,
+) -> Result<Vec<(String, LockfileDependency)>, Box<dyn std::error::Error>> {
+    let members = match package {
+        Some(package) => vec![&workspace.members[workspace.target(paths, Some(package))?]],
+        None => workspace.members.iter().collect(),
+    };
+    Ok(members
+        .into_iter()
+        .filter(|member| has_build_system(&member.dir))
+        .filter(|member| {
+            !lockfile
+                .dependencies
+                .keys()
+                .any(|dep| normalize_name(dep) == member.normalized_name())
+        })
+        .map(|member| {
+            let entry = LockfileDependency {
+                version: member.manifest.project.version.clone(),
+                path: paths.to_locked(&member.dir),
+                ..empty_entry(WORKSPACE_SOURCE)
+            };
+            (member.normalized_name(), entry)
+        })
+        .collect())
+}
+
+/// The lock entries `selection` picks whose marker applies to this environment.
+fn locked_for_environment<'a>(
+    lockfile: &'a Lockfile,
+    selection: &'a Selection,
+    system_info: &box_core::SystemEnvironmentInfo,
+) -> Vec<(&'a String, &'a LockfileDependency)> {
+    let mut locked = Vec::new();
+    for (dep, info) in &lockfile.dependencies {
+        // Locks from before groups only have the project's own dependencies
+        let wanted = match &info.groups {
+            Some(groups) => groups.iter().any(|group| selection.groups.contains(group)),
+            None => selection.groups.contains(MAIN_GROUP),
+        };
+        let in_package = selection
+            .packages
+            .as_ref()
+            .is_none_or(|packages| packages.contains(&normalize_name(dep)));
+        if !wanted || !in_package {
+            continue;
+        }
+        if let Some(marker) = &info.marker {
+            let applies = marker
+                .parse::<Marker>()
+                .map(|marker| marker.evaluate(&system_info.markers, &[]))
+                .unwrap_or(true);
+            if !applies {
+                println!("Skipping {}, marker doesn't match: {}", dep, marker);
+                continue;
+            }
+        }
+        locked.push((dep, info));
+    }
+    locked.extend(selection.projects.iter().map(|(name, entry)| (name, entry)));
+    locked
+}
+
+/// The hash each entry's wheel is installed with. Workspace members and
+/// path dependencies are installed editable, so only a change to their
+/// build configuration (see editable_hash) has sync install them again.
+fn source_hashes(
+    paths: &ProjectPaths,
+    locked: &[(&String, &LockfileDependency)],
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut hashes = HashMap::new();
+    for (dep, info) in locked {
+        let hash = if info.is_local() {
+            editable_hash(&paths.resolve_locked(&info.path))?
+        } else {
+            info.hash.clone()
+        };
+        hashes.insert(dep.to_string(), hash);
+    }
+    Ok(hashes)
+}
+
+/// Stands in for the source hash of an editable install: the hashes of the
+/// project's build configuration, which its metadata comes from.
+fn editable_hash(dir: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hashes = Vec::new();
+    for file in BUILD_CONFIG_FILES {
+        let path = dir.join(file);
+        if path.exists() {
+            hashes.push(format!("{}:{}", file, sha256_file(&path)?));
+        }
+    }
+    Ok(format!("editable {}", hashes.join(" ")))
+}
+
+/// The cached wheel of every entry, after checking its source against
+/// box.lock. Wheels gone from the cache are fetched (and built) again from
+/// the locked source; workspace members and path dependencies get an
+/// editable wheel built from their directory. Builds get their requirements
+/// from `index_url`.
+fn prepare_wheels(
+    paths: &ProjectPaths,
+    locked: &[(&String, &LockfileDependency)],
+    index_url: &str,
+    jobs_count: usize,
+) -> Result<HashMap<String, PathBuf>, Box<dyn std::error::Error>> {
+    let mut wheels = HashMap::new();
+    let mut jobs = Vec::new();
+    for (dep, info) in locked {
+        if info.is_local() {
+            let build_dir = paths.build.join(dep.as_str());
+            let wheel_path = build_editable(
+                &paths.resolve_locked(&info.path),
+                &build_dir.join("env"),
+                &build_dir.join("wheels"),
+                index_url,
+            )?;
+            wheels.insert(dep.to_string(), wheel_path);
+            continue;
+        }
+        let wheel_path = paths.resolve_locked(&info.path);
+        if wheel_path.exists() {
+            continue;
+        }
+        if info.source.as_deref() == Some(GIT_SOURCE) {
+            wheels.insert(dep.to_string(), rebuild_git(paths, dep, info, index_url)?);
+            continue;
+        }
+        let cache_dir = wheel_path
+            .parent()
+            .ok_or_else(|| format!("Invalid path for {}: {}", dep, info.path))?;
+        std::fs::create_dir_all(cache_dir)?;
+        jobs.push(Job {
+            package: dep.to_string(),
+            artifact: locked_artifact(dep, info)?,
+            cache_dir: cache_dir.to_path_buf(),
+            depends_on: Vec::new(),
+            prebuilt: None,
+        });
+    }
+    std::fs::create_dir_all(&paths.build)?;
+    let rebuilt = run_jobs(&jobs, &paths.build, index_url, jobs_count, &report_progress)?;
+
+    for (dep, info) in locked {
+        if wheels.contains_key(dep.as_str()) {
+            continue;
+        }
+        verify_source(paths, dep, info)?;
+
+        let wheel_path = rebuilt
+            .get(dep.as_str())
+            .map(|output| output.wheel.clone())
+            .unwrap_or_else(|| paths.resolve_locked(&info.path));
+        wheels.insert(dep.to_string(), wheel_path);
+    }
+    Ok(wheels)
+}
+
+/// Checks out a git source at its locked commit and builds its wheel into
+/// the cache again, refusing a checkout that isn't what was locked.
+fn rebuild_git(
+    paths: &ProjectPaths,
+    dep: &str,
+    info: &LockfileDependency,
+    index_url: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let wheel_path = paths.resolve_locked(&info.path);
+    let cache_dir = wheel_path
+        .parent()
+        .ok_or_else(|| format!("Invalid path for {}: {}", dep, info.path))?;
+    let rev = info
+        .rev
+        .as_deref()
+        .ok_or_else(|| format!("box.lock has no rev for {}", dep))?;
+
+    let build_dir = paths.build.join(dep);
+    let checkout = build_dir.join("checkout");
+    git_checkout(&info.url, Some(rev), &checkout)?;
+    let hash = format!("sha256:{}", hash_directory(&checkout)?);
+    if hash != info.hash {
+        return Err(format!(
+            "Refusing to install {}: {} at {} has hash {}, box.lock has {}",
+            dep, info.url, rev, hash, info.hash
+        )
+        .into());
+    }
+
+    let wheel = build_wheel(
+        &checkout,
+        &build_dir.join("env"),
+        &build_dir.join("wheels"),
+        index_url,
+    )?;
+    std::fs::create_dir_all(cache_dir)?;
+    move_wheel(&wheel, cache_dir)
+}
+
+/// The index file a lock entry was made from, as far as the lock records it.
+fn locked_artifact(
+    dep: &str,
+    info: &LockfileDependency,
+) -> Result<DistributionFile, Box<dyn std::error::Error>> {
+    if info.url.is_empty() {
+        return Err(format!("{} isn't in the cache and box.lock has no url for it", dep).into());
+    }
+    let filename = info.url.rsplit('/').next().unwrap_or_default().to_string();
+    let kind = if filename.ends_with(".whl") {
+        DistributionKind::Wheel
+    } else {
+        DistributionKind::Sdist
+    };
+
+    let mut hashes = BTreeMap::new();
+    if let Some(digest) = info.hash.strip_prefix("sha256:") {
+        hashes.insert("sha256".to_string(), digest.to_string());
+    }
+
+    Ok(DistributionFile {
+        filename,
+        url: info.url.clone(),
+        kind,
+        version: info.version.parse()?,
+        hashes,
+        requires_python: None,
+        yanked: false,
+        core_metadata: false,
+    })
+}
+
+fn report_progress(package: &str, progress: &Progress) {
+    println!("[{}] {}", package, progress);
+}
+
+/// Checks the cached source of a lock entry against its recorded hash,
+/// fetching it again from its url if it's gone from the cache.
+fn verify_source(
+    paths: &ProjectPaths,
+    dep: &str,
+    info: &LockfileDependency,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Its wheel was built from a checkout that matched the lock (see rebuild_git)
+    if info.source.as_deref() == Some(GIT_SOURCE) {
+        return Ok(());
+    }
+    if info.hash.is_empty() || info.url.is_empty() {
+        println!(
+            "Warning: no source hash recorded for {}, skipping verification",
+            dep
+        );
+        return Ok(());
+    }
+
+    let wheel_path = paths.resolve_locked(&info.path);
+    let cache_dir = wheel_path
+        .parent()
+        .ok_or_else(|| format!("Invalid path for {}: {}", dep, info.path))?;
+    let filename = info.url.rsplit('/').next().unwrap_or_default();
+    let mut source_path = cache_dir.join(filename);
+    if !source_path.exists() {
+        source_path = download_source(&info.url, cache_dir)?;
+    }
+
+    verify_hash(&source_path, &info.hash)
+        .map_err(|e| format!("Refusing to install {}: {}", dep, e))?;
+    println!("Verified {} against {}", dep, info.hash);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    // A project in `dir` whose mypkg.toml has `dependencies` and then `rest`
+    fn write_project(dir: &Path, dependencies: &str, rest: &str) -> ProjectPaths {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            format!(
+                "[project]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}{}",
+                dependencies, rest
+            ),
+        )
+        .unwrap();
+        ProjectPaths::new(dir)
+    }
+
+    // box.lock entries, each `(name, its dependencies)`
+    fn lockfile(entries: &[(&str, &[&str])]) -> Lockfile {
+        let mut text = "[python]\npython_version = \"3.11\"\n".to_string();
+        for (name, dependencies) in entries {
+            text.push_str(&format!(
+                "\n[dependencies.{}]\nversion = \"1.0\"\npath = \"\"\ndependencies = {:?}\n",
+                name, dependencies
+            ));
+        }
+        from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn orphaned_entries_keep_what_others_still_need() {
+        let dir = TempDir::new("orphaned");
+        // `b` was just removed; `a` still needs `shared`
+        let paths = write_project(&dir.0, "a = \"*\"\n", "");
+        let workspace = Workspace::load(&paths).unwrap();
+        let lockfile = lockfile(&[
+            ("a", &["shared"]),
+            ("b", &["shared", "Only_B"]),
+            ("shared", &[]),
+            ("Only_B", &["deeper"]),
+            ("deeper", &[]),
+        ]);
+        assert_eq!(
+            orphaned_entries(&workspace, &lockfile, "b"),
+            ["Only_B", "b", "deeper"]
+        );
+
+        // Without a dependency graph only the package itself goes
+        let mut lockfile = lockfile;
+        lockfile.dependencies.get_mut("a").unwrap().dependencies = None;
+        assert_eq!(orphaned_entries(&workspace, &lockfile, "b"), ["b"]);
+    }
+
+    #[test]
+    fn removes_from_a_group_by_its_normalized_name() {
+        let dir = TempDir::new("remove-group");
+        let paths = write_project(
+            &dir.0,
+            "a = \"*\"\n",
+            "\n[dependency-groups.dev]\npytest = \"*\"\n",
+        );
+        let mut locked = lockfile(&[("a", &[]), ("pytest", &["pluggy"]), ("pluggy", &[])]);
+        locked.dependencies.get_mut("pytest").unwrap().groups = Some(vec![DEV_GROUP.into()]);
+        locked.dependencies.get_mut("pluggy").unwrap().groups = Some(vec![DEV_GROUP.into()]);
+        write_lockfile(&paths, &locked).unwrap();
+
+        let target = DependencyTarget::Group("Dev".to_string());
+        remove(&paths, None, "PyTest", &target).unwrap();
+        let manifest = load_manifest(&paths.manifest).unwrap();
+        assert!(manifest.dependency_groups["dev"].is_empty());
+        let locked = read_lockfile(&paths).unwrap();
+        assert_eq!(locked.dependencies.keys().collect::<Vec<_>>(), ["a"]);
+
+        let target = DependencyTarget::Group("docs".to_string());
+        let error = remove(&paths, None, "a", &target).unwrap_err();
+        assert_eq!(error.to_string(), "app has no dependency group docs");
+    }
+
+    #[test]
+    fn update_of_one_package_keeps_the_others_locked() {
+        let dir = TempDir::new("update");
+        let paths = write_project(&dir.0, "foo = \"*\"\n", "");
+        let workspace = Workspace::load(&paths).unwrap();
+        let requirements = workspace_requirements(&workspace).unwrap();
+        // foo and bar 1.0 and 2.0, where foo needs bar, served over file://
+        let index = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/index");
+        let settings = LockSettings {
+            index_url: index.display().to_string(),
+            policy: BinaryPolicy::new(&[], &[]),
+            jobs: 1,
+        };
+        let resolve = |preferences: &BTreeMap<String, Version>| -> Vec<String> {
+            plan_jobs(
+                &paths,
+                "app",
+                &requirements,
+                &mut BTreeMap::new(),
+                &settings,
+                preferences,
+                &get_system_info(),
+            )
+            .unwrap()
+            .iter()
+            .map(|job| format!("{} {}", job.package, job.artifact.version))
+            .collect()
+        };
+
+        let mut locked = lockfile(&[("foo", &["bar"]), ("bar", &[])]);
+        // lock keeps both, `update foo` only moves foo, `update` moves everything
+        assert_eq!(
+            resolve(&locked_versions(&locked, None)),
+            ["bar 1.0", "foo 1.0"]
+        );
+        assert_eq!(
+            resolve(&locked_versions(&locked, Some("FOO"))),
+            ["bar 1.0", "foo 2.0"]
+        );
+        assert_eq!(resolve(&BTreeMap::new()), ["bar 2.0", "foo 2.0"]);
+
+        // A locked version foo no longer allows gives way
+        locked.dependencies.get_mut("bar").unwrap().version = "0.5".to_string();
+        assert_eq!(
+            resolve(&locked_versions(&locked, Some("foo"))),
+            ["bar 2.0", "foo 2.0"]
+        );
+    }
+
+    // What `add` does to the manifest at `path`: add `requirement` and write it back
+    fn add_to(path: &Path, requirement: &str, target: &DependencyTarget) -> String {
+        let mut manifest = load_manifest(path).unwrap();
+        add_requirement(&mut manifest, target, &requirement.parse().unwrap());
+        write_manifest(path, &manifest).unwrap();
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn add_only_touches_the_dependency_it_adds() {
+        let dir = TempDir::new("roundtrip-mypkg");
+        let path = dir.0.join(MANIFEST_FILE);
+        let original = r#"# The app
+[project]
+version = "0.1.0"   # bumped by CI
+name = "app"
+
+[tool.boxpkg]
+index-url = "https://example.com/simple"
+
+[dependencies]
+# pinned for the old API
+zlib-ng = "==1.0"
+attrs = ">=23"
+local = { path = "../local" }
+
+[dependency-groups.dev]
+pytest = "*"
+"#;
+        std::fs::write(&path, original).unwrap();
+
+        let text = add_to(&path, "rich>=13", &DependencyTarget::Project);
+        let expected = original.replace(
+            "local = { path = \"../local\" }\n",
+            "local = { path = \"../local\" }\nrich = \">=13\"\n",
+        );
+        assert_eq!(text, expected);
+
+        // Adding again changes the constraint in place, whatever the spelling
+        let text = add_to(&path, "Zlib_NG==2.0", &DependencyTarget::Project);
+        let expected = expected.replace("zlib-ng = \"==1.0\"", "zlib-ng = \"==2.0\"");
+        assert_eq!(text, expected);
+
+        let text = add_to(&path, "mypy", &DependencyTarget::Group("dev".into()));
+        assert_eq!(text, format!("{}mypy = \"*\"\n", expected));
+    }
+
+    #[test]
+    fn add_keeps_the_rest_of_pyproject_toml() {
+        let dir = TempDir::new("roundtrip-pyproject");
+        let path = dir.0.join(PYPROJECT_FILE);
+        let original = r#"[build-system]
+requires = ["setuptools"]
+build-backend = "setuptools.build_meta"
+
+[project]
+name = "app"
+version = "0.1.0"
+authors = [{ name = "A. Person", email = "a@example.com" }]
+dependencies = [
+    "attrs>=23",  # the old API
+    "requests",
+]
+
+[tool.black]
+line-length = 100
+"#;
+        std::fs::write(&path, original).unwrap();
+
+        let text = add_to(&path, "rich>=13", &DependencyTarget::Project);
+        assert_eq!(
+            text,
+            original.replace(
+                "    \"requests\",\n",
+                "    \"requests\",\n    \"rich>=13\",\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rewriting_box_lock_keeps_what_didnt_change() {
+        let dir = TempDir::new("roundtrip-lock");
+        let paths = write_project(&dir.0, "", "");
+        let original = r#"[python]
+python_version = "3.11"
+
+# keep this one
+[dependencies.attrs]
+version = "23.1"
+path = ".box/cache/attrs.whl"
+dependencies = []
+groups = ["main"]
+
+[dependencies.rich]
+version = "13.0"
+path = ".box/cache/rich.whl"
+dependencies = []
+groups = ["main"]
+"#;
+        std::fs::write(&paths.lockfile, original).unwrap();
+
+        let mut lockfile = read_lockfile(&paths).unwrap();
+        write_lockfile(&paths, &lockfile).unwrap();
+        assert_eq!(std::fs::read_to_string(&paths.lockfile).unwrap(), original);
+
+        lockfile.dependencies.get_mut("rich").unwrap().version = "13.1".to_string();
+        write_lockfile(&paths, &lockfile).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&paths.lockfile).unwrap(),
+            original.replace("\"13.0\"", "\"13.1\"")
+        );
+        let reread = read_lockfile(&paths).unwrap();
+        assert_eq!(reread.dependencies, lockfile.dependencies);
+    }
+
+    fn init_in(dir: &Path, name: Option<&str>, force: bool, src: bool) -> Result<(), String> {
+        let options = InitOptions {
+            name: name.map(String::from),
+            force,
+            src,
+            gitignore: false,
+        };
+        let system_info = box_core::SystemEnvironmentInfo {
+            python_version: "3.12.1".to_string(),
+            ..get_system_info()
+        };
+        init(&ProjectPaths::new(dir), &options, &system_info).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn init_checks_the_project_name() {
+        let dir = TempDir::new("init-names");
+        for bad in ["my app", "-app", "app_", "ünicode", ""] {
+            let error = init_in(&dir.0, Some(bad), false, false).unwrap_err();
+            assert!(error.contains("isn't a valid project name"), "{}", error);
+        }
+        assert!(!dir.0.join(MANIFEST_FILE).exists());
+
+        let unnamed = dir.0.join("not a name");
+        let error = init_in(&unnamed, None, false, false).unwrap_err();
+        assert!(error.contains("pass a name"), "{}", error);
+
+        init_in(&dir.0, Some("My.App-2"), false, false).unwrap();
+        let manifest = load_manifest(&dir.0.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.project.name, "My.App-2");
+        assert_eq!(manifest.project.version, "0.1.0");
+        // From the interpreter it was started with
+        assert_eq!(manifest.project.requires_python.as_deref(), Some(">=3.12"));
+
+        let named = dir.0.join("from-dir");
+        init_in(&named, None, false, false).unwrap();
+        let manifest = load_manifest(&named.join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.project.name, "from-dir");
+    }
+
+    #[test]
+    fn init_only_overwrites_the_manifest_with_force() {
+        let dir = TempDir::new("init-force");
+        let manifest = dir.0.join(MANIFEST_FILE);
+        let lockfile = dir.0.join(LOCK_FILE);
+        std::fs::write(&manifest, "# mine\n").unwrap();
+        std::fs::write(&lockfile, "# locked before\n").unwrap();
+
+        let error = init_in(&dir.0, Some("app"), false, false).unwrap_err();
+        assert!(error.contains("use --force"), "{}", error);
+        assert_eq!(std::fs::read_to_string(&manifest).unwrap(), "# mine\n");
+
+        init_in(&dir.0, Some("app"), true, false).unwrap();
+        assert_eq!(load_manifest(&manifest).unwrap().project.name, "app");
+        // What's locked stays, even with --force
+        assert_eq!(
+            std::fs::read_to_string(&lockfile).unwrap(),
+            "# locked before\n"
+        );
+        assert!(dir.0.join(".box/cache").is_dir());
+    }
+
+    #[test]
+    fn init_scaffolds_a_src_layout() {
+        let dir = TempDir::new("init-src");
+        init_in(&dir.0, Some("My.Fancy-App"), false, true).unwrap();
+        let init_file = dir.0.join("src/my_fancy_app/__init__.py");
+        assert_eq!(std::fs::read_to_string(&init_file).unwrap(), "");
+        let lockfile = read_lockfile(&ProjectPaths::new(&dir.0)).unwrap();
+        assert_eq!(lockfile.python.python_version, "3.12.1");
+        assert!(lockfile.dependencies.is_empty());
+
+        // An existing package is left as it is
+        std::fs::write(&init_file, "VERSION = 1\n").unwrap();
+        init_in(&dir.0, Some("My.Fancy-App"), true, true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&init_file).unwrap(),
+            "VERSION = 1\n"
+        );
+    }
+
+    // `dependencies = <array>` after syncing it with `wanted`
+    fn synced_array(array: &str, wanted: &[(&str, &str)]) -> String {
+        let mut document: DocumentMut = format!("dependencies = {}\n", array).parse().unwrap();
+        let mut dependencies: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, constraint) in wanted {
+            dependencies
+                .entry(name.to_string())
+                .or_default()
+                .push(constraint.to_string());
+        }
+        let array = document["dependencies"].as_array_mut().unwrap();
+        sync_requirement_array(array, &dependencies).unwrap();
+        document.to_string()
+    }
+
+    #[test]
+    fn requirement_arrays_keep_their_layout() {
+        let multiline = "[\n    \"attrs>=23\",  # old API\n    \"Requests[socks]>=2\",\n]";
+        // Added at the end, indented like the rest
+        assert_eq!(
+            synced_array(
+                multiline,
+                &[
+                    ("attrs", ">=23"),
+                    ("Requests", "[socks]>=2"),
+                    ("rich", ">=13")
+                ]
+            ),
+            "dependencies = [\n    \"attrs>=23\",  # old API\n    \"Requests[socks]>=2\",\n    \"rich>=13\",\n]\n"
+        );
+        // Replaced in place, found by normalized name
+        assert_eq!(
+            synced_array(multiline, &[("attrs", ">=24"), ("requests", "[socks]>=2")]),
+            "dependencies = [\n    \"attrs>=24\",  # old API\n    \"Requests[socks]>=2\",\n]\n"
+        );
+        assert_eq!(
+            synced_array(
+                "[\"zope.interface>=5\", \"Foo_Bar\"]",
+                &[("Zope-Interface", ">=6"), ("foo-bar", "*")]
+            ),
+            "dependencies = [\"Zope-Interface>=6\", \"Foo_Bar\"]\n"
+        );
+        // Removed, the next entry taking the first one's place
+        assert_eq!(
+            synced_array(multiline, &[("requests", "[socks]>=2")]),
+            "dependencies = [\n    \"Requests[socks]>=2\",\n]\n"
+        );
+        assert_eq!(
+            synced_array("[\"a\", \"b\", \"c\"]", &[("a", "*"), ("c", "*")]),
+            "dependencies = [\"a\", \"c\"]\n"
+        );
+        // One line stays one line, and what isn't a requirement is left alone
+        assert_eq!(
+            synced_array(
+                "[\"a\", \"not a requirement!\"]",
+                &[("a", "*"), ("b", "==1")]
+            ),
+            "dependencies = [\"a\", \"not a requirement!\", \"b==1\"]\n"
+        );
+        assert_eq!(
+            synced_array("[]", &[("a", ">=1")]),
+            "dependencies = [\"a>=1\"]\n"
+        );
+    }
+
+    #[test]
+    fn requirement_arrays_keep_marker_split_entries_apart() {
+        let split = r#"["numpy>=1.24; python_version < '3.10'", "attrs", "numpy>=2; python_version >= '3.10'"]"#;
+        let old = r#">=1.24; python_version < "3.10""#;
+        let new = r#">=2; python_version >= "3.10""#;
+        // Both kept as written, in their places
+        assert_eq!(
+            synced_array(split, &[("numpy", old), ("attrs", "*"), ("numpy", new)]),
+            format!("dependencies = {}\n", split)
+        );
+        // Only the one that changed is replaced
+        assert_eq!(
+            synced_array(
+                split,
+                &[
+                    ("numpy", old),
+                    ("attrs", "*"),
+                    ("numpy", r#">=2.1; python_version >= "3.10""#)
+                ]
+            ),
+            r#"dependencies = ["numpy>=1.24; python_version < '3.10'", "attrs", 'numpy>=2.1; python_version >= "3.10"']
+"#
+        );
+        // One constraint left for the name, the other entry goes
+        assert_eq!(
+            synced_array(split, &[("numpy", ">=2"), ("attrs", "*")]),
+            "dependencies = [\"numpy>=2\", \"attrs\"]\n"
+        );
+        assert_eq!(
+            synced_array("[\"numpy\"]", &[("numpy", old), ("numpy", new)]),
+            r#"dependencies = ['numpy>=1.24; python_version < "3.10"', 'numpy>=2; python_version >= "3.10"']
+"#
+        );
+    }
+
+    #[test]
+    fn a_dependency_can_be_listed_once_per_marker() {
+        let dir = TempDir::new("marker-split");
+        let path = dir.0.join(PYPROJECT_FILE);
+        let original = r#"[project]
+name = "app"
+version = "0.1.0"
+dependencies = [
+    "numpy>=1.24; python_version < '3.10'",
+    "NumPy>=2; python_version >= '3.10'",
+]
+"#;
+        std::fs::write(&path, original).unwrap();
+
+        let manifest = load_manifest(&path).unwrap();
+        assert_eq!(
+            manifest.dependencies["numpy"],
+            [
+                ">=1.24; python_version < \"3.10\"",
+                ">=2; python_version >= \"3.10\""
+            ]
+        );
+        let requirements = manifest_requirements(&manifest).unwrap();
+        assert_eq!(requirements.len(), 2);
+        assert_eq!(
+            direct_marker(&requirements, "numpy").unwrap(),
+            "python_version < \"3.10\" or python_version >= \"3.10\""
+        );
+
+        // Adding something else leaves both entries alone
+        let text = add_to(&path, "rich>=13", &DependencyTarget::Project);
+        assert_eq!(
+            text,
+            original.replace("'3.10'\",\n]", "'3.10'\",\n    \"rich>=13\",\n]")
+        );
+
+        // mypkg.toml lists them in an array
+        let path = dir.0.join(MANIFEST_FILE);
+        let original = r#"[project]
+name = "app"
+version = "0.1.0"
+
+[dependencies]
+numpy = [">=1.24; python_version < '3.10'", ">=2; python_version >= '3.10'"]
+"#;
+        std::fs::write(&path, original).unwrap();
+        assert_eq!(load_manifest(&path).unwrap().dependencies["numpy"].len(), 2);
+        let text = add_to(&path, "rich>=13", &DependencyTarget::Project);
+        assert_eq!(text, format!("{}rich = \">=13\"\n", original));
+    }
+
+    #[test]
+    fn direct_references_become_sources() {
+        let dir = TempDir::new("direct-references");
+        let path = dir.0.join(PYPROJECT_FILE);
+        let original = r#"[project]
+name = "app"
+version = "0.1.0"
+dependencies = [
+    "attrs @ https://example.com/attrs-23.1.tar.gz",
+    "pkg[extra] @ git+ssh://git@example.com/pkg.git@v1.0#egg=pkg ; os_name != 'nt'",
+    "local @ file:///src/local",
+    "other @ git+https://example.com/other.git",
+]
+"#;
+        std::fs::write(&path, original).unwrap();
+
+        let manifest = load_manifest(&path).unwrap();
+        let source =
+            |path: Option<&str>, git: Option<&str>, rev: Option<&str>, url: Option<&str>| {
+                DirectSource {
+                    path: path.map(str::to_string),
+                    git: git.map(str::to_string),
+                    rev: rev.map(str::to_string),
+                    url: url.map(str::to_string),
+                }
+            };
+        assert_eq!(
+            manifest.sources["attrs"],
+            source(
+                None,
+                None,
+                None,
+                Some("https://example.com/attrs-23.1.tar.gz")
+            )
+        );
+        assert_eq!(
+            manifest.sources["pkg"],
+            source(
+                None,
+                Some("ssh://git@example.com/pkg.git"),
+                Some("v1.0"),
+                None
+            )
+        );
+        assert_eq!(
+            manifest.sources["local"],
+            source(Some("/src/local"), None, None, None)
+        );
+        assert_eq!(
+            manifest.sources["other"],
+            source(None, Some("https://example.com/other.git"), None, None)
+        );
+        let requirements = manifest_requirements(&manifest).unwrap();
+        assert_eq!(requirements.len(), 4);
+
+        // Adding something else leaves them as written
+        let text = add_to(&path, "rich>=13", &DependencyTarget::Project);
+        assert_eq!(
+            text,
+            original.replace("other.git\",\n]", "other.git\",\n    \"rich>=13\",\n]")
+        );
+
+        // A name can only come from one place
+        for conflicting in [
+            "[project.optional-dependencies]\nx = [\"attrs @ https://example.com/attrs.zip\"]\n",
+            "[tool.boxpkg.sources]\nlocal = { path = \"../local\" }\n",
+        ] {
+            std::fs::write(&path, format!("{}\n{}", original, conflicting)).unwrap();
+            let error = load_manifest(&path).unwrap_err().to_string();
+            assert!(error.contains("given two different sources"), "{}", error);
+        }
+        std::fs::write(
+            &path,
+            original.replace(
+                "git+https://example.com/other.git",
+                "hg+https://example.com/other",
+            ),
+        )
+        .unwrap();
+        let error = load_manifest(&path).unwrap_err().to_string();
+        assert!(
+            error.contains("only git+ version control URLs"),
+            "{}",
+            error
+        );
+    }
+
+    const GROUPS_PYPROJECT: &str = r#"[project]
+name = "app"
+version = "0.1.0"
+dependencies = ["attrs"]
+
+[project.optional-dependencies]
+cli = ["click"]
+
+[dependency-groups]
+dev = ["pytest", { include-group = "Lint" }]
+lint = ["ruff", { include-group = "typing" }]
+typing = ["mypy"]
+docs = ["sphinx"]
+loop-a = ["x", { include-group = "loop_b" }]
+loop-b = ["y", { include-group = "loop-a" }]
+"#;
+
+    fn sorted(names: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut names: Vec<String> = names.into_iter().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn group_roots_follow_included_groups() {
+        let dir = TempDir::new("group-roots");
+        let path = dir.0.join(PYPROJECT_FILE);
+        std::fs::write(&path, GROUPS_PYPROJECT).unwrap();
+        let roots = group_roots(&load_manifest(&path).unwrap());
+
+        let group = |name: &str| sorted(roots[name].iter().cloned());
+        assert_eq!(
+            sorted(roots.keys().cloned()),
+            ["dev", "docs", "lint", "loop-a", "loop-b", "main", "typing"]
+        );
+        // Optional dependencies are locked with the project's own
+        assert_eq!(group("main"), ["attrs", "click"]);
+        // dev includes lint, which includes typing
+        assert_eq!(group("dev"), ["mypy", "pytest", "ruff"]);
+        assert_eq!(group("lint"), ["mypy", "ruff"]);
+        // Groups including each other end up with both groups' requirements
+        assert_eq!(group("loop-a"), ["x", "y"]);
+        assert_eq!(group("loop-b"), ["x", "y"]);
+
+        // Including a group that doesn't exist is an error
+        std::fs::write(
+            &path,
+            GROUPS_PYPROJECT.replace("include-group = \"typing\"", "include-group = \"nope\""),
+        )
+        .unwrap();
+        let error = load_manifest(&path).unwrap_err().to_string();
+        assert!(error.contains("lint includes nope"), "{}", error);
+    }
+
+    #[test]
+    fn selects_groups_from_the_command_line() {
+        let dir = TempDir::new("select");
+        std::fs::write(dir.0.join(PYPROJECT_FILE), GROUPS_PYPROJECT).unwrap();
+        let paths = ProjectPaths::new(&dir.0);
+        let lockfile = lockfile(&[
+            ("attrs", &[]),
+            ("click", &[]),
+            ("pytest", &["pluggy"]),
+            ("pluggy", &[]),
+            ("ruff", &[]),
+            ("mypy", &[]),
+            ("sphinx", &[]),
+        ]);
+        let args = |group: &[&str], no_dev: bool, only_group: &[&str]| GroupArgs {
+            group: group.iter().map(|g| g.to_string()).collect(),
+            no_dev,
+            only_group: only_group.iter().map(|g| g.to_string()).collect(),
+        };
+        let groups = |args: GroupArgs| -> Vec<String> {
+            sorted(select(&paths, None, &args, &lockfile).unwrap().groups)
+        };
+
+        // The project's dependencies and dev by default
+        assert_eq!(groups(args(&[], false, &[])), ["dev", "main"]);
+        assert_eq!(groups(args(&["Docs"], false, &[])), ["dev", "docs", "main"]);
+        assert_eq!(groups(args(&[], true, &[])), ["main"]);
+        assert_eq!(groups(args(&["docs"], true, &[])), ["docs", "main"]);
+        assert_eq!(groups(args(&[], false, &["LINT"])), ["lint"]);
+
+        let error = select(&paths, None, &args(&["nope"], false, &[]), &lockfile)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "app has no dependency group nope");
+
+        // For one member, only what its selected groups need
+        let selection =
+            select(&paths, Some("app"), &args(&[], false, &["lint"]), &lockfile).unwrap();
+        assert_eq!(sorted(selection.packages.unwrap()), ["mypy", "ruff"]);
+        let selection = select(&paths, Some("app"), &args(&[], false, &[]), &lockfile).unwrap();
+        assert_eq!(
+            sorted(selection.packages.unwrap()),
+            ["attrs", "click", "mypy", "pluggy", "pytest", "ruff"]
+        );
+
+        // Without a dev group there's nothing to leave out
+        std::fs::write(
+            dir.0.join(PYPROJECT_FILE),
+            "[project]\nname = \"app\"\nversion = \"0.1.0\"\ndependencies = []\n",
+        )
+        .unwrap();
+        assert_eq!(groups(args(&[], false, &[])), ["main"]);
+    }
+}
@@ -0,0 +1,195 @@
+//! Working out what sync changes in an environment.
+
+use std::collections::{HashMap, HashSet};
+
+use box_core::{InstalledDistribution, normalize_name};
+
+use crate::{LockfileDependency, same_version};
+
+/// What sync does to an environment: uninstall `to_remove`, then install
+/// `to_install`. `unchanged` already match box.lock.
+pub(crate) struct SyncPlan<'a> {
+    pub(crate) to_remove: Vec<InstalledDistribution>,
+    pub(crate) to_install: Vec<(&'a String, &'a LockfileDependency)>,
+    pub(crate) unchanged: usize,
+}
+
+impl SyncPlan<'_> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.to_remove.is_empty() && self.to_install.is_empty()
+    }
+}
+
+/// Compares what is `installed` with the lock entries that are `wanted`,
+/// each installed with its hash from `hashes`. A package is kept only when
+/// its version and source hash both match; one box.lock doesn't list, or
+/// a second copy of one it does, is removed.
+pub(crate) fn plan_sync<'a>(
+    installed: Vec<InstalledDistribution>,
+    wanted: &[(&'a String, &'a LockfileDependency)],
+    hashes: &HashMap<String, String>,
+) -> SyncPlan<'a> {
+    let by_name: HashMap<String, (&LockfileDependency, &String)> = wanted
+        .iter()
+        .map(|(dep, info)| (normalize_name(dep), (*info, &hashes[dep.as_str()])))
+        .collect();
+
+    let mut up_to_date = HashSet::new();
+    let mut to_remove = Vec::new();
+    for installed in installed {
+        let name = normalize_name(&installed.name);
+        // An editable install's hash covers its version, which may be dynamic
+        let matches = by_name.get(&name).is_some_and(|(info, hash)| {
+            (info.is_local() || same_version(&installed.version, &info.version))
+                && installed.source_hash.as_ref() == Some(*hash)
+        });
+        if matches && !up_to_date.contains(&name) {
+            up_to_date.insert(name);
+        } else {
+            to_remove.push(installed);
+        }
+    }
+    let to_install = wanted
+        .iter()
+        .filter(|(dep, _)| !up_to_date.contains(&normalize_name(dep)))
+        .copied()
+        .collect();
+
+    SyncPlan {
+        to_remove,
+        to_install,
+        unchanged: up_to_date.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PATH_SOURCE, empty_entry};
+    use std::path::PathBuf;
+
+    fn locked(version: &str, hash: &str) -> LockfileDependency {
+        LockfileDependency {
+            version: version.to_string(),
+            hash: hash.to_string(),
+            source: None,
+            ..empty_entry("")
+        }
+    }
+
+    fn installed(name: &str, version: &str, source_hash: Option<&str>) -> InstalledDistribution {
+        InstalledDistribution {
+            name: name.to_string(),
+            version: version.to_string(),
+            dist_info: PathBuf::from(format!("{}-{}.dist-info", name, version)),
+            installer: Some("boxpkg".to_string()),
+            source_hash: source_hash.map(String::from),
+        }
+    }
+
+    fn names(plan: &SyncPlan) -> (Vec<String>, Vec<String>) {
+        let removed = plan
+            .to_remove
+            .iter()
+            .map(|dist| format!("{} {}", dist.name, dist.version))
+            .collect();
+        let installed = plan
+            .to_install
+            .iter()
+            .map(|(dep, info)| format!("{} {}", dep, info.version))
+            .collect();
+        (removed, installed)
+    }
+
+    #[test]
+    fn plans_removals_installs_and_reinstalls() {
+        let (keep, upgrade, rehash, missing, local) = (
+            "Keep_Me".to_string(),
+            "upgrade".to_string(),
+            "rehash".to_string(),
+            "missing".to_string(),
+            "local".to_string(),
+        );
+        let entries = [
+            locked("1.0", "sha256:keep"),
+            locked("2.0", "sha256:upgrade"),
+            locked("1.0", "sha256:new"),
+            locked("3.0", "sha256:missing"),
+            LockfileDependency {
+                version: "0.2.0".to_string(),
+                source: Some(PATH_SOURCE.to_string()),
+                ..empty_entry("")
+            },
+        ];
+        let wanted: Vec<(&String, &LockfileDependency)> =
+            [&keep, &upgrade, &rehash, &missing, &local]
+                .into_iter()
+                .zip(&entries)
+                .collect();
+        let hashes: HashMap<String, String> = wanted
+            .iter()
+            .map(|(dep, info)| {
+                let hash = if info.is_local() {
+                    "sha256:editable".to_string()
+                } else {
+                    info.hash.clone()
+                };
+                (dep.to_string(), hash)
+            })
+            .collect();
+
+        let plan = plan_sync(
+            vec![
+                installed("keep-me", "1.0.0", Some("sha256:keep")),
+                installed("upgrade", "1.0", Some("sha256:old")),
+                installed("rehash", "1.0", Some("sha256:old")),
+                installed("extra", "5.0", Some("sha256:extra")),
+                installed("pip-installed", "1.0", None),
+                // An editable install's version may have moved on since it was locked
+                installed("local", "0.3.0", Some("sha256:editable")),
+            ],
+            &wanted,
+            &hashes,
+        );
+        let (removed, installed) = names(&plan);
+        assert_eq!(
+            removed,
+            [
+                "upgrade 1.0",
+                "rehash 1.0",
+                "extra 5.0",
+                "pip-installed 1.0"
+            ]
+        );
+        assert_eq!(installed, ["upgrade 2.0", "rehash 1.0", "missing 3.0"]);
+        assert_eq!(plan.unchanged, 2);
+        assert!(!plan.is_empty());
+    }
+
+    #[test]
+    fn nothing_to_do_when_the_environment_matches() {
+        let name = "demo".to_string();
+        let entry = locked("1.0", "sha256:abc");
+        let wanted = [(&name, &entry)];
+        let hashes = HashMap::from([(name.clone(), "sha256:abc".to_string())]);
+
+        let plan = plan_sync(
+            vec![installed("Demo", "1.0", Some("sha256:abc"))],
+            &wanted,
+            &hashes,
+        );
+        assert!(plan.is_empty());
+        assert_eq!(plan.unchanged, 1);
+
+        // A second copy of the same package goes
+        let plan = plan_sync(
+            vec![
+                installed("demo", "1.0", Some("sha256:abc")),
+                installed("demo", "1.0", Some("sha256:abc")),
+            ],
+            &wanted,
+            &hashes,
+        );
+        assert_eq!(names(&plan), (vec!["demo 1.0".to_string()], vec![]));
+    }
+}
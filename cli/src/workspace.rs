@@ -0,0 +1,599 @@
+//! Finding the project a command runs in and the workspace it belongs to.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use box_core::normalize_name;
+use toml_edit::DocumentMut;
+
+use crate::{LOCK_FILE, MANIFEST_FILE, Manifest, PYPROJECT_FILE, WorkspaceSettings, load_manifest};
+
+/// Where a project's files live, all derived from its root: the directory
+/// holding the manifest, or the workspace's root manifest.
+#[derive(Debug)]
+pub(crate) struct ProjectPaths {
+    pub(crate) root: PathBuf,
+    pub(crate) member: PathBuf, // the workspace member the command runs in, the root outside workspaces
+    pub(crate) manifest: PathBuf,
+    pub(crate) lockfile: PathBuf,
+    pub(crate) box_dir: PathBuf,
+    pub(crate) cache: PathBuf,
+    pub(crate) build: PathBuf,
+    pub(crate) venv: PathBuf,
+}
+
+impl ProjectPaths {
+    pub(crate) fn new(root: &Path) -> ProjectPaths {
+        // Absolute, so paths stay right whatever directory a build runs in
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let box_dir = root.join(".box");
+        ProjectPaths {
+            member: root.clone(),
+            manifest: manifest_in(&root).unwrap_or_else(|| root.join(MANIFEST_FILE)),
+            lockfile: root.join(LOCK_FILE),
+            cache: box_dir.join("cache"),
+            build: box_dir.join("build"),
+            venv: box_dir.join("venv"),
+            box_dir,
+            root,
+        }
+    }
+
+    /// A path as box.lock records it: relative to the root when it's inside
+    /// the project, so the lock doesn't depend on where the project is checked out.
+    pub(crate) fn to_locked(&self, path: &Path) -> String {
+        // Relative to the root, or to one of its parents short of the
+        // filesystem root, e.g. "../libfoo" for a path dependency
+        let relative = self
+            .root
+            .ancestors()
+            .take_while(|ancestor| ancestor.parent().is_some())
+            .enumerate()
+            .find_map(|(up, ancestor)| Some((up, path.strip_prefix(ancestor).ok()?)));
+        match relative {
+            Some((0, relative)) if relative.as_os_str().is_empty() => ".".to_string(),
+            Some((up, relative)) => std::iter::repeat_n("..".to_string(), up)
+                .chain(
+                    relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+                )
+                .collect::<Vec<_>>()
+                .join("/"),
+            None => path.display().to_string(),
+        }
+    }
+
+    pub(crate) fn resolve_locked(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub(crate) fn is_pyproject(&self) -> bool {
+        is_pyproject(&self.manifest)
+    }
+
+    pub(crate) fn manifest_name(&self) -> &str {
+        if self.is_pyproject() {
+            PYPROJECT_FILE
+        } else {
+            MANIFEST_FILE
+        }
+    }
+}
+
+/// Whether a manifest is a PEP 621 pyproject.toml rather than mypkg.toml.
+pub(crate) fn is_pyproject(manifest: &Path) -> bool {
+    manifest.file_name() == Some(PYPROJECT_FILE.as_ref())
+}
+
+/// The manifest in `dir`: mypkg.toml, or else a pyproject.toml with a [project] table.
+fn manifest_in(dir: &Path) -> Option<PathBuf> {
+    let manifest = dir.join(MANIFEST_FILE);
+    if manifest.is_file() {
+        return Some(manifest);
+    }
+    let pyproject = dir.join(PYPROJECT_FILE);
+    let document: DocumentMut = std::fs::read_to_string(&pyproject).ok()?.parse().ok()?;
+    document
+        .get("project")
+        .is_some_and(|project| project.is_table_like())
+        .then_some(pyproject)
+}
+
+/// The project `project_dir` names, or else the closest directory holding a
+/// manifest from the current directory upwards, like cargo finds Cargo.toml.
+/// A workspace member's paths are those of the workspace it belongs to.
+pub(crate) fn find_project(
+    project_dir: Option<&Path>,
+) -> Result<ProjectPaths, Box<dyn std::error::Error>> {
+    find_project_from(project_dir, &env::current_dir()?)
+}
+
+// find_project, searching from `current_dir`
+fn find_project_from(
+    project_dir: Option<&Path>,
+    current_dir: &Path,
+) -> Result<ProjectPaths, Box<dyn std::error::Error>> {
+    let dir = match project_dir {
+        Some(dir) => {
+            if manifest_in(dir).is_none() {
+                return Err(format!(
+                    "No {} or {} with a [project] table in {}",
+                    MANIFEST_FILE,
+                    PYPROJECT_FILE,
+                    dir.display()
+                )
+                .into());
+            }
+            dir.canonicalize()?
+        }
+        None => current_dir
+            .ancestors()
+            .find(|dir| manifest_in(dir).is_some())
+            .map(Path::to_path_buf)
+            .ok_or_else(|| {
+                format!(
+                    "Couldn't find {} or {} with a [project] table in {} or any parent directory",
+                    MANIFEST_FILE,
+                    PYPROJECT_FILE,
+                    current_dir.display()
+                )
+            })?,
+    };
+
+    match find_workspace_root(&dir) {
+        Some(root) => Ok(ProjectPaths {
+            member: dir,
+            ..ProjectPaths::new(&root)
+        }),
+        None => Ok(ProjectPaths::new(&dir)),
+    }
+}
+
+/// The closest directory above `dir` whose manifest lists it as a workspace member.
+fn find_workspace_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().skip(1).find_map(|ancestor| {
+        let manifest = load_manifest(&manifest_in(ancestor)?).ok()?;
+        let members = workspace_members(ancestor, &manifest.tool.boxpkg.workspace?);
+        members
+            .ok()?
+            .contains(&dir.to_path_buf())
+            .then(|| ancestor.to_path_buf())
+    })
+}
+
+/// The member directories `settings` names under `root`, less those its
+/// `exclude` patterns match. A pattern with wildcards only matches
+/// directories holding a manifest; one without must name such a directory.
+fn workspace_members(
+    root: &Path,
+    settings: &WorkspaceSettings,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut excluded = Vec::new();
+    for pattern in &settings.exclude {
+        for dir in expand_pattern(root, pattern) {
+            excluded.extend(dir.canonicalize().ok());
+        }
+    }
+
+    let mut members = Vec::new();
+    for pattern in &settings.members {
+        let wildcard = pattern.contains(['*', '?']);
+        for dir in expand_pattern(root, pattern) {
+            if dir.canonicalize().is_ok_and(|dir| excluded.contains(&dir)) {
+                continue;
+            }
+            if manifest_in(&dir).is_some() {
+                // Overlapping patterns name a member more than once; keep the first
+                let dir = dir.canonicalize()?;
+                if !members.contains(&dir) {
+                    members.push(dir);
+                }
+            } else if !wildcard {
+                return Err(format!(
+                    "Workspace member {} has no {} or {} with a [project] table",
+                    dir.display(),
+                    MANIFEST_FILE,
+                    PYPROJECT_FILE
+                )
+                .into());
+            }
+        }
+    }
+    Ok(members)
+}
+
+// The paths `pattern` names under `root`, one component at a time. Wildcard
+// components only match existing directories; the others are kept as is.
+fn expand_pattern(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_path_buf()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut matched = Vec::new();
+        for dir in &dirs {
+            if !component.contains(['*', '?']) {
+                matched.push(dir.join(component));
+                continue;
+            }
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().is_dir() && wildcard_match(component, &name) {
+                    matched.push(entry.path());
+                }
+            }
+        }
+        matched.sort();
+        dirs = matched;
+    }
+    dirs
+}
+
+// Shell-style matching of one path component: `*` is any run of characters, `?` one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The root project and the members its [tool.boxpkg.workspace] lists,
+/// which share its box.lock and .box/. A project on its own is a workspace
+/// of one.
+pub(crate) struct Workspace {
+    pub(crate) members: Vec<Member>, // the root first
+}
+
+pub(crate) struct Member {
+    pub(crate) dir: PathBuf,
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) manifest: Manifest,
+}
+
+impl Member {
+    pub(crate) fn normalized_name(&self) -> String {
+        normalize_name(&self.manifest.project.name)
+    }
+}
+
+impl Workspace {
+    pub(crate) fn load(paths: &ProjectPaths) -> Result<Workspace, Box<dyn std::error::Error>> {
+        let root = load_manifest(&paths.manifest)?;
+        let settings = root.tool.boxpkg.workspace.clone().unwrap_or_default();
+        let mut members = vec![Member {
+            dir: paths.root.clone(),
+            manifest_path: paths.manifest.clone(),
+            manifest: root,
+        }];
+
+        for dir in workspace_members(&paths.root, &settings)? {
+            if dir == paths.root {
+                continue;
+            }
+            let manifest_path = manifest_in(&dir).ok_or("Workspace member without a manifest")?;
+            let member = Member {
+                manifest: load_manifest(&manifest_path)?,
+                manifest_path,
+                dir,
+            };
+            if let Some(other) = members
+                .iter()
+                .find(|other| other.normalized_name() == member.normalized_name())
+            {
+                return Err(format!(
+                    "{} and {} are both named {}",
+                    other.dir.display(),
+                    member.dir.display(),
+                    member.manifest.project.name
+                )
+                .into());
+            }
+            members.push(member);
+        }
+        Ok(Workspace { members })
+    }
+
+    pub(crate) fn root(&self) -> &Member {
+        &self.members[0]
+    }
+
+    /// The member `package` names, or else the one the command runs in.
+    pub(crate) fn target(
+        &self,
+        paths: &ProjectPaths,
+        package: Option<&str>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        match package {
+            Some(package) => self
+                .members
+                .iter()
+                .position(|member| member.normalized_name() == normalize_name(package))
+                .ok_or_else(|| format!("No workspace member named {}", package).into()),
+            None => Ok(self
+                .members
+                .iter()
+                .position(|member| member.dir == paths.member)
+                .unwrap_or(0)),
+        }
+    }
+
+    pub(crate) fn member(&self, name: &str) -> Option<&Member> {
+        self.members
+            .iter()
+            .find(|member| member.normalized_name() == normalize_name(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::fs;
+
+    // A mypkg.toml project named `name` in `dir`, with `extra` appended
+    fn write_project(dir: &Path, name: &str, extra: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            format!(
+                "[project]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}",
+                name, extra
+            ),
+        )
+        .unwrap();
+    }
+
+    fn settings(members: &[&str], exclude: &[&str]) -> WorkspaceSettings {
+        WorkspaceSettings {
+            members: members.iter().map(|m| m.to_string()).collect(),
+            exclude: exclude.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn finds_the_project_from_a_subdirectory() {
+        let dir = TempDir::new("subdir");
+        write_project(&dir.0, "app", "");
+        fs::create_dir_all(dir.0.join("src/app/deep")).unwrap();
+        // A pyproject.toml without [project] is just tool configuration
+        fs::write(dir.0.join("src/pyproject.toml"), "[tool.black]\n").unwrap();
+
+        let paths = find_project_from(None, &dir.0.join("src/app/deep")).unwrap();
+        assert_eq!(paths.root, dir.0);
+        assert_eq!(paths.member, dir.0);
+        assert_eq!(paths.manifest, dir.0.join(MANIFEST_FILE));
+        assert_eq!(paths.lockfile, dir.0.join(LOCK_FILE));
+        assert_eq!(paths.venv, dir.0.join(".box/venv"));
+
+        let paths = find_project_from(Some(&dir.0), Path::new("/")).unwrap();
+        assert_eq!(paths.root, dir.0);
+    }
+
+    #[test]
+    fn no_project_is_an_error() {
+        let dir = TempDir::new("no-project");
+        fs::create_dir_all(dir.0.join("a/b")).unwrap();
+
+        let error = find_project_from(None, &dir.0.join("a/b"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Couldn't find mypkg.toml"), "{}", error);
+        assert!(error.contains("or any parent directory"), "{}", error);
+
+        let error = find_project_from(Some(&dir.0.join("a")), &dir.0)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!(
+                "No mypkg.toml or pyproject.toml with a [project] table in {}",
+                dir.0.join("a").display()
+            )
+        );
+    }
+
+    #[test]
+    fn members_find_their_workspace_and_other_projects_dont() {
+        let dir = TempDir::new("nested");
+        write_project(
+            &dir.0,
+            "root",
+            "\n[tool.boxpkg.workspace]\nmembers = [\"packages/*\"]\n",
+        );
+        let member = dir.0.join("packages/a");
+        write_project(&member, "a", "");
+        fs::create_dir_all(member.join("src/a")).unwrap();
+        // Inside a member, but not a member itself
+        let example = member.join("examples/demo");
+        write_project(&example, "demo", "");
+
+        let paths = find_project_from(None, &member.join("src/a")).unwrap();
+        assert_eq!(paths.root, dir.0);
+        assert_eq!(paths.member, member);
+        assert_eq!(paths.manifest, dir.0.join(MANIFEST_FILE));
+        assert_eq!(paths.lockfile, dir.0.join(LOCK_FILE));
+
+        let paths = find_project_from(Some(&member), &dir.0).unwrap();
+        assert_eq!((paths.root, paths.member), (dir.0.clone(), member));
+
+        let paths = find_project_from(None, &example).unwrap();
+        assert_eq!(paths.root, example);
+        assert_eq!(paths.member, example);
+        assert_eq!(paths.lockfile, example.join(LOCK_FILE));
+    }
+
+    #[test]
+    fn wildcard_members_are_directories_with_a_manifest() {
+        let dir = TempDir::new("glob");
+        write_project(&dir.0.join("packages/b"), "b", "");
+        write_project(&dir.0.join("packages/a"), "a", "");
+        fs::create_dir_all(dir.0.join("packages/docs")).unwrap();
+        fs::write(dir.0.join("packages/notes.txt"), "").unwrap();
+        write_project(&dir.0.join("tools/cli"), "cli", "");
+
+        let members = workspace_members(&dir.0, &settings(&["packages/*"], &[])).unwrap();
+        assert_eq!(
+            members,
+            [dir.0.join("packages/a"), dir.0.join("packages/b")]
+        );
+        let members = workspace_members(&dir.0, &settings(&["*/?"], &[])).unwrap();
+        assert_eq!(
+            members,
+            [dir.0.join("packages/a"), dir.0.join("packages/b")]
+        );
+        let members =
+            workspace_members(&dir.0, &settings(&["./tools/cli", "packages/a"], &[])).unwrap();
+        assert_eq!(members, [dir.0.join("tools/cli"), dir.0.join("packages/a")]);
+    }
+
+    #[test]
+    fn excluded_directories_are_not_members() {
+        let dir = TempDir::new("exclude");
+        for name in ["a", "b", "legacy-x", "legacy-y"] {
+            write_project(&dir.0.join("packages").join(name), name, "");
+        }
+
+        let members =
+            workspace_members(&dir.0, &settings(&["packages/*"], &["packages/legacy-*"])).unwrap();
+        assert_eq!(
+            members,
+            [dir.0.join("packages/a"), dir.0.join("packages/b")]
+        );
+        // Even when named outright, and an exclusion that matches nothing is fine
+        let members = workspace_members(
+            &dir.0,
+            &settings(&["packages/a", "packages/b"], &["packages/b", "gone"]),
+        )
+        .unwrap();
+        assert_eq!(members, [dir.0.join("packages/a")]);
+    }
+
+    #[test]
+    fn overlapping_patterns_list_a_member_once() {
+        let dir = TempDir::new("overlap");
+        write_project(&dir.0.join("packages/a"), "a", "");
+        write_project(&dir.0.join("packages/b"), "b", "");
+
+        let members = workspace_members(
+            &dir.0,
+            &settings(
+                &["packages/*", "packages/a", "./packages/b", "packages/*"],
+                &[],
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            members,
+            [dir.0.join("packages/a"), dir.0.join("packages/b")]
+        );
+        let members =
+            workspace_members(&dir.0, &settings(&["packages/b", "packages/*"], &[])).unwrap();
+        assert_eq!(
+            members,
+            [dir.0.join("packages/b"), dir.0.join("packages/a")]
+        );
+
+        // Nor does the workspace see the repeated member as two with one name
+        write_project(
+            &dir.0,
+            "root",
+            "\n[tool.boxpkg.workspace]\nmembers = [\"packages/*\", \"packages/a\"]\n",
+        );
+        let workspace = Workspace::load(&ProjectPaths::new(&dir.0)).unwrap();
+        let names: Vec<String> = workspace
+            .members
+            .iter()
+            .map(Member::normalized_name)
+            .collect();
+        assert_eq!(names, ["root", "a", "b"]);
+    }
+
+    #[test]
+    fn a_member_named_outright_must_exist() {
+        let dir = TempDir::new("missing");
+        fs::create_dir_all(dir.0.join("packages/empty")).unwrap();
+
+        for missing in ["packages/gone", "packages/empty"] {
+            let error = workspace_members(&dir.0, &settings(&[missing], &[]))
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.contains(&format!(
+                    "Workspace member {}",
+                    dir.0.join(missing).display()
+                )),
+                "{}",
+                error
+            );
+        }
+        assert!(
+            workspace_members(&dir.0, &settings(&["missing/*"], &[]))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn loads_the_root_and_its_members() {
+        let dir = TempDir::new("load");
+        write_project(
+            &dir.0,
+            "root",
+            "\n[tool.boxpkg.workspace]\nmembers = [\"packages/*\"]\nexclude = [\"packages/old\"]\n",
+        );
+        write_project(&dir.0.join("packages/Lib_A"), "Lib_A", "");
+        write_project(&dir.0.join("packages/old"), "old", "");
+
+        let paths = ProjectPaths::new(&dir.0);
+        let workspace = Workspace::load(&paths).unwrap();
+        let names: Vec<String> = workspace
+            .members
+            .iter()
+            .map(Member::normalized_name)
+            .collect();
+        assert_eq!(names, ["root", "lib-a"]);
+        assert_eq!(workspace.root().dir, dir.0);
+        assert_eq!(
+            workspace.member("lib.a").unwrap().dir,
+            dir.0.join("packages/Lib_A")
+        );
+        assert_eq!(workspace.target(&paths, Some("LIB-A")).unwrap(), 1);
+        assert!(workspace.target(&paths, Some("old")).is_err());
+
+        // Two members can't share a name
+        write_project(&dir.0.join("packages/lib-a"), "lib-a", "");
+        let error = match Workspace::load(&paths) {
+            Ok(_) => panic!("loaded two members named lib-a"),
+            Err(e) => e.to_string(),
+        };
+        assert!(error.contains("are both named"), "{}", error);
+    }
+
+    #[test]
+    fn wildcards_match_within_a_component() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("lib-*", "lib-"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(wildcard_match("p?", "p1"));
+        assert!(!wildcard_match("p?", "p"));
+        assert!(!wildcard_match("a*b", "aXXc"));
+        assert!(!wildcard_match("lib", "libs"));
+    }
+}
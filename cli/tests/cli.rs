@@ -0,0 +1,61 @@
+//! The box command run the way users run it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A project without dependencies, in a directory of its own
+fn new_project(name: &str) -> PathBuf {
+    let project = std::env::temp_dir().join(format!("box-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&project);
+    std::fs::create_dir_all(&project).unwrap();
+    std::fs::write(
+        project.join("mypkg.toml"),
+        "[project]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\n",
+    )
+    .unwrap();
+    project
+}
+
+fn box_command(project: &Path, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args(args)
+        .arg("--project-dir")
+        .arg(project)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn plain_install_runs_the_install() {
+    let project = new_project("install");
+
+    // Without box.lock there's nothing to install from, so running it fails
+    let output = box_command(&project, &["install"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stderr.contains("run `box lock` first"), "{}", stderr);
+    assert!(!stdout.contains("Not installing"), "{}", stdout);
+
+    // The old opt-in flag is gone
+    let output = box_command(&project, &["install", "--path"]);
+    assert!(!output.status.success());
+
+    let _ = std::fs::remove_dir_all(&project);
+}
+
+#[test]
+fn only_debug_output_shows_the_project_root() {
+    let project = new_project("debug");
+
+    let output = box_command(&project, &["lock", "--check"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("Project root"), "{}", stdout);
+
+    let output = box_command(&project, &["--debug", "lock", "--check"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Project root"), "{}", stdout);
+
+    let _ = std::fs::remove_dir_all(&project);
+}